@@ -0,0 +1,100 @@
+// Generates the built-in opcode tables from the instruction set
+// files in resources/, so the emulator doesn't need to find (or
+// parse) them at runtime.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/system/util/instr_set_format.rs"]
+mod instr_set_format;
+
+use instr_set_format::{is_blank, parse_row, Row};
+
+// each table is a base instruction set plus the files
+// that add to or override it, applied in order
+const TABLES: &[(&str, &[&str])] = &[
+    (
+        "NMOS_OPTABLE",
+        &["resources/6502ops.csv", "resources/6502undoc.csv"],
+    ),
+    (
+        "CMOS_OPTABLE",
+        &["resources/6502ops.csv", "resources/65c02ops.csv"],
+    ),
+];
+
+// "ABSX" -> "Absx"
+fn variant_name(name: &str) -> String {
+    let lower = name.to_ascii_lowercase();
+    let mut chars = lower.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+fn parse_file(path: &str, table: &mut [Option<Row>]) {
+    let file = fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+
+    for (n, line) in file.lines().enumerate() {
+        if is_blank(line) {
+            continue;
+        }
+        match parse_row(line) {
+            Ok(row) => {
+                let opcode = row.opcode as usize;
+                table[opcode] = Some(row);
+            }
+            Err((_, e)) => panic!("{}:{}: {:?}", path, n + 1, e),
+        }
+    }
+}
+
+fn main() {
+    let mut out = String::new();
+
+    for (name, files) in TABLES {
+        let mut table: Vec<Option<Row>> = (0..0x100).map(|_| None).collect();
+        for file in *files {
+            println!("cargo:rerun-if-changed={}", file);
+            parse_file(file, &mut table);
+        }
+
+        writeln!(out, "pub static {}: [Op; 0x100] = [", name).unwrap();
+        for (opcode, row) in table.iter().enumerate() {
+            // opcodes missing from the files decode as 1-cycle NOPs
+            let nop = Row {
+                opcode: opcode as u8,
+                mnemonic: String::from("NOP"),
+                addr_mode: String::from("IMP"),
+                bytes: 1,
+                cycles: 1,
+                flags: 0,
+                undocumented: true,
+            };
+            let row = row.as_ref().unwrap_or(&nop);
+            writeln!(
+                out,
+                "    // {:#04x}\n    Op {{\n        address_mode: Cpu::{},\n        instruction: Cpu::{},\n        cycles: {},\n        info: OpInfo {{\n            address_mode: AddrMode::{},\n            instruction: Instruction::{},\n            bytes: {},\n            flags: {:#04x},\n            undocumented: {},\n        }},\n    }},",
+                opcode,
+                row.addr_mode.to_ascii_lowercase(),
+                row.mnemonic.to_ascii_lowercase(),
+                row.cycles,
+                variant_name(&row.addr_mode),
+                variant_name(&row.mnemonic),
+                row.bytes,
+                row.flags,
+                row.undocumented,
+            )
+            .unwrap();
+        }
+        writeln!(out, "];\n").unwrap();
+    }
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("optables.rs");
+    fs::write(dest, out).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/system/util/instr_set_format.rs");
+}
@@ -0,0 +1,181 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+// Anything the CPU can be attached to: a flat RAM, a console's
+// memory map with its devices, a debugging wrapper, etc.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, value: u8);
+
+    // reads a byte without any of the side effects a real read
+    // could have (e.g. clearing a device's status register),
+    // so debuggers and disassemblers can look at memory safely
+    fn peek(&self, addr: u16) -> u8;
+}
+
+// 64 KiB of plain RAM covering the whole address space
+pub struct Ram {
+    pub data: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new() -> Ram {
+        Ram {
+            data: vec![0; 0x10000],
+        }
+    }
+
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        let start = addr as usize;
+        let end = (start + bytes.len()).min(self.data.len());
+        self.data[start..end].copy_from_slice(&bytes[..end - start]);
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Ram {
+        Ram::new()
+    }
+}
+
+impl Bus for Ram {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.data[addr as usize] = value;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+}
+
+impl<B: Bus + ?Sized> Bus for Box<B> {
+    fn read(&mut self, addr: u16) -> u8 {
+        (**self).read(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        (**self).write(addr, value);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        (**self).peek(addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // a write of a value other than the one already there
+    Change,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+// an access that set off a watchpoint; for reads `old` and
+// `new` are both the value read
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Access {
+    pub id: usize,
+    pub kind: WatchKind,
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Debug, Default)]
+pub struct Watches {
+    pub points: Vec<Watchpoint>,
+    // in the order they happened, until someone takes them
+    pub hits: Vec<Access>,
+    next_id: usize,
+}
+
+impl Watches {
+    pub fn add(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> usize {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.points.push(Watchpoint { id, range, kind });
+        id
+    }
+
+    // false if there was no such watchpoint
+    pub fn remove(&mut self, id: usize) -> bool {
+        let before = self.points.len();
+        self.points.retain(|point| point.id != id);
+        self.points.len() != before
+    }
+
+    fn check(&mut self, addr: u16, old: u8, new: u8, write: bool) {
+        for point in &self.points {
+            let fires = match point.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Change => write && old != new,
+            };
+            if fires && point.range.contains(&addr) {
+                self.hits.push(Access {
+                    id: point.id,
+                    kind: point.kind,
+                    addr,
+                    old,
+                    new,
+                });
+            }
+        }
+    }
+}
+
+// a bus that passes everything through to `inner`, noting the
+// accesses that set off any of `watches`; the watches are shared
+// so a debugger can keep hold of them once the CPU owns the bus
+pub struct Watched<B> {
+    pub inner: B,
+    pub watches: Rc<RefCell<Watches>>,
+}
+
+impl<B: Bus> Watched<B> {
+    pub fn new(inner: B) -> Watched<B> {
+        Watched {
+            inner,
+            watches: Rc::new(RefCell::new(Watches::default())),
+        }
+    }
+}
+
+impl<B: Bus> Bus for Watched<B> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.read(addr);
+        let mut watches = self.watches.borrow_mut();
+        if !watches.points.is_empty() {
+            watches.check(addr, value, value, false);
+        }
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let mut watches = self.watches.borrow_mut();
+        if watches.points.is_empty() {
+            self.inner.write(addr, value);
+            return;
+        }
+        let old = self.inner.peek(addr);
+        self.inner.write(addr, value);
+        watches.check(addr, old, value, true);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.inner.peek(addr)
+    }
+}
@@ -1,17 +1,23 @@
-#![allow(dead_code)]
-
-mod mmio;
-mod system;
-
+use std::env;
 use std::fs;
+use std::io::{self, BufReader};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::process;
 
-use system::cpu::{bit, Cpu, Flag};
-
-fn print_memory(bytes: &[u8], start_addr: u16) {
-    for (i, byte) in bytes.iter().enumerate() {
-        println!("{:#06x}: {:#04x}", start_addr as usize + i, byte);
-    }
-}
+use vanilla::system::cpu::{Cpu, Flag, Variant};
+use vanilla::system::optable;
+use vanilla::system::util::assembler::Assembler;
+use vanilla::system::util::code_map::{self, CodeMap};
+use vanilla::system::util::conformance::Checker;
+use vanilla::system::util::dap::DapServer;
+use vanilla::system::util::debugger::Debugger;
+use vanilla::system::util::disassembler::Disassembler;
+use vanilla::system::util::functional_test::{FunctionalTest, Outcome};
+use vanilla::system::util::gdb::GdbServer;
+use vanilla::system::util::source::{self, Dialect};
+use vanilla::system::util::symbols::SymbolTable;
 
 fn dump_regs(cpu: &Cpu) {
     let regs = &cpu.regs;
@@ -46,29 +52,389 @@ fn dump_regs(cpu: &Cpu) {
     );
 }
 
-use system::util::disassembler::Disassembler;
-use system::util::instr_set_parser::InstrSetParser;
+// the code around PC, marking the instruction about to run
+fn dump_code(cpu: &Cpu, symbols: &SymbolTable) {
+    let mut disassembler = Disassembler::with_memory(&*cpu.bus, optable::optable(cpu.variant));
+    disassembler.symbols = Some(symbols);
+    println!("====[CODE]====");
+    for mut line in disassembler.around(cpu.regs.pc, 5, 4) {
+        if line.addr == cpu.regs.pc {
+            line.text = line.annotated(cpu);
+            println!("> {}", line);
+        } else {
+            println!("  {}", line);
+        }
+    }
+}
 
-fn main() {
-    let mut cpu = Cpu::new();
-    cpu.regs.a = 0x50;
-    cpu.operand = 0xB0;
-    cpu.sbc();
+const USAGE: &str = "\
+usage: vanilla <command> [args]
+
+commands:
+    functional-test [--variant <cpu>] [--labels <file>...] [image]
+        run Klaus Dormann's 6502 functional test
+        (defaults to resources/6502_functional_test.bin)
+    conformance [--variant <cpu>] [iterations] [seed]
+        check that every opcode only changes the flags
+        its opcode table entry declares
+    disasm [--variant <cpu>] [--labels <file>...] <file> [origin] [start] [end]
+        disassemble a binary file loaded at `origin` (default 0),
+        from address `start` up to (not including) `end`
+    trace [--variant <cpu>] [--syntax <asm>] [--labels <file>...] <file> [origin] [entry...]
+        disassemble a binary file loaded at `origin` by following
+        the code from each entry point (default: the NMI, RESET and
+        IRQ vectors), listing unreached bytes as data; with --syntax,
+        as source that assembles back to the same binary
+    asm [--variant <cpu>] [--symbols <file>] [--debug <file>] <source> <output> [listing]
+        assemble a source file into a binary starting at its
+        lowest address, optionally writing a listing file,
+        a `name = $addr` file of the labels and an ld65-style
+        debug file with the constants too and source lines
+    debug [--variant <cpu>] [--labels <file>...] <file> [origin] [start]
+        load a binary file at `origin` (default 0) into RAM and
+        debug it interactively, starting at `start` (default: the
+        RESET vector if the file covers it, otherwise `origin`)
+    gdb [--listen <addr> | --unix <path>] [debug args...]
+        load a file like `debug` does and wait for gdb's remote
+        protocol on a TCP address (default 127.0.0.1:1234) or a
+        unix socket; exits once that client detaches, kills the
+        target or hangs up
+    dap [debug args...]
+        speak the debug adapter protocol on stdin and stdout, for
+        editors; with no file, the client's launch request names
+        the program, origin, start, variant and labels
+
+cpu variants: 6502 (default), 2a03, 65c02
+assembler syntaxes: ca65, asm6, nesasm
+label files: `name = $addr`, VICE/ld65 -Ln, FCEUX .nl, ld65 --dbgfile";
+
+// splits `--variant <cpu>` out of the arguments
+fn parse_variant(args: &[String]) -> Result<(Variant, Vec<String>), String> {
+    let mut variant = Variant::default();
+    let mut rest = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--variant" {
+            let name = it.next().ok_or("--variant needs a value")?;
+            variant = name.parse()?;
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    Ok((variant, rest))
+}
+
+// splits `<name> <value>` out of the arguments
+fn parse_option(args: Vec<String>, name: &str) -> Result<(Option<String>, Vec<String>), String> {
+    let mut value = None;
+    let mut rest = Vec::new();
+    let mut it = args.into_iter();
+    while let Some(arg) = it.next() {
+        if arg == name {
+            value = Some(it.next().ok_or(format!("{} needs a value", name))?);
+        } else {
+            rest.push(arg);
+        }
+    }
+    Ok((value, rest))
+}
+
+// loads every `--labels <file>` into one table
+fn parse_labels(args: Vec<String>) -> Result<(SymbolTable, Vec<String>), String> {
+    let mut symbols = SymbolTable::new();
+    let mut rest = Vec::new();
+    let mut it = args.into_iter();
+    while let Some(arg) = it.next() {
+        if arg == "--labels" {
+            let path = it.next().ok_or("--labels needs a value")?;
+            symbols.load(&path).map_err(|e| e.to_string())?;
+        } else {
+            rest.push(arg);
+        }
+    }
+    Ok((symbols, rest))
+}
+
+// each command returns its exit code, or an error for `main`
+// to print and exit with 2
+type CommandResult = Result<i32, String>;
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("error reading {}: {}", path, e))
+}
+
+fn write_file(path: &str, contents: impl AsRef<[u8]>) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("error writing {}: {}", path, e))
+}
+
+// parses addresses up to `limit`
+fn parse_addresses(args: &[String], limit: usize) -> Result<Vec<usize>, String> {
+    args.iter()
+        .map(|arg| match parse_number(arg)? {
+            n if n <= limit => Ok(n),
+            n => Err(format!("address out of range: {:#x}", n)),
+        })
+        .collect()
+}
+
+fn functional_test(args: &[String]) -> CommandResult {
+    let (variant, args) = parse_variant(args)?;
+    let (symbols, args) = parse_labels(args)?;
+    let path = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or("resources/6502_functional_test.bin");
+    let image = read_file(path)?;
+
+    let mut test = FunctionalTest::new(image);
+    test.variant = variant;
+    let (report, cpu) = test.run();
+    match report.outcome {
+        Outcome::Passed => {
+            println!(
+                "functional test passed ({} instructions, {} cycles)",
+                report.instructions, report.cycles
+            );
+            return Ok(0);
+        }
+        Outcome::Failed { test_case, trap } => println!(
+            "functional test failed: test {:#04x} trapped at {:#06x}",
+            test_case, trap
+        ),
+        Outcome::Timeout { test_case, pc } => println!(
+            "functional test timed out in test {:#04x} at {:#06x}",
+            test_case, pc
+        ),
+    }
     dump_regs(&cpu);
+    dump_code(&cpu, &symbols);
+    Ok(1)
+}
+
+fn disasm(args: &[String]) -> CommandResult {
+    let (variant, args) = parse_variant(args)?;
+    let (symbols, args) = parse_labels(args)?;
+    let Some(path) = args.first() else {
+        return Err(String::from(USAGE));
+    };
+    // only the end can be one past $FFFF
+    let mut numbers = parse_addresses(&args[1..args.len().min(3)], 0xFFFF)?;
+    numbers.extend(parse_addresses(args.get(3..).unwrap_or_default(), 0x10000)?);
+    let data = read_file(path)?;
 
-    let a: u16 = 0x20;
-    let b: i8 = -1;
-    println!("0xFF extends to {:#4X}", b as u16);
-    println!("{:#4X}", a.wrapping_add(b as u16));
+    let origin = numbers.first().copied().unwrap_or(0) as u16;
+    let start = numbers.get(1).map_or(origin, |&start| start as u16);
+    let end = numbers.get(2).copied().unwrap_or(0x10000);
 
-    // let mut parser = InstrSetParser::new("resources/6502ops.csv");
-    // let optable = parser.parse()
-    //     .expect("Parsing error");
+    let mut disassembler = Disassembler::with_origin(data, optable::optable(variant), origin);
+    disassembler.symbols = Some(&symbols);
+    let lines = match u16::try_from(end) {
+        Ok(end) => disassembler.range(start..end),
+        Err(_) => disassembler.range(start..),
+    };
+    for line in lines {
+        if let Some(name) = symbols.name(line.addr) {
+            println!("{}:", name);
+        }
+        println!("{}", line);
+    }
+    Ok(0)
+}
 
-    // let a: u8 = 0xFF;
-    // println!("{}", a.wrapping_add(1));
+fn trace(args: &[String]) -> CommandResult {
+    let (variant, args) = parse_variant(args)?;
+    let (dialect, rest) = parse_option(args, "--syntax")?;
+    let dialect = dialect.map(|name| name.parse::<Dialect>()).transpose()?;
+    let (symbols, rest) = parse_labels(rest)?;
+    let Some(path) = rest.first() else {
+        return Err(String::from(USAGE));
+    };
+    let numbers = parse_addresses(&rest[1..], 0xFFFF)?;
+    let data = read_file(path)?;
+
+    let origin = numbers.first().map_or(0, |&origin| origin as u16);
+    let mut disassembler = Disassembler::with_origin(data, optable::optable(variant), origin);
+    disassembler.symbols = Some(&symbols);
+    let entries: Vec<u16> = match numbers.get(1..) {
+        Some(entries) if !entries.is_empty() => entries.iter().map(|&n| n as u16).collect(),
+        _ => code_map::vectors(&disassembler),
+    };
+    if entries.is_empty() {
+        return Err(String::from(
+            "no entry points given and the image doesn't cover the vectors",
+        ));
+    }
 
-    // let data = fs::read("resources/6502_functional_test.bin").unwrap();
-    // let mut disassembler = Disassembler::new(data, &optable);
-    // disassembler.read(0x400);
+    let map = CodeMap::trace(&disassembler, &entries);
+    let listing = map.listing(&disassembler);
+    match dialect {
+        Some(dialect) => print!("{}", source::render(&listing, dialect)),
+        None => print!("{}", listing),
+    }
+    Ok(0)
+}
+
+fn asm(args: &[String]) -> CommandResult {
+    let (variant, args) = parse_variant(args)?;
+    let (symbols, args) = parse_option(args, "--symbols")?;
+    let (debug, args) = parse_option(args, "--debug")?;
+    let (Some(source), Some(output)) = (args.first(), args.get(1)) else {
+        return Err(String::from(USAGE));
+    };
+
+    let mut assembler = Assembler::new(optable::optable(variant));
+    let assembled = match assembler.assemble(source) {
+        Ok(assembled) => assembled,
+        // a mistake in the source rather than in how we were run
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(1);
+        }
+    };
+
+    write_file(output, &assembled.bytes)?;
+    if let Some(listing) = args.get(2) {
+        write_file(listing, &assembled.listing)?;
+    }
+    if let Some(symbols) = symbols {
+        write_file(&symbols, assembled.symbol_file())?;
+    }
+    if let Some(debug) = debug {
+        write_file(&debug, assembled.debug_file())?;
+    }
+
+    println!(
+        "{} bytes at ${:04X}",
+        assembled.bytes.len(),
+        assembled.origin
+    );
+    Ok(0)
+}
+
+// the arguments `debug` and `gdb` share: `[--variant <cpu>]
+// [--labels <file>...] <file> [origin] [start]`, loaded into RAM
+fn load_debugger(args: Vec<String>) -> Result<Debugger, String> {
+    let (variant, args) = parse_variant(&args)?;
+    let (symbols, args) = parse_labels(args)?;
+    let Some(path) = args.first() else {
+        return Err(String::from(USAGE));
+    };
+    let numbers = parse_addresses(&args[1..], 0xFFFF)?;
+    let data = read_file(path)?;
+
+    let origin = numbers.first().map_or(0, |&origin| origin as u16);
+    let start = numbers.get(1).map(|&start| start as u16);
+    let mut debugger = Debugger::with_image(variant, &data, origin, start);
+    debugger.symbols = symbols;
+    Ok(debugger)
+}
+
+fn debug(args: &[String]) -> CommandResult {
+    let mut debugger = load_debugger(args.to_vec())?;
+    debugger
+        .repl(&mut io::stdin().lock(), &mut io::stdout())
+        .map_err(|e| e.to_string())?;
+    Ok(0)
+}
+
+fn gdb(args: &[String]) -> CommandResult {
+    let (unix, args) = parse_option(args.to_vec(), "--unix")?;
+    let (listen, args) = parse_option(args, "--listen")?;
+    let mut server = GdbServer::new(load_debugger(args)?);
+
+    // a single client, until it detaches, kills the target or hangs up
+    let result = match unix {
+        #[cfg(unix)]
+        Some(path) => UnixListener::bind(&path).and_then(|listener| {
+            eprintln!("listening on {}", path);
+            let (mut stream, _) = listener.accept()?;
+            server.serve(&mut stream)
+        }),
+        #[cfg(not(unix))]
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix sockets aren't supported here",
+        )),
+        None => {
+            let addr = listen.unwrap_or_else(|| String::from("127.0.0.1:1234"));
+            TcpListener::bind(&addr).and_then(|listener| {
+                eprintln!("listening on {}", addr);
+                let (mut stream, _) = listener.accept()?;
+                // acks are a byte each, don't hold them back
+                stream.set_nodelay(true)?;
+                server.serve(&mut stream)
+            })
+        }
+    };
+    result.map_err(|e| e.to_string())?;
+    Ok(0)
+}
+
+fn dap(args: &[String]) -> CommandResult {
+    // with no program, the client launches one
+    let debugger = match args.is_empty() {
+        true => None,
+        false => Some(load_debugger(args.to_vec())?),
+    };
+    let mut server = DapServer::new(debugger);
+    server
+        .serve(Box::new(BufReader::new(io::stdin())), &mut io::stdout())
+        .map_err(|e| e.to_string())?;
+    Ok(0)
+}
+
+fn conformance(args: &[String]) -> CommandResult {
+    let (variant, args) = parse_variant(args)?;
+
+    let mut checker = Checker::new(variant);
+    for (i, arg) in args.iter().enumerate().take(2) {
+        let n = parse_number(arg)?;
+        match i {
+            0 => checker.iterations = n,
+            _ => checker.seed = n as u64,
+        }
+    }
+
+    let violations = checker.run();
+    for violation in &violations {
+        println!("{}", violation);
+    }
+
+    if violations.is_empty() {
+        println!("all opcodes conform to their declared flags");
+        Ok(0)
+    } else {
+        println!("{} opcodes changed undeclared flags", violations.len());
+        Ok(1)
+    }
+}
+
+// accepts decimal, 0x/$-prefixed hex
+fn parse_number(s: &str) -> Result<usize, String> {
+    let res = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        usize::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    res.map_err(|_| format!("Invalid number: {}", s))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("functional-test") => functional_test(&args[1..]),
+        Some("conformance") => conformance(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("trace") => trace(&args[1..]),
+        Some("asm") => asm(&args[1..]),
+        Some("debug") => debug(&args[1..]),
+        Some("gdb") => gdb(&args[1..]),
+        Some("dap") => dap(&args[1..]),
+        _ => Err(String::from(USAGE)),
+    };
+    let code = result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        2
+    });
+    process::exit(code);
 }
@@ -1,3 +1,5 @@
+use crate::bus::Bus;
+
 pub struct Mmio {
     // ideally we should have several arrays here
     // representing the memory mapped physical devices
@@ -15,11 +17,11 @@ impl Mmio {
 
     pub fn read_byte(&self, addr: u16) -> u8 {
         if addr <= 0x07FF {
-            return self.ram[addr as usize];
+            self.ram[addr as usize]
         } else if 0x8000 <= addr {
-            return self.rom[(addr - 0x8000) as usize];
+            self.rom[(addr - 0x8000) as usize]
         } else {
-            return 0x0;
+            0x0
         }
     }
 
@@ -43,3 +45,23 @@ impl Mmio {
         }
     }
 }
+
+impl Default for Mmio {
+    fn default() -> Mmio {
+        Mmio::new()
+    }
+}
+
+impl Bus for Mmio {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.write_byte(addr, value);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+}
@@ -1,5 +1,8 @@
 #![allow(dead_code)] // TODO: remove
 
+use std::str::FromStr;
+
+#[derive(Default)]
 pub struct Regs {
     pub a: u8,
     pub x: u8,
@@ -10,6 +13,7 @@ pub struct Regs {
 }
 
 // NVssDIZC
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Flag {
     N = 7, // Negative
     V = 6, // Overflow
@@ -19,19 +23,13 @@ pub enum Flag {
     C = 0, // Carry
 }
 
-impl Default for Regs {
-    fn default() -> Regs {
-        Regs {
-            a: 0x00,
-            x: 0x00,
-            y: 0x00,
-            s: 0x00,
-            p: 0x00,
-            pc: 0x0000,
-        }
+impl Flag {
+    pub fn mask(self) -> u8 {
+        1 << (self as u8)
     }
 }
 
+#[derive(Default)]
 pub struct Status {
     offset: i8,
     addr: u16,
@@ -40,14 +38,38 @@ pub struct Status {
     page_crossed: bool,
 }
 
-impl Default for Status {
-    fn default() -> Status {
-        Status {
-            offset: 0x0,
-            addr: 0x0,
-            operand: 0x0,
-            cycles: 0,
-            page_crossed: false,
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Variant {
+    // the original MOS 6502
+    #[default]
+    Nmos6502,
+    // the NES CPU: an NMOS core with the
+    // decimal mode circuitry cut out
+    Ricoh2A03,
+    // the CMOS redesign: new instructions and
+    // addressing modes, most NMOS quirks fixed
+    Wdc65C02,
+}
+
+impl Variant {
+    pub fn has_decimal_mode(&self) -> bool {
+        !matches!(self, Variant::Ricoh2A03)
+    }
+
+    pub fn is_cmos(&self) -> bool {
+        matches!(self, Variant::Wdc65C02)
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Variant, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "6502" | "nmos" | "nmos6502" => Ok(Variant::Nmos6502),
+            "2a03" | "ricoh2a03" | "nes" => Ok(Variant::Ricoh2A03),
+            "65c02" | "w65c02" | "wdc65c02" | "cmos" => Ok(Variant::Wdc65C02),
+            _ => Err(format!("Invalid CPU variant: {}", s)),
         }
     }
 }
@@ -67,13 +89,83 @@ pub enum AddrMode {
     Indx,
     Indy,
     Ind,
+    // 65C02 only: ($zz) and JMP ($xxxx,X)
+    Zpi,
+    Iabsx,
+    // WDC 65C02 only: BBRn/BBSn's zero page byte
+    // followed by a branch offset
+    Zpr,
+}
+
+impl AddrMode {
+    pub const ALL: [AddrMode; 16] = [
+        AddrMode::Acc,
+        AddrMode::Imm,
+        AddrMode::Abs,
+        AddrMode::Zp,
+        AddrMode::Zpx,
+        AddrMode::Zpy,
+        AddrMode::Absx,
+        AddrMode::Absy,
+        AddrMode::Imp,
+        AddrMode::Rel,
+        AddrMode::Indx,
+        AddrMode::Indy,
+        AddrMode::Ind,
+        AddrMode::Zpi,
+        AddrMode::Iabsx,
+        AddrMode::Zpr,
+    ];
+
+    // number of operand bytes following the opcode
+    pub fn operand_bytes(&self) -> u8 {
+        instr_set_format::operand_bytes(&self.name()).expect("every mode has a length")
+    }
+
+    // the mode's name in the instruction set files
+    pub fn name(&self) -> String {
+        format!("{:?}", self).to_uppercase()
+    }
+}
+
+impl FromStr for AddrMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AddrMode, String> {
+        AddrMode::ALL
+            .iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("Invalid addressing mode: {}", s))
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Instruction {
     Adc,
+    Ahx,
+    Alr,
+    Anc,
     And,
+    Arr,
     Asl,
+    Axs,
+    Bbr0,
+    Bbr1,
+    Bbr2,
+    Bbr3,
+    Bbr4,
+    Bbr5,
+    Bbr6,
+    Bbr7,
+    Bbs0,
+    Bbs1,
+    Bbs2,
+    Bbs3,
+    Bbs4,
+    Bbs5,
+    Bbs6,
+    Bbs7,
     Bcc,
     Bcs,
     Beq,
@@ -81,6 +173,7 @@ pub enum Instruction {
     Bmi,
     Bne,
     Bpl,
+    Bra,
     Brk,
     Bvc,
     Bvs,
@@ -91,6 +184,7 @@ pub enum Instruction {
     Cmp,
     Cpx,
     Cpy,
+    Dcp,
     Dec,
     Dex,
     Dey,
@@ -98,8 +192,12 @@ pub enum Instruction {
     Inc,
     Inx,
     Iny,
+    Isc,
+    Jam,
     Jmp,
     Jsr,
+    Las,
+    Lax,
     Lda,
     Ldx,
     Ldy,
@@ -108,31 +206,255 @@ pub enum Instruction {
     Ora,
     Pha,
     Php,
+    Phx,
+    Phy,
     Pla,
     Plp,
+    Plx,
+    Ply,
+    Rla,
+    Rmb0,
+    Rmb1,
+    Rmb2,
+    Rmb3,
+    Rmb4,
+    Rmb5,
+    Rmb6,
+    Rmb7,
     Rol,
     Ror,
+    Rra,
     Rti,
     Rts,
+    Sax,
     Sbc,
     Sec,
     Sed,
     Sei,
+    Shx,
+    Shy,
+    Slo,
+    Smb0,
+    Smb1,
+    Smb2,
+    Smb3,
+    Smb4,
+    Smb5,
+    Smb6,
+    Smb7,
+    Sre,
     Sta,
+    Stp,
     Stx,
     Sty,
+    Stz,
     Tax,
+    Tas,
     Tay,
+    Trb,
+    Tsb,
     Tsx,
     Txa,
     Txs,
     Tya,
+    Wai,
+    Xaa,
 }
 
-#[derive(Clone, Copy)]
+impl Instruction {
+    pub const ALL: [Instruction; 117] = [
+        Instruction::Adc,
+        Instruction::Ahx,
+        Instruction::Alr,
+        Instruction::Anc,
+        Instruction::And,
+        Instruction::Arr,
+        Instruction::Asl,
+        Instruction::Axs,
+        Instruction::Bbr0,
+        Instruction::Bbr1,
+        Instruction::Bbr2,
+        Instruction::Bbr3,
+        Instruction::Bbr4,
+        Instruction::Bbr5,
+        Instruction::Bbr6,
+        Instruction::Bbr7,
+        Instruction::Bbs0,
+        Instruction::Bbs1,
+        Instruction::Bbs2,
+        Instruction::Bbs3,
+        Instruction::Bbs4,
+        Instruction::Bbs5,
+        Instruction::Bbs6,
+        Instruction::Bbs7,
+        Instruction::Bcc,
+        Instruction::Bcs,
+        Instruction::Beq,
+        Instruction::Bit,
+        Instruction::Bmi,
+        Instruction::Bne,
+        Instruction::Bpl,
+        Instruction::Bra,
+        Instruction::Brk,
+        Instruction::Bvc,
+        Instruction::Bvs,
+        Instruction::Clc,
+        Instruction::Cld,
+        Instruction::Cli,
+        Instruction::Clv,
+        Instruction::Cmp,
+        Instruction::Cpx,
+        Instruction::Cpy,
+        Instruction::Dcp,
+        Instruction::Dec,
+        Instruction::Dex,
+        Instruction::Dey,
+        Instruction::Eor,
+        Instruction::Inc,
+        Instruction::Inx,
+        Instruction::Iny,
+        Instruction::Isc,
+        Instruction::Jam,
+        Instruction::Jmp,
+        Instruction::Jsr,
+        Instruction::Las,
+        Instruction::Lax,
+        Instruction::Lda,
+        Instruction::Ldx,
+        Instruction::Ldy,
+        Instruction::Lsr,
+        Instruction::Nop,
+        Instruction::Ora,
+        Instruction::Pha,
+        Instruction::Php,
+        Instruction::Phx,
+        Instruction::Phy,
+        Instruction::Pla,
+        Instruction::Plp,
+        Instruction::Plx,
+        Instruction::Ply,
+        Instruction::Rla,
+        Instruction::Rmb0,
+        Instruction::Rmb1,
+        Instruction::Rmb2,
+        Instruction::Rmb3,
+        Instruction::Rmb4,
+        Instruction::Rmb5,
+        Instruction::Rmb6,
+        Instruction::Rmb7,
+        Instruction::Rol,
+        Instruction::Ror,
+        Instruction::Rra,
+        Instruction::Rti,
+        Instruction::Rts,
+        Instruction::Sax,
+        Instruction::Sbc,
+        Instruction::Sec,
+        Instruction::Sed,
+        Instruction::Sei,
+        Instruction::Shx,
+        Instruction::Shy,
+        Instruction::Slo,
+        Instruction::Smb0,
+        Instruction::Smb1,
+        Instruction::Smb2,
+        Instruction::Smb3,
+        Instruction::Smb4,
+        Instruction::Smb5,
+        Instruction::Smb6,
+        Instruction::Smb7,
+        Instruction::Sre,
+        Instruction::Sta,
+        Instruction::Stp,
+        Instruction::Stx,
+        Instruction::Sty,
+        Instruction::Stz,
+        Instruction::Tax,
+        Instruction::Tas,
+        Instruction::Tay,
+        Instruction::Trb,
+        Instruction::Tsb,
+        Instruction::Tsx,
+        Instruction::Txa,
+        Instruction::Txs,
+        Instruction::Tya,
+        Instruction::Wai,
+        Instruction::Xaa,
+    ];
+
+    pub fn mnemonic(&self) -> String {
+        format!("{:?}", self).to_uppercase()
+    }
+
+    // store instructions only need the effective address, reading the
+    // operand beforehand would touch memory the real CPU never reads
+    pub fn reads_operand(&self) -> bool {
+        !matches!(
+            self,
+            Instruction::Sta
+                | Instruction::Stx
+                | Instruction::Sty
+                | Instruction::Stz
+                | Instruction::Sax
+                | Instruction::Ahx
+                | Instruction::Shx
+                | Instruction::Shy
+                | Instruction::Tas
+                | Instruction::Jmp
+                | Instruction::Jsr
+        )
+    }
+
+    // read instructions take an extra cycle when the indexed
+    // address crosses a page boundary (stores and RMW always do)
+    pub fn has_page_penalty(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Adc
+                | Instruction::And
+                | Instruction::Bit
+                | Instruction::Cmp
+                | Instruction::Eor
+                | Instruction::Las
+                | Instruction::Lax
+                | Instruction::Lda
+                | Instruction::Ldx
+                | Instruction::Ldy
+                | Instruction::Nop
+                | Instruction::Ora
+                | Instruction::Sbc
+        )
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Instruction, String> {
+        Instruction::ALL
+            .iter()
+            .find(|instr| instr.mnemonic().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("Invalid instruction: {}", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct OpInfo {
     pub address_mode: AddrMode,
     pub instruction: Instruction,
+    // instruction length, including the opcode
+    pub bytes: u8,
+    // mask of the P bits the instruction may change
+    pub flags: u8,
+    // not part of the variant's official instruction set
+    pub undocumented: bool,
+}
+
+impl OpInfo {
+    pub fn affects(&self, flag: Flag) -> bool {
+        self.flags & flag.mask() != 0
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -143,14 +465,35 @@ pub struct Op {
     pub info: OpInfo,
 }
 
-use crate::mmio;
+impl Op {
+    // what opcodes missing from an instruction set decode as
+    pub fn nop() -> Op {
+        Op {
+            address_mode: Cpu::imp,
+            instruction: Cpu::nop,
+            cycles: 1,
+            info: OpInfo {
+                address_mode: AddrMode::Imp,
+                instruction: Instruction::Nop,
+                bytes: 1,
+                flags: 0,
+                undocumented: true,
+            },
+        }
+    }
+}
+
+use crate::bus::Bus;
+use crate::system::optable;
+use crate::system::util::instr_set_format;
 pub struct Cpu {
     pub regs: Regs,
+    pub variant: Variant,
 
     pub offset: i8,
     pub addr: u16,
     pub operand: u8,
-    pub cycles: u8,
+    pub cycles: u64,
 
     pub branch: bool,
     pub page_crossed: bool,
@@ -163,7 +506,20 @@ pub struct Cpu {
     // context of a single instruction
     pub addr_mode: AddrMode,
 
-    pub mmio: mmio::Mmio,
+    // interrupt lines as seen by the CPU; NMI is
+    // edge-triggered, so we latch the transition
+    // until the next instruction boundary
+    pub nmi_line: bool,
+    pub irq_line: bool,
+    nmi_pending: bool,
+
+    // set by the JAM opcodes and STP, only a reset
+    // gets the CPU going again
+    pub jammed: bool,
+    // set by WAI until an interrupt comes in
+    pub waiting: bool,
+
+    pub bus: Box<dyn Bus>,
     opcodes: Vec<Op>,
 }
 
@@ -172,62 +528,265 @@ macro_rules! bit {
         ($x & (1 << $n) > 0)
     };
 }
+#[allow(unused_imports)]
 pub(crate) use bit;
 
-// we compare (XOR) the carries (c6, c7) from bits 6 and 7
-fn check_add_overflow(x: u8, y: u8) -> bool {
-    let c6 = bit!(x, 6) & bit!(y, 6);
-    let c7 = bit!(x, 7) & bit!(y, 7);
-    c6 ^ c7
+// RMBn and SMBn reset or set bit n of a zero page
+// byte, BBRn and BBSn branch if it's reset or set
+macro_rules! bit_instructions {
+    ($($n: literal: $rmb: ident, $smb: ident, $bbr: ident, $bbs: ident;)*) => {
+        $(
+            pub fn $rmb(&mut self) {
+                self.write_result(self.operand & !(1 << $n));
+            }
+
+            pub fn $smb(&mut self) {
+                self.write_result(self.operand | (1 << $n));
+            }
+
+            pub fn $bbr(&mut self) {
+                self.branch = !bit!(self.operand, $n);
+            }
+
+            pub fn $bbs(&mut self) {
+                self.branch = bit!(self.operand, $n);
+            }
+        )*
+    };
 }
 
 fn is_neg(x: u8) -> bool {
     bit!(x, 7)
 }
 
+// NMOS decimal subtraction result (the flags are
+// the same as in binary mode), `c` is the carry flag
+fn sbc_decimal(a: u8, m: u8, c: u8) -> u8 {
+    let mut lo = (a & 0x0F) as i16 - (m & 0x0F) as i16 + c as i16 - 1;
+    if lo < 0 {
+        lo = ((lo - 0x06) & 0x0F) - 0x10;
+    }
+
+    let mut res = (a & 0xF0) as i16 - (m & 0xF0) as i16 + lo;
+    if res < 0 {
+        res -= 0x60;
+    }
+    res as u8
+}
+
+// 65C02 decimal subtraction result, which differs
+// from the NMOS one for invalid BCD inputs
+fn sbc_decimal_cmos(a: u8, m: u8, c: u8) -> u8 {
+    let lo = (a & 0x0F) as i16 - (m & 0x0F) as i16 + c as i16 - 1;
+    let mut res = a as i16 - m as i16 + c as i16 - 1;
+    if res < 0 {
+        res -= 0x60;
+    }
+    if lo < 0 {
+        res -= 0x06;
+    }
+    res as u8
+}
+
+// the stack lives in page 1
+const STACK_BASE: u16 = 0x0100;
+
+// bits 4 (B) and 5 (unused) only exist in the
+// copy of P that gets pushed to the stack
+const P_BREAK: u8 = 1 << 4;
+const P_UNUSED: u8 = 1 << 5;
+
+// what the unstable LAX #imm and XAA opcodes OR
+// into A; it varies between chips, $EE is common
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
 impl Cpu {
     pub fn execute(&mut self, opcode: u8) {
         let op = &self.opcodes[opcode as usize].clone();
         self.addr_mode = op.info.address_mode;
+        self.branch = false;
         (op.address_mode)(self);
+
+        match self.addr_mode {
+            AddrMode::Imp
+            | AddrMode::Acc
+            | AddrMode::Imm
+            | AddrMode::Rel
+            | AddrMode::Ind
+            | AddrMode::Iabsx => {}
+            _ => {
+                if op.info.instruction.reads_operand() {
+                    self.operand = self.read_data();
+                }
+            }
+        }
+
         (op.instruction)(self);
 
+        if self.page_crossed && self.has_page_penalty(op.info.instruction) {
+            self.cycles += 1;
+        }
+
         if self.branch {
-            self.cycles += 1 + self.page_crossed as u8;
-            self.regs.pc = self.regs.pc.wrapping_add(self.offset as u16);
+            let target = self.regs.pc.wrapping_add(self.offset as u16);
+            self.page_crossed = (target & 0xFF00) != (self.regs.pc & 0xFF00);
+            self.cycles += 1 + self.page_crossed as u64;
+            self.regs.pc = target;
         }
 
-        self.cycles += op.cycles;
+        self.cycles += op.cycles as u64;
+    }
+
+    fn has_page_penalty(&self, instruction: Instruction) -> bool {
+        match instruction {
+            // the 65C02 only spends the extra cycle
+            // on indexed shifts when it needs to
+            Instruction::Asl | Instruction::Lsr | Instruction::Rol | Instruction::Ror => {
+                self.variant.is_cmos()
+            }
+            _ => instruction.has_page_penalty(),
+        }
     }
 
     fn read_inst(&mut self) -> u8 {
-        let inst = self.mmio.read_byte(self.regs.pc);
-        self.regs.pc += 1;
+        let inst = self.bus.read(self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         inst
     }
 
-    fn read_data(&self) -> u8 {
-        self.mmio.read_byte(self.addr)
+    fn read_data(&mut self) -> u8 {
+        self.bus.read(self.addr)
+    }
+
+    // reads a little-endian word, with the high byte
+    // fetched from `hi_addr` (the pointer modes wrap
+    // within the zero page, JMP ($xxFF) within the page)
+    fn read_word(&mut self, lo_addr: u16, hi_addr: u16) -> u16 {
+        let lo = self.bus.read(lo_addr) as u16;
+        let hi = self.bus.read(hi_addr) as u16;
+        (hi << 8) | lo
     }
 
+    // writes the result of a read-modify-write instruction
+    // back to wherever the operand came from; the new value
+    // also becomes the operand, which is what lets the
+    // undocumented RMW opcodes chain a second instruction
+    fn write_result(&mut self, value: u8) {
+        self.operand = value;
+        match self.addr_mode {
+            AddrMode::Acc => self.regs.a = value,
+            _ => self.bus.write(self.addr, value),
+        }
+    }
+
+    pub fn push(&mut self, value: u8) {
+        self.bus.write(STACK_BASE | self.regs.s as u16, value);
+        self.regs.s = self.regs.s.wrapping_sub(1);
+    }
+
+    pub fn pull(&mut self) -> u8 {
+        self.regs.s = self.regs.s.wrapping_add(1);
+        self.bus.read(STACK_BASE | self.regs.s as u16)
+    }
+
+    pub fn push_word(&mut self, value: u16) {
+        self.push((value >> 8) as u8);
+        self.push(value as u8);
+    }
+
+    pub fn pull_word(&mut self) -> u16 {
+        let lo = self.pull() as u16;
+        let hi = self.pull() as u16;
+        (hi << 8) | lo
+    }
+
+    // interrupts are polled between instructions: a step
+    // either services a pending interrupt (jumping to the
+    // handler) or executes one instruction
     pub fn step(&mut self) {
+        if self.jammed {
+            return;
+        }
+
+        // any interrupt ends a WAI, a masked IRQ
+        // just carries on after it without a handler
+        if self.waiting {
+            if !self.nmi_pending && !self.irq_line {
+                return;
+            }
+            self.waiting = false;
+        }
+
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(NMI_VECTOR);
+            return;
+        }
+
+        if self.irq_line && !self.get_flag(Flag::I) {
+            self.interrupt(IRQ_VECTOR);
+            return;
+        }
+
         let inst = self.read_inst();
         self.execute(inst);
     }
 
-    pub fn new() -> Cpu {
-        let nop = Op {
-            address_mode: Cpu::imp,
-            instruction: Cpu::nop,
-            cycles: 1,
-            info: OpInfo {
-                address_mode: AddrMode::Imp,
-                instruction: Instruction::Nop,
-            },
-        };
+    pub fn assert_nmi(&mut self) {
+        if !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = true;
+    }
+
+    pub fn release_nmi(&mut self) {
+        self.nmi_line = false;
+    }
+
+    pub fn assert_irq(&mut self) {
+        self.irq_line = true;
+    }
+
+    pub fn release_irq(&mut self) {
+        self.irq_line = false;
+    }
+
+    // hardware interrupts push P with B clear, which is
+    // the only way a handler can tell them apart from BRK
+    fn interrupt(&mut self, vector: u16) {
+        self.push_word(self.regs.pc);
+        self.push(self.regs.p | P_UNUSED);
+        self.set_flag(Flag::I, true);
+        if self.variant.is_cmos() {
+            self.set_flag(Flag::D, false);
+        }
+        self.regs.pc = self.read_word(vector, vector + 1);
+        self.cycles += 7;
+    }
+
+    pub fn new(bus: Box<dyn Bus>) -> Cpu {
+        Cpu::with_variant(Variant::default(), bus)
+    }
+
+    // a CPU with the built-in opcode table for `variant`
+    pub fn with_variant(variant: Variant, bus: Box<dyn Bus>) -> Cpu {
+        let mut cpu = Cpu::with_optable(optable::optable(variant).to_vec(), bus);
+        cpu.variant = variant;
+        cpu
+    }
 
-        let cpu = Cpu {
+    // builds a CPU that decodes instructions using `optable`,
+    // e.g. the one returned by `InstrSetParser::parse`
+    pub fn with_optable(optable: Vec<Op>, bus: Box<dyn Bus>) -> Cpu {
+        assert_eq!(optable.len(), 0x100, "opcode table must have 256 entries");
+
+        Cpu {
             regs: Regs::default(),
+            variant: Variant::default(),
 
             offset: 0x0,
             addr: 0x0,
@@ -237,11 +796,58 @@ impl Cpu {
             branch: false,
             addr_mode: AddrMode::Imm,
 
-            mmio: mmio::Mmio::new(),
-            opcodes: [nop; 0x100].to_vec(),
-        };
+            nmi_line: false,
+            irq_line: false,
+            nmi_pending: false,
+
+            jammed: false,
+            waiting: false,
+
+            bus,
+            opcodes: optable,
+        }
+    }
 
-        return cpu;
+    pub fn set_optable(&mut self, optable: Vec<Op>) {
+        assert_eq!(optable.len(), 0x100, "opcode table must have 256 entries");
+        self.opcodes = optable;
+    }
+
+    // the table entry the CPU decodes `opcode` with
+    pub fn op(&self, opcode: u8) -> &Op {
+        &self.opcodes[opcode as usize]
+    }
+
+    // switches both the semantics and the decoding to `variant`
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.opcodes = optable::optable(variant).to_vec();
+    }
+
+    // puts the registers in their power-up state and
+    // runs the reset sequence; memory is left untouched
+    pub fn power_on(&mut self) {
+        self.regs = Regs::default();
+        self.cycles = 0;
+        self.reset();
+    }
+
+    // the reset sequence is a BRK with the bus writes turned into
+    // reads: S still goes down by 3 (ending at $FD after power on),
+    // I is set and PC is loaded from the reset vector in 7 cycles
+    pub fn reset(&mut self) {
+        self.regs.s = self.regs.s.wrapping_sub(3);
+        self.set_flag(Flag::I, true);
+        if self.variant.is_cmos() {
+            self.set_flag(Flag::D, false);
+        }
+        self.regs.pc = self.read_word(RESET_VECTOR, RESET_VECTOR + 1);
+        self.branch = false;
+        self.page_crossed = false;
+        self.nmi_pending = false;
+        self.jammed = false;
+        self.waiting = false;
+        self.cycles += 7;
     }
 
     // Addressing modes
@@ -251,6 +857,7 @@ impl Cpu {
     }
 
     pub fn acc(&mut self) {
+        self.operand = self.regs.a;
         self.page_crossed = false;
     }
 
@@ -262,13 +869,11 @@ impl Cpu {
     pub fn abs(&mut self) {
         self.addr = self.read_inst() as u16;
         self.addr += (self.read_inst() as u16) << 8;
-        self.operand = self.read_data();
         self.page_crossed = false;
     }
 
     pub fn zp(&mut self) {
         self.addr = self.read_inst() as u16;
-        self.operand = self.read_data();
         self.page_crossed = false;
     }
 
@@ -277,7 +882,6 @@ impl Cpu {
         self.addr += self.regs.x as u16;
         // zero-page addrmodes wrap within zero page
         self.addr &= 0x00FF;
-        self.operand = self.read_data();
         self.page_crossed = false;
     }
 
@@ -286,73 +890,86 @@ impl Cpu {
         self.addr += self.regs.y as u16;
         // zero-page addrmodes wrap within zero page
         self.addr &= 0x00FF;
-        self.operand = self.read_data();
         self.page_crossed = false;
     }
 
     pub fn absx(&mut self) {
-        self.addr = self.read_inst() as u16;
-        self.addr += (self.read_inst() as u16) << 8;
-        self.addr += self.regs.x as u16;
-        self.operand = self.read_data();
-        self.page_crossed = self.addr & 0xFF00 != 0;
+        let mut base = self.read_inst() as u16;
+        base += (self.read_inst() as u16) << 8;
+        self.addr = base.wrapping_add(self.regs.x as u16);
+        self.page_crossed = (base & 0xFF00) != (self.addr & 0xFF00);
     }
 
     pub fn absy(&mut self) {
-        self.addr = self.read_inst() as u16;
-        self.addr += (self.read_inst() as u16) << 8;
-        self.addr += self.regs.y as u16;
-        self.operand = self.read_data();
-        self.page_crossed = self.addr & 0xFF00 != 0;
+        let mut base = self.read_inst() as u16;
+        base += (self.read_inst() as u16) << 8;
+        self.addr = base.wrapping_add(self.regs.y as u16);
+        self.page_crossed = (base & 0xFF00) != (self.addr & 0xFF00);
     }
 
     pub fn indx(&mut self) {
-        self.addr = self.read_inst() as u16;
-        self.addr += self.regs.x as u16;
-        self.addr &= 0x00FF;
-        let lo = self.read_data() as u16;
-        let hi = self.read_inst() as u16;
-        self.addr = (hi << 8) + lo;
-        self.operand = self.read_data();
+        // the pointer itself wraps within the zero page
+        let ptr = self.read_inst().wrapping_add(self.regs.x);
+        self.addr = self.read_word(ptr as u16, ptr.wrapping_add(1) as u16);
         self.page_crossed = false;
     }
 
     pub fn indy(&mut self) {
-        self.addr = self.read_inst() as u16;
-        let mut lo = self.read_data() as u16;
-        lo += self.regs.y as u16;
-        self.page_crossed = lo & 0xFF00 != 0;
-        let hi = self.read_inst() as u16;
-        self.addr = (hi << 8) + lo;
-        self.operand = self.read_data();
+        let ptr = self.read_inst();
+        let base = self.read_word(ptr as u16, ptr.wrapping_add(1) as u16);
+        self.addr = base.wrapping_add(self.regs.y as u16);
+        self.page_crossed = (base & 0xFF00) != (self.addr & 0xFF00);
     }
 
     pub fn ind(&mut self) {
         let ptr_lo = self.read_inst() as u16;
         let ptr_hi = self.read_inst() as u16;
-        self.addr = (ptr_hi << 8) + ptr_lo;
+        let ptr = (ptr_hi << 8) + ptr_lo;
 
-        let mut pc = self.read_data() as u16;
-        // accounts for the JMP bug
-        if ptr_lo == 0xFF {
-            self.addr &= 0xFF00;
+        // accounts for the JMP bug: the high byte is
+        // fetched without carrying into the pointer's page
+        // (the 65C02 fixed it at the cost of one cycle)
+        let ptr_next = if self.variant.is_cmos() {
+            ptr.wrapping_add(1)
         } else {
-            self.addr += 1;
-        }
-        pc += (self.read_data() as u16) << 8;
-        self.regs.pc = pc;
+            (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF)
+        };
+        self.addr = self.read_word(ptr, ptr_next);
+        self.page_crossed = false;
+    }
+
+    pub fn zpi(&mut self) {
+        let ptr = self.read_inst();
+        self.addr = self.read_word(ptr as u16, ptr.wrapping_add(1) as u16);
+        self.page_crossed = false;
+    }
+
+    pub fn iabsx(&mut self) {
+        let mut base = self.read_inst() as u16;
+        base += (self.read_inst() as u16) << 8;
+        let ptr = base.wrapping_add(self.regs.x as u16);
+        self.addr = self.read_word(ptr, ptr.wrapping_add(1));
+        self.page_crossed = false;
+    }
+
+    pub fn zpr(&mut self) {
+        self.addr = self.read_inst() as u16;
+        self.offset = self.read_inst() as i8;
         self.page_crossed = false;
     }
 
     pub fn rel(&mut self) {
         self.offset = self.read_inst() as i8;
+        self.page_crossed = false;
     }
 
     pub fn set_flag(&mut self, flag: Flag, value: bool) {
-        let mut p = self.regs.p;
-        let mask = (value as u8) << (flag as u8);
-        p = (p & !mask) | mask;
-        self.regs.p = p;
+        let mask = 1 << (flag as u8);
+        if value {
+            self.regs.p |= mask;
+        } else {
+            self.regs.p &= !mask;
+        }
     }
 
     pub fn get_flag(&self, flag: Flag) -> bool {
@@ -360,8 +977,19 @@ impl Cpu {
     }
 
     pub fn update_nz_flags(&mut self) {
-        self.set_flag(Flag::N, is_neg(self.regs.a));
-        self.set_flag(Flag::Z, self.regs.a == 0);
+        self.set_nz_flags(self.regs.a);
+    }
+
+    pub fn set_nz_flags(&mut self, value: u8) {
+        self.set_flag(Flag::N, is_neg(value));
+        self.set_flag(Flag::Z, value == 0);
+    }
+
+    fn compare(&mut self, reg: u8) {
+        let m = self.operand;
+        self.set_flag(Flag::Z, reg == m);
+        self.set_flag(Flag::C, reg >= m);
+        self.set_flag(Flag::N, is_neg(reg.wrapping_sub(m)));
     }
 
     // Instructions
@@ -372,7 +1000,16 @@ impl Cpu {
     pub fn nop(&mut self) {}
 
     pub fn brk(&mut self) {
-        unimplemented!();
+        // BRK skips the padding byte that follows the opcode
+        // and pushes P with B set, then goes through the IRQ vector
+        let pc = self.regs.pc.wrapping_add(1);
+        self.push_word(pc);
+        self.push(self.regs.p | P_BREAK | P_UNUSED);
+        self.set_flag(Flag::I, true);
+        if self.variant.is_cmos() {
+            self.set_flag(Flag::D, false);
+        }
+        self.regs.pc = self.read_word(IRQ_VECTOR, IRQ_VECTOR + 1);
     }
 
     /*
@@ -380,6 +1017,11 @@ impl Cpu {
     */
 
     pub fn adc(&mut self) {
+        if self.decimal_mode() {
+            self.adc_decimal();
+            return;
+        }
+
         let a = self.regs.a;
         let a16 = a as u16;
         let m = self.operand;
@@ -409,7 +1051,7 @@ impl Cpu {
         let m = self.operand;
         let not_m = !m;
         let not_m16 = not_m as u16;
-        let c16: u16 = !self.get_flag(Flag::C) as u16;
+        let c16 = self.get_flag(Flag::C) as u16;
 
         let res16 = a16 + not_m16 + c16;
         let res8 = res16 as u8;
@@ -420,33 +1062,112 @@ impl Cpu {
 
         self.update_nz_flags();
         self.set_flag(Flag::C, (res16 & 0xFF00) > 0);
+
+        // on NMOS parts all flags come from the
+        // binary subtraction, only A is adjusted;
+        // the 65C02 also gets N and Z right, which
+        // takes it an extra cycle
+        if self.decimal_mode() {
+            if self.variant.is_cmos() {
+                self.regs.a = sbc_decimal_cmos(a, m, c16 as u8);
+                self.update_nz_flags();
+                self.cycles += 1;
+            } else {
+                self.regs.a = sbc_decimal(a, m, c16 as u8);
+            }
+        }
     }
 
-    pub fn cmp(&mut self) {
-        // tests A-M
+    fn decimal_mode(&self) -> bool {
+        self.variant.has_decimal_mode() && self.get_flag(Flag::D)
+    }
+
+    // NMOS decimal addition, following Bruce Clark's "Decimal
+    // Mode" tutorial: C and A are BCD-correct for valid BCD
+    // inputs, Z reflects the binary sum and N/V are taken from
+    // the sum before the high nibble gets adjusted
+    fn adc_decimal(&mut self) {
         let a = self.regs.a;
         let m = self.operand;
-        self.set_flag(Flag::Z, a == m);
-        self.set_flag(Flag::C, a >= m);
-        self.set_flag(Flag::N, a < m);
+        let c = self.get_flag(Flag::C) as u8;
+
+        let binary = a.wrapping_add(m).wrapping_add(c);
+
+        let mut lo = (a & 0x0F) as u16 + (m & 0x0F) as u16 + c as u16;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+
+        // same sum, with the high nibbles taken as signed
+        let signed = ((a & 0xF0) as i8 as i16) + ((m & 0xF0) as i8 as i16) + lo as i16;
+        self.set_flag(Flag::N, bit!(signed, 7));
+        self.set_flag(Flag::V, !(-128..=127).contains(&signed));
+
+        let mut res = (a & 0xF0) as u16 + (m & 0xF0) as u16 + lo;
+        if res >= 0xA0 {
+            res += 0x60;
+        }
+
+        self.regs.a = res as u8;
+        self.set_flag(Flag::Z, binary == 0);
+        self.set_flag(Flag::C, res >= 0x100);
+
+        // the 65C02 fixes up N and Z in an extra cycle
+        if self.variant.is_cmos() {
+            self.update_nz_flags();
+            self.cycles += 1;
+        }
+    }
+
+    pub fn cmp(&mut self) {
+        // tests A-M
+        self.compare(self.regs.a);
     }
 
     pub fn cpx(&mut self) {
         // tests X-M
-        let a = self.regs.a;
-        let x = self.regs.x;
-        self.set_flag(Flag::Z, a == x);
-        self.set_flag(Flag::C, a >= x);
-        self.set_flag(Flag::N, a < x);
+        self.compare(self.regs.x);
     }
 
     pub fn cpy(&mut self) {
         // tests Y-M
-        let a = self.regs.a;
-        let y = self.regs.y;
-        self.set_flag(Flag::Z, a == y);
-        self.set_flag(Flag::C, a >= y);
-        self.set_flag(Flag::N, a < y);
+        self.compare(self.regs.y);
+    }
+
+    /*
+        Increments and decrements
+    */
+
+    pub fn dec(&mut self) {
+        let res = self.operand.wrapping_sub(1);
+        self.write_result(res);
+        self.set_nz_flags(res);
+    }
+
+    pub fn dex(&mut self) {
+        self.regs.x = self.regs.x.wrapping_sub(1);
+        self.set_nz_flags(self.regs.x);
+    }
+
+    pub fn dey(&mut self) {
+        self.regs.y = self.regs.y.wrapping_sub(1);
+        self.set_nz_flags(self.regs.y);
+    }
+
+    pub fn inc(&mut self) {
+        let res = self.operand.wrapping_add(1);
+        self.write_result(res);
+        self.set_nz_flags(res);
+    }
+
+    pub fn inx(&mut self) {
+        self.regs.x = self.regs.x.wrapping_add(1);
+        self.set_nz_flags(self.regs.x);
+    }
+
+    pub fn iny(&mut self) {
+        self.regs.y = self.regs.y.wrapping_add(1);
+        self.set_nz_flags(self.regs.y);
     }
 
     /*
@@ -454,11 +1175,39 @@ impl Cpu {
     */
 
     pub fn asl(&mut self) {
-        let res: u16 = (self.regs.a as u16) * 2;
-        self.regs.a = res as u8;
+        let m = self.operand;
+        let res = m << 1;
+        self.write_result(res);
 
-        self.set_flag(Flag::V, res & 0xFF > 0);
-        self.update_nz_flags();
+        self.set_flag(Flag::C, bit!(m, 7));
+        self.set_nz_flags(res);
+    }
+
+    pub fn lsr(&mut self) {
+        let m = self.operand;
+        let res = m >> 1;
+        self.write_result(res);
+
+        self.set_flag(Flag::C, bit!(m, 0));
+        self.set_nz_flags(res);
+    }
+
+    pub fn rol(&mut self) {
+        let m = self.operand;
+        let res = (m << 1) | self.get_flag(Flag::C) as u8;
+        self.write_result(res);
+
+        self.set_flag(Flag::C, bit!(m, 7));
+        self.set_nz_flags(res);
+    }
+
+    pub fn ror(&mut self) {
+        let m = self.operand;
+        let res = (m >> 1) | ((self.get_flag(Flag::C) as u8) << 7);
+        self.write_result(res);
+
+        self.set_flag(Flag::C, bit!(m, 0));
+        self.set_nz_flags(res);
     }
 
     /*
@@ -468,11 +1217,42 @@ impl Cpu {
     pub fn and(&mut self) {
         self.regs.a &= self.operand;
         self.update_nz_flags();
-        if self.page_crossed {
-            self.cycles += 1;
+    }
+
+    pub fn eor(&mut self) {
+        self.regs.a ^= self.operand;
+        self.update_nz_flags();
+    }
+
+    pub fn ora(&mut self) {
+        self.regs.a |= self.operand;
+        self.update_nz_flags();
+    }
+
+    pub fn bit(&mut self) {
+        let m = self.operand;
+        let res = self.regs.a & m;
+        self.set_flag(Flag::Z, res == 0);
+
+        // BIT #imm (65C02) only affects Z
+        if self.addr_mode != AddrMode::Imm {
+            self.set_flag(Flag::V, bit!(m, 6));
+            self.set_flag(Flag::N, bit!(m, 7));
         }
     }
 
+    pub fn trb(&mut self) {
+        let m = self.operand;
+        self.set_flag(Flag::Z, self.regs.a & m == 0);
+        self.write_result(m & !self.regs.a);
+    }
+
+    pub fn tsb(&mut self) {
+        let m = self.operand;
+        self.set_flag(Flag::Z, self.regs.a & m == 0);
+        self.write_result(m | self.regs.a);
+    }
+
     /*
         Branches
     */
@@ -493,14 +1273,6 @@ impl Cpu {
         self.branch = !self.get_flag(Flag::Z);
     }
 
-    pub fn bit(&mut self) {
-        let m = self.operand;
-        let res = self.regs.a & m;
-        self.set_flag(Flag::Z, res == 0);
-        self.set_flag(Flag::V, bit!(m, 6));
-        self.set_flag(Flag::N, bit!(m, 7));
-    }
-
     pub fn bmi(&mut self) {
         self.branch = self.get_flag(Flag::N);
     }
@@ -517,6 +1289,209 @@ impl Cpu {
         self.branch = self.get_flag(Flag::V);
     }
 
+    pub fn bra(&mut self) {
+        self.branch = true;
+    }
+
+    bit_instructions! {
+        0: rmb0, smb0, bbr0, bbs0;
+        1: rmb1, smb1, bbr1, bbs1;
+        2: rmb2, smb2, bbr2, bbs2;
+        3: rmb3, smb3, bbr3, bbs3;
+        4: rmb4, smb4, bbr4, bbs4;
+        5: rmb5, smb5, bbr5, bbs5;
+        6: rmb6, smb6, bbr6, bbs6;
+        7: rmb7, smb7, bbr7, bbs7;
+    }
+
+    /*
+        Jumps and subroutines
+    */
+
+    pub fn jmp(&mut self) {
+        self.regs.pc = self.addr;
+    }
+
+    pub fn jsr(&mut self) {
+        // the pushed return address points to
+        // the last byte of the JSR instruction
+        let ret = self.regs.pc.wrapping_sub(1);
+        self.push_word(ret);
+        self.regs.pc = self.addr;
+    }
+
+    pub fn rts(&mut self) {
+        self.regs.pc = self.pull_word().wrapping_add(1);
+    }
+
+    pub fn rti(&mut self) {
+        let p = self.pull();
+        self.regs.p = p & !(P_BREAK | P_UNUSED);
+        self.regs.pc = self.pull_word();
+    }
+
+    /*
+        WDC 65C02 only
+    */
+
+    pub fn wai(&mut self) {
+        self.waiting = true;
+    }
+
+    pub fn stp(&mut self) {
+        // stops the clock until the next reset
+        self.jammed = true;
+    }
+
+    /*
+        Undocumented (NMOS only)
+    */
+
+    pub fn jam(&mut self) {
+        // the CPU locks up with the JAM opcode on the bus
+        self.regs.pc = self.regs.pc.wrapping_sub(1);
+        self.jammed = true;
+    }
+
+    pub fn slo(&mut self) {
+        self.asl();
+        self.ora();
+    }
+
+    pub fn rla(&mut self) {
+        self.rol();
+        self.and();
+    }
+
+    pub fn sre(&mut self) {
+        self.lsr();
+        self.eor();
+    }
+
+    pub fn rra(&mut self) {
+        self.ror();
+        self.adc();
+    }
+
+    pub fn dcp(&mut self) {
+        self.dec();
+        self.cmp();
+    }
+
+    pub fn isc(&mut self) {
+        self.inc();
+        self.sbc();
+    }
+
+    pub fn sax(&mut self) {
+        self.bus.write(self.addr, self.regs.a & self.regs.x);
+    }
+
+    pub fn lax(&mut self) {
+        // LAX #imm is unstable, it ORs A with a
+        // chip-dependent "magic" value first
+        let m = match self.addr_mode {
+            AddrMode::Imm => (self.regs.a | UNSTABLE_MAGIC) & self.operand,
+            _ => self.operand,
+        };
+        self.regs.a = m;
+        self.regs.x = m;
+        self.update_nz_flags();
+    }
+
+    pub fn las(&mut self) {
+        let res = self.operand & self.regs.s;
+        self.regs.a = res;
+        self.regs.x = res;
+        self.regs.s = res;
+        self.update_nz_flags();
+    }
+
+    pub fn anc(&mut self) {
+        self.and();
+        self.set_flag(Flag::C, is_neg(self.regs.a));
+    }
+
+    pub fn alr(&mut self) {
+        let t = self.regs.a & self.operand;
+        self.regs.a = t >> 1;
+        self.set_flag(Flag::C, bit!(t, 0));
+        self.update_nz_flags();
+    }
+
+    pub fn arr(&mut self) {
+        let t = self.regs.a & self.operand;
+        let c = self.get_flag(Flag::C) as u8;
+        let mut res = (t >> 1) | (c << 7);
+
+        if self.decimal_mode() {
+            // N and Z come from the rotated value, V from the bits
+            // that moved and both nibbles get a BCD-ish fixup
+            self.set_nz_flags(res);
+            self.set_flag(Flag::V, bit!(t ^ res, 6));
+
+            let (hi, lo) = (t >> 4, t & 0x0F);
+            if lo + (lo & 1) > 5 {
+                res = (res & 0xF0) | (res.wrapping_add(6) & 0x0F);
+            }
+            let carry = hi + (hi & 1) > 5;
+            if carry {
+                res = res.wrapping_add(0x60);
+            }
+            self.set_flag(Flag::C, carry);
+        } else {
+            self.set_nz_flags(res);
+            self.set_flag(Flag::C, bit!(res, 6));
+            self.set_flag(Flag::V, bit!(res, 6) ^ bit!(res, 5));
+        }
+
+        self.regs.a = res;
+    }
+
+    pub fn axs(&mut self) {
+        // (A & X) - M without borrow, setting flags like CMP
+        let ax = self.regs.a & self.regs.x;
+        let m = self.operand;
+        self.regs.x = ax.wrapping_sub(m);
+        self.set_flag(Flag::C, ax >= m);
+        self.set_nz_flags(self.regs.x);
+    }
+
+    pub fn xaa(&mut self) {
+        // unstable, see LAX #imm
+        self.regs.a = (self.regs.a | UNSTABLE_MAGIC) & self.regs.x & self.operand;
+        self.update_nz_flags();
+    }
+
+    // the unstable stores AND the value with the high byte of
+    // the base address plus one, and when the indexing crosses
+    // a page that value replaces the high byte of the address
+    fn store_unstable(&mut self, value: u8) {
+        let hi = ((self.addr >> 8) as u8).wrapping_sub(self.page_crossed as u8);
+        let res = value & hi.wrapping_add(1);
+        if self.page_crossed {
+            self.addr = ((res as u16) << 8) | (self.addr & 0x00FF);
+        }
+        self.bus.write(self.addr, res);
+    }
+
+    pub fn ahx(&mut self) {
+        self.store_unstable(self.regs.a & self.regs.x);
+    }
+
+    pub fn shx(&mut self) {
+        self.store_unstable(self.regs.x);
+    }
+
+    pub fn shy(&mut self) {
+        self.store_unstable(self.regs.y);
+    }
+
+    pub fn tas(&mut self) {
+        self.regs.s = self.regs.a & self.regs.x;
+        self.store_unstable(self.regs.s);
+    }
+
     /*
         Status flag changes
     */
@@ -537,74 +1512,125 @@ impl Cpu {
         self.set_flag(Flag::V, false);
     }
 
-    pub fn dec(&mut self) {}
-
-    pub fn dex(&mut self) {}
-
-    pub fn dey(&mut self) {}
-
-    pub fn eor(&mut self) {}
-
-    pub fn inc(&mut self) {}
-
-    pub fn inx(&mut self) {}
+    pub fn sec(&mut self) {
+        self.set_flag(Flag::C, true);
+    }
 
-    pub fn iny(&mut self) {}
+    pub fn sed(&mut self) {
+        self.set_flag(Flag::D, true);
+    }
 
-    pub fn jmp(&mut self) {}
+    pub fn sei(&mut self) {
+        self.set_flag(Flag::I, true);
+    }
 
-    pub fn jsr(&mut self) {}
+    /*
+        Loads and stores
+    */
 
-    pub fn lda(&mut self) {}
+    pub fn lda(&mut self) {
+        self.regs.a = self.operand;
+        self.update_nz_flags();
+    }
 
     pub fn ldx(&mut self) {
         self.regs.x = self.operand;
-        self.update_nz_flags();
+        self.set_nz_flags(self.regs.x);
     }
 
-    pub fn ldy(&mut self) {}
-
-    pub fn lsr(&mut self) {}
-
-    pub fn ora(&mut self) {}
+    pub fn ldy(&mut self) {
+        self.regs.y = self.operand;
+        self.set_nz_flags(self.regs.y);
+    }
 
-    pub fn pha(&mut self) {}
+    pub fn sta(&mut self) {
+        self.bus.write(self.addr, self.regs.a);
+    }
 
-    pub fn php(&mut self) {}
+    pub fn stx(&mut self) {
+        self.bus.write(self.addr, self.regs.x);
+    }
 
-    pub fn pla(&mut self) {}
+    pub fn sty(&mut self) {
+        self.bus.write(self.addr, self.regs.y);
+    }
 
-    pub fn plp(&mut self) {}
+    pub fn stz(&mut self) {
+        self.bus.write(self.addr, 0);
+    }
 
-    pub fn rol(&mut self) {}
+    /*
+        Stack
+    */
 
-    pub fn ror(&mut self) {}
+    pub fn pha(&mut self) {
+        self.push(self.regs.a);
+    }
 
-    pub fn rti(&mut self) {}
+    pub fn php(&mut self) {
+        // PHP always pushes with the B flag set
+        self.push(self.regs.p | P_BREAK | P_UNUSED);
+    }
 
-    pub fn rts(&mut self) {}
+    pub fn phx(&mut self) {
+        self.push(self.regs.x);
+    }
 
-    pub fn sec(&mut self) {}
+    pub fn phy(&mut self) {
+        self.push(self.regs.y);
+    }
 
-    pub fn sed(&mut self) {}
+    pub fn pla(&mut self) {
+        self.regs.a = self.pull();
+        self.update_nz_flags();
+    }
 
-    pub fn sei(&mut self) {}
+    pub fn plp(&mut self) {
+        let p = self.pull();
+        self.regs.p = p & !(P_BREAK | P_UNUSED);
+    }
 
-    pub fn sta(&mut self) {}
+    pub fn plx(&mut self) {
+        self.regs.x = self.pull();
+        self.set_nz_flags(self.regs.x);
+    }
 
-    pub fn stx(&mut self) {}
+    pub fn ply(&mut self) {
+        self.regs.y = self.pull();
+        self.set_nz_flags(self.regs.y);
+    }
 
-    pub fn sty(&mut self) {}
+    /*
+        Transfers
+    */
 
-    pub fn tax(&mut self) {}
+    pub fn tax(&mut self) {
+        self.regs.x = self.regs.a;
+        self.set_nz_flags(self.regs.x);
+    }
 
-    pub fn tay(&mut self) {}
+    pub fn tay(&mut self) {
+        self.regs.y = self.regs.a;
+        self.set_nz_flags(self.regs.y);
+    }
 
-    pub fn tsx(&mut self) {}
+    pub fn tsx(&mut self) {
+        self.regs.x = self.regs.s;
+        self.set_nz_flags(self.regs.x);
+    }
 
-    pub fn txa(&mut self) {}
+    pub fn txa(&mut self) {
+        self.regs.a = self.regs.x;
+        self.update_nz_flags();
+    }
 
-    pub fn txs(&mut self) {}
+    pub fn txs(&mut self) {
+        // TXS is the only transfer that leaves the flags alone
+        self.regs.s = self.regs.x;
+    }
 
-    pub fn tya(&mut self) {}
+    pub fn tya(&mut self) {
+        self.regs.a = self.regs.y;
+        self.update_nz_flags();
+    }
 }
@@ -1,2 +1,3 @@
 pub mod cpu;
+pub mod optable;
 pub mod util;
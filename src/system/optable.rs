@@ -0,0 +1,49 @@
+// The opcode tables built from resources/*.csv at compile time
+// (see build.rs), one per instruction set.
+
+use crate::system::cpu::{AddrMode, Cpu, Instruction, Op, OpInfo, Variant};
+
+include!(concat!(env!("OUT_DIR"), "/optables.rs"));
+
+pub fn optable(variant: Variant) -> &'static [Op; 0x100] {
+    match variant {
+        Variant::Nmos6502 | Variant::Ricoh2A03 => &NMOS_OPTABLE,
+        Variant::Wdc65C02 => &CMOS_OPTABLE,
+    }
+}
+
+pub fn info(variant: Variant, opcode: u8) -> &'static OpInfo {
+    &optable(variant)[opcode as usize].info
+}
+
+// the opcode encoding `instruction` with `mode`, preferring the
+// documented one when undocumented duplicates exist (e.g. NOP)
+pub fn opcode_for(optable: &[Op], instruction: Instruction, mode: AddrMode) -> Option<u8> {
+    let mut found = None;
+    for (opcode, op) in optable.iter().enumerate() {
+        if op.info.instruction != instruction || op.info.address_mode != mode {
+            continue;
+        }
+        if !op.info.undocumented {
+            return Some(opcode as u8);
+        }
+        found = found.or(Some(opcode as u8));
+    }
+    found
+}
+
+pub fn opcode_for_mnemonic(optable: &[Op], mnemonic: &str, mode: AddrMode) -> Option<u8> {
+    let instruction = mnemonic.parse().ok()?;
+    opcode_for(optable, instruction, mode)
+}
+
+// every addressing mode `instruction` can be encoded with
+pub fn modes_for(optable: &[Op], instruction: Instruction) -> Vec<AddrMode> {
+    let mut modes = Vec::new();
+    for op in optable {
+        if op.info.instruction == instruction && !modes.contains(&op.info.address_mode) {
+            modes.push(op.info.address_mode);
+        }
+    }
+    modes
+}
@@ -0,0 +1,1532 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::system::cpu::{AddrMode, Instruction, Op, Variant};
+use crate::system::optable;
+use crate::system::util::expression::{self, Context, EvalError, Expr};
+use crate::system::util::symbols::SourceLine;
+
+// how deep includes, macros and repeats may nest: deep enough for
+// any sane project, shallow enough to catch a file including
+// itself or a macro expanding itself
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    Io(String),
+    Syntax(String),
+    UnknownInstruction(String),
+    UnknownDirective(String),
+    // the instruction exists, just not with this operand
+    InvalidMode(Instruction, &'static str),
+    Undefined(String),
+    DivideByZero,
+    // where the symbol was first defined
+    DuplicateSymbol(String, String),
+    LocalWithoutScope(String),
+    // a value and what it had to fit in
+    OutOfRange(i64, &'static str),
+    BranchOutOfRange(i64),
+    Overlap(u16),
+    NestedTooDeep,
+    // a block directive missing its end, e.g. `.if` without `.endif`
+    Unclosed(&'static str, &'static str),
+    // an end directive, or `.else`, with nothing open to go with
+    Unmatched(String),
+    // how many parameters the macro takes and how many it got
+    MacroArgs(String, usize, usize),
+    // a symbol moved between the passes
+    PhaseError(String),
+    // an instruction the first pass didn't assemble
+    PassMismatch(Instruction),
+}
+
+impl fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmErrorKind::Io(e) => write!(f, "{}", e),
+            AsmErrorKind::Syntax(e) => write!(f, "{}", e),
+            AsmErrorKind::UnknownInstruction(s) => write!(f, "unknown instruction `{}`", s),
+            AsmErrorKind::UnknownDirective(s) => write!(f, "unknown directive `{}`", s),
+            AsmErrorKind::InvalidMode(instruction, mode) => {
+                write!(f, "{} can't be used with {}", instruction.mnemonic(), mode)
+            }
+            AsmErrorKind::Undefined(name) => write!(f, "undefined symbol `{}`", name),
+            AsmErrorKind::DivideByZero => write!(f, "division by zero"),
+            AsmErrorKind::DuplicateSymbol(name, location) => {
+                write!(f, "`{}` is already defined at {}", name, location)
+            }
+            AsmErrorKind::LocalWithoutScope(name) => {
+                write!(f, "local label `{}` needs a label before it", name)
+            }
+            AsmErrorKind::OutOfRange(value, what) => {
+                write!(f, "{} ({:#x}) doesn't fit in {}", value, value, what)
+            }
+            AsmErrorKind::BranchOutOfRange(offset) => {
+                write!(
+                    f,
+                    "branch target is {} bytes away, the limit is -128..127",
+                    offset
+                )
+            }
+            AsmErrorKind::Overlap(addr) => write!(f, "${:04X} was already assembled into", addr),
+            AsmErrorKind::NestedTooDeep => {
+                write!(f, "includes, macros and repeats nested too deep")
+            }
+            AsmErrorKind::Unclosed(open, close) => {
+                write!(f, "`{}` without a matching `{}`", open, close)
+            }
+            AsmErrorKind::Unmatched(directive) => {
+                write!(f, "`{}` without anything open to go with", directive)
+            }
+            AsmErrorKind::MacroArgs(name, expected, got) => write!(
+                f,
+                "`{}` takes {} arguments, {} were given",
+                name, expected, got
+            ),
+            AsmErrorKind::PhaseError(name) => {
+                write!(f, "`{}` has a different value on the second pass", name)
+            }
+            AsmErrorKind::PassMismatch(instruction) => write!(
+                f,
+                "{} wasn't assembled on the first pass",
+                instruction.mnemonic()
+            ),
+        }
+    }
+}
+
+impl From<EvalError> for AsmErrorKind {
+    fn from(e: EvalError) -> AsmErrorKind {
+        match e {
+            EvalError::Undefined(name) => AsmErrorKind::Undefined(name),
+            EvalError::DivideByZero => AsmErrorKind::DivideByZero,
+            EvalError::NoMemory => AsmErrorKind::Syntax(e.to_string()),
+            // the assembler always has a program counter
+            EvalError::NoPc => unreachable!(),
+        }
+    }
+}
+
+// line and column are 1-based, column 0 means the whole line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}", self.file, self.kind);
+        }
+
+        write!(
+            f,
+            "{}:{}:{}: {}\n    {}",
+            self.file, self.line, self.column, self.kind, self.text
+        )?;
+        if self.column > 0 {
+            write!(f, "\n    {}^", " ".repeat(self.column - 1))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AsmError {}
+
+pub struct Output {
+    // the address of `bytes[0]`
+    pub origin: u16,
+    // everything from the lowest to the highest address assembled
+    // into, with any gaps left as zeroes
+    pub bytes: Vec<u8>,
+    // labels and constants, with local labels as `global@local`
+    pub symbols: BTreeMap<String, i64>,
+    // the names in `symbols` given a value with `=` rather than
+    // being labels
+    pub constants: BTreeSet<String>,
+    pub listing: String,
+    // where each run of bytes came from, in the order assembled;
+    // macro and repeat bodies count as the line that used them
+    pub lines: Vec<SourceLine>,
+}
+
+impl Output {
+    // the labels, one `name = $xxxx` a line, for the debugger and
+    // disassembler to load; constants are left out, as a count of 3
+    // would otherwise name every access to $0003
+    pub fn symbol_file(&self) -> String {
+        let mut out = String::new();
+        for (name, &value) in &self.symbols {
+            // labels local to one macro expansion mean nothing outside it
+            if (0..=0xFFFF).contains(&value)
+                && !name.contains('#')
+                && !self.constants.contains(name)
+            {
+                out += &format!("{} = ${:04X}\n", name, value);
+            }
+        }
+        out
+    }
+
+    // the symbols and lines as a debug file in ld65's `--dbgfile`
+    // format, with everything in one segment starting at $0000
+    pub fn debug_file(&self) -> String {
+        let mut out = String::from("version\tmajor=2,minor=0\n");
+        let mut files: Vec<&str> = Vec::new();
+        for line in &self.lines {
+            if !files.contains(&line.file.as_str()) {
+                files.push(&line.file);
+            }
+        }
+        for (id, file) in files.iter().enumerate() {
+            out += &format!("file\tid={},name=\"{}\"\n", id, file);
+        }
+        out += "seg\tid=0,name=\"CODE\",start=0x000000,size=0x10000,addrsize=absolute,type=rw\n";
+        for (id, line) in self.lines.iter().enumerate() {
+            out += &format!(
+                "span\tid={},seg=0,start={},size={}\n",
+                id, line.addr, line.size
+            );
+        }
+        for (id, line) in self.lines.iter().enumerate() {
+            let file = files.iter().position(|&file| file == line.file).unwrap();
+            out += &format!(
+                "line\tid={},file={},line={},span={}\n",
+                id, file, line.line, id
+            );
+        }
+        let symbols = self
+            .symbols
+            .iter()
+            .filter(|(name, value)| (0..=0xFFFF).contains(*value) && !name.contains('#'));
+        for (id, (name, value)) in symbols.enumerate() {
+            let kind = match self.constants.contains(name) {
+                true => "equ",
+                false => "lab",
+            };
+            out += &format!(
+                "sym\tid={},name=\"{}\",addrsize=absolute,val=0x{:04X},type={}\n",
+                id, name, value, kind
+            );
+        }
+        out
+    }
+}
+
+pub struct Assembler<'a> {
+    // the instruction set for code before any `.setcpu`
+    pub optable: &'a [Op],
+    sources: HashMap<PathBuf, Rc<str>>,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(optable: &'a [Op]) -> Assembler<'a> {
+        Assembler {
+            optable,
+            sources: HashMap::new(),
+        }
+    }
+
+    // makes `text` available as `path`, both to `assemble` and
+    // to `.include`, without touching the file system
+    pub fn add_source(&mut self, path: &str, text: &str) {
+        self.sources.insert(PathBuf::from(path), Rc::from(text));
+    }
+
+    pub fn assemble(&mut self, path: &str) -> Result<Output, AsmError> {
+        let mut symbols = BTreeMap::new();
+        let mut modes = HashMap::new();
+        self.pass(path, false, &mut symbols, &mut modes)?;
+        let (memory, listing, lines) = self.pass(path, true, &mut symbols, &mut modes)?;
+
+        let first = memory.iter().position(|b| b.is_some());
+        let last = memory.iter().rposition(|b| b.is_some());
+        let (origin, bytes) = match (first, last) {
+            (Some(first), Some(last)) => (
+                first as u16,
+                memory[first..=last]
+                    .iter()
+                    .map(|b| b.unwrap_or(0))
+                    .collect(),
+            ),
+            _ => (0, Vec::new()),
+        };
+
+        Ok(Output {
+            origin,
+            bytes,
+            constants: symbols
+                .iter()
+                .filter(|(_, symbol)| symbol.constant)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            symbols: symbols
+                .into_iter()
+                .map(|(name, symbol)| (name, symbol.value))
+                .collect(),
+            listing,
+            lines,
+        })
+    }
+
+    // returns the memory image, the listing and the source lines,
+    // which only the second pass fills in
+    fn pass(
+        &mut self,
+        path: &str,
+        second: bool,
+        symbols: &mut BTreeMap<String, Symbol>,
+        modes: &mut Modes,
+    ) -> Result<Assembled, AsmError> {
+        let mut pass = Pass {
+            sources: &mut self.sources,
+            optable: self.optable,
+            second,
+            pc: 0,
+            scope: None,
+            symbols,
+            defined: HashSet::new(),
+            modes,
+            occurrences: HashMap::new(),
+            memory: vec![None; 0x10000],
+            line_bytes: Vec::new(),
+            listing: String::new(),
+            depth: 0,
+            macros: HashMap::new(),
+            expansions: 0,
+            conditions: Vec::new(),
+            capture: None,
+            counters: Vec::new(),
+            replaying: 0,
+            source: None,
+            lines: Vec::new(),
+            pending: Vec::new(),
+        };
+        pass.file(Path::new(path), None)?;
+        if !second {
+            pass.resolve_pending()?;
+        }
+        Ok((pass.memory, pass.listing, pass.lines))
+    }
+
+    pub fn assemble_str(&mut self, name: &str, text: &str) -> Result<Output, AsmError> {
+        self.add_source(name, text);
+        self.assemble(name)
+    }
+}
+
+// the memory image, listing and source lines of a pass
+type Assembled = (Vec<Option<u8>>, String, Vec<SourceLine>);
+
+// by `file`, line number, the instructions each line assembled
+// to on the first pass, in order (more than one for macros and
+// repeats)
+type Modes = HashMap<(String, usize), Vec<(Instruction, AddrMode)>>;
+
+struct Symbol {
+    value: i64,
+    // `file:line` of the definition
+    location: String,
+    // defined with `=`, not a label
+    constant: bool,
+}
+
+// a `name = expr` the first pass couldn't work out yet, with
+// what the expression needs to be evaluated again later
+struct Pending {
+    name: String,
+    expr: Expr,
+    pc: u32,
+    scope: Option<String>,
+    counters: Vec<(String, i64)>,
+    // pointing at the expression
+    error: AsmError,
+}
+
+// the line being assembled, for error locations
+struct Line<'t> {
+    file: &'t str,
+    number: usize,
+    text: &'t str,
+}
+
+impl Line<'_> {
+    // an error pointing at `at`, which must be a slice of the line
+    fn error(&self, at: &str, kind: AsmErrorKind) -> AsmError {
+        let start = self.text.as_ptr() as usize;
+        let offset = (at.as_ptr() as usize)
+            .checked_sub(start)
+            .filter(|&offset| offset <= self.text.len());
+        AsmError {
+            file: String::from(self.file),
+            line: self.number,
+            column: offset.map_or(0, |offset| offset + 1),
+            text: String::from(self.text),
+            kind,
+        }
+    }
+
+    fn location(&self) -> String {
+        format!("{}:{}", self.file, self.number)
+    }
+}
+
+// a line of a macro or repeat body, kept with where it came from
+#[derive(Clone)]
+struct BodyLine {
+    path: PathBuf,
+    file: String,
+    number: usize,
+    text: String,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<BodyLine>,
+}
+
+enum Block {
+    Macro(String, Vec<String>),
+    // the count and the optional counter symbol
+    Repeat(i64, Option<String>),
+}
+
+impl Block {
+    fn closer(&self) -> &'static str {
+        match self {
+            Block::Macro(..) => ".endmacro",
+            Block::Repeat(..) => ".endrepeat",
+        }
+    }
+}
+
+// a `.macro` or `.repeat` body on its way in
+struct Capture {
+    block: Block,
+    body: Vec<BodyLine>,
+    // blocks of the same kinds opened inside this one
+    nesting: usize,
+    depth: usize,
+    unclosed: AsmError,
+}
+
+struct Condition {
+    // whether lines under the current branch get assembled
+    active: bool,
+    // whether an earlier branch was, so `.else` has to skip
+    taken: bool,
+    seen_else: bool,
+    depth: usize,
+    unclosed: AsmError,
+}
+
+// operand syntax, before picking an addressing mode
+enum Operand<'t> {
+    None,
+    Accumulator,
+    Immediate(Expr, &'t str),
+    // an optional `,X` or `,Y`
+    Direct(Expr, &'t str, Option<char>),
+    // `(expr)`, `(expr,X)` and `(expr),Y`
+    Indirect(Expr, &'t str, Option<char>),
+}
+
+// `a:` and `z:` force absolute or zero page addressing
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Size {
+    Auto,
+    Zp,
+    Abs,
+}
+
+struct Pass<'p, 'a> {
+    sources: &'p mut HashMap<PathBuf, Rc<str>>,
+    optable: &'a [Op],
+    second: bool,
+    // u32 so the last byte can go at $FFFF
+    pc: u32,
+    // the global label local ones belong to
+    scope: Option<String>,
+    symbols: &'p mut BTreeMap<String, Symbol>,
+    // the symbols defined so far in this pass; `symbols` already
+    // holds every one of them on the second
+    defined: HashSet<String>,
+    // the mode each instruction got on the first pass, so the
+    // second one keeps the sizes even once forward references
+    // turn out to fit in the zero page
+    modes: &'p mut Modes,
+    // how many instructions each line has assembled this pass
+    occurrences: HashMap<(String, usize), usize>,
+    memory: Vec<Option<u8>>,
+    line_bytes: Vec<u8>,
+    listing: String,
+    // how many includes, macros and repeats we're inside
+    depth: usize,
+    macros: HashMap<String, Rc<Macro>>,
+    // numbers each macro expansion, giving it its own local labels
+    expansions: usize,
+    // innermost last
+    conditions: Vec<Condition>,
+    capture: Option<Capture>,
+    // `.repeat` counters, innermost last
+    counters: Vec<(String, i64)>,
+    // how many macro or repeat bodies we're inside
+    replaying: usize,
+    // the file and line number bytes are put down for
+    source: Option<(String, usize)>,
+    lines: Vec<SourceLine>,
+    // constants waiting on symbols defined further down
+    pending: Vec<Pending>,
+}
+
+impl Context for Pass<'_, '_> {
+    fn symbol(&self, name: &str) -> Option<i64> {
+        if let Some(&(_, value)) = self.counters.iter().rev().find(|(n, _)| n == name) {
+            return Some(value);
+        }
+        let name = self.qualify(name)?;
+        self.symbols.get(&name).map(|symbol| symbol.value)
+    }
+
+    fn pc(&self) -> Option<i64> {
+        Some(self.pc as i64)
+    }
+}
+
+// cuts the line at a `;` that isn't in a string or character
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..i],
+            (None, '"') => quote = Some('"'),
+            (None, '\'') => quote = Some('\''),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    text
+}
+
+// splits directive arguments on the commas outside of
+// parentheses and strings
+fn split_args(text: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                args.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(text[start..].trim());
+    args
+}
+
+// the text inside a `"..."` argument
+fn string_arg(arg: &str) -> Option<&str> {
+    arg.strip_prefix('"')?.strip_suffix('"')
+}
+
+// the length of the identifier at the start of `text`
+fn ident_len(text: &str) -> usize {
+    match text.chars().next() {
+        Some(c) if expression::is_ident_start(c) => {
+            1 + text[1..]
+                .find(|c: char| !expression::is_ident_char(c))
+                .unwrap_or(text.len() - 1)
+        }
+        _ => 0,
+    }
+}
+
+// replaces each whole-word `params` in `text` with the matching
+// argument, leaving strings and characters alone
+fn substitute(text: &str, params: &[String], args: &[&str]) -> String {
+    let mut out = String::new();
+    let mut quote = None;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let after_ident = text[..i].ends_with(|c: char| expression::is_ident_char(c) || c == '@');
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if !after_ident && ident_len(&text[i..]) > 0 => {
+                let len = ident_len(&text[i..]);
+                let word = &text[i..i + len];
+                match params.iter().position(|param| param == word) {
+                    Some(n) => out += args.get(n).copied().unwrap_or(""),
+                    None => out += word,
+                }
+                i += len;
+                continue;
+            }
+            None => {}
+        }
+        out.push(c);
+        i += c.len_utf8();
+    }
+    out
+}
+
+// `,X` or `,Y` and nothing else
+fn index_suffix(text: &str) -> Result<Option<char>, ()> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let register = text.strip_prefix(',').ok_or(())?.trim();
+    match register {
+        "x" | "X" => Ok(Some('X')),
+        "y" | "Y" => Ok(Some('Y')),
+        _ => Err(()),
+    }
+}
+
+impl<'p, 'a> Pass<'p, 'a> {
+    // turns `@local` into `scope@local`
+    fn qualify(&self, name: &str) -> Option<String> {
+        if name.starts_with('@') {
+            return Some(format!("{}{}", self.scope.as_ref()?, name));
+        }
+        Some(String::from(name))
+    }
+
+    // `include` is the `.include` line and its argument, if any
+    fn file(&mut self, path: &Path, include: Option<(&Line, &str)>) -> Result<(), AsmError> {
+        let error = |kind| match include {
+            Some((line, at)) => line.error(at, kind),
+            None => AsmError {
+                file: path.display().to_string(),
+                line: 0,
+                column: 0,
+                text: String::new(),
+                kind,
+            },
+        };
+
+        if self.depth == MAX_DEPTH {
+            return Err(error(AsmErrorKind::NestedTooDeep));
+        }
+
+        let text = match self.sources.get(path) {
+            Some(text) => text.clone(),
+            None => {
+                let text: Rc<str> = fs::read_to_string(path)
+                    .map_err(|e| {
+                        error(AsmErrorKind::Io(format!(
+                            "can't read {}: {}",
+                            path.display(),
+                            e
+                        )))
+                    })?
+                    .into();
+                self.sources.insert(path.to_path_buf(), text.clone());
+                text
+            }
+        };
+
+        let name = path.display().to_string();
+        if self.second {
+            self.listing += &format!("; {}\n", name);
+        }
+
+        self.depth += 1;
+        for (i, text) in text.lines().enumerate() {
+            let line = Line {
+                file: &name,
+                number: i + 1,
+                text,
+            };
+            self.line(&line, path)?;
+        }
+        self.check_closed()?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    // runs a macro or repeat body
+    fn replay(
+        &mut self,
+        line: &Line,
+        body: &[BodyLine],
+        args: (&[String], &[&str]),
+    ) -> Result<(), AsmError> {
+        if self.depth == MAX_DEPTH {
+            return Err(line.error(line.text, AsmErrorKind::NestedTooDeep));
+        }
+
+        self.depth += 1;
+        self.replaying += 1;
+        for body_line in body {
+            let text = substitute(&body_line.text, args.0, args.1);
+            let line = Line {
+                file: &body_line.file,
+                number: body_line.number,
+                text: &text,
+            };
+            self.line(&line, &body_line.path)?;
+        }
+        self.check_closed()?;
+        self.replaying -= 1;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    // blocks have to end in the file, macro or repeat they started in
+    fn check_closed(&self) -> Result<(), AsmError> {
+        if let Some(condition) = self.conditions.last().filter(|c| c.depth == self.depth) {
+            return Err(condition.unclosed.clone());
+        }
+        if let Some(capture) = self.capture.as_ref().filter(|c| c.depth == self.depth) {
+            return Err(capture.unclosed.clone());
+        }
+        Ok(())
+    }
+
+    fn active(&self) -> bool {
+        self.conditions
+            .last()
+            .is_none_or(|condition| condition.active)
+    }
+
+    fn line(&mut self, line: &Line, path: &Path) -> Result<(), AsmError> {
+        let mut rest = strip_comment(line.text).trim();
+        let mut label = None;
+        let len = ident_len(rest);
+        if len > 0 && rest[len..].starts_with(':') {
+            label = Some(&rest[..len]);
+            rest = rest[len + 1..].trim();
+        }
+
+        let word_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (word, args) = (&rest[..word_len], rest[word_len..].trim());
+        let directive = word.to_ascii_lowercase();
+
+        if self.capture.is_some() {
+            return self.capture_line(line, path, &directive);
+        }
+        if matches!(
+            directive.as_str(),
+            ".if" | ".ifdef" | ".ifndef" | ".elseif" | ".else" | ".endif"
+        ) {
+            return self.conditional(line, word, &directive, args);
+        }
+        if !self.active() {
+            return Ok(());
+        }
+        if self.replaying == 0 {
+            self.source = Some((String::from(line.file), line.number));
+        }
+
+        let start = self.pc;
+        self.line_bytes.clear();
+
+        if let Some(label) = label {
+            self.define_label(line, label)?;
+        }
+
+        // `name = expr`
+        let len = ident_len(rest);
+        if len > 0
+            && rest[len..].trim_start().starts_with('=')
+            && !rest[len..].trim_start().starts_with("==")
+        {
+            let name = &rest[..len];
+            let value_text = rest[len..].trim_start()[1..].trim();
+            let expr = self.parse_expr(line, value_text)?;
+            let Some(qualified) = self.qualify(name) else {
+                return Err(line.error(name, AsmErrorKind::LocalWithoutScope(String::from(name))));
+            };
+            // forward references leave it without a value until the
+            // end of the first pass, but `.ifdef` sees it on both
+            match self.value(line, &expr, value_text)? {
+                Some(value) => self.define(line, name, qualified, value, true)?,
+                None => {
+                    self.defined.insert(qualified.clone());
+                    self.pending.push(Pending {
+                        name: qualified,
+                        expr,
+                        pc: self.pc,
+                        scope: self.scope.clone(),
+                        counters: self.counters.clone(),
+                        error: line.error(value_text, AsmErrorKind::Undefined(String::new())),
+                    });
+                }
+            }
+            self.list(line, start);
+            return Ok(());
+        }
+
+        match directive.as_str() {
+            ".macro" | ".repeat" => {
+                self.list(line, start);
+                return self.open_block(line, word, &directive, args);
+            }
+            ".endmacro" | ".endrepeat" => {
+                return Err(line.error(word, AsmErrorKind::Unmatched(directive)));
+            }
+            _ => {}
+        }
+
+        if let Some(body) = self.macros.get(word).cloned() {
+            self.list(line, start);
+            let args = if args.is_empty() {
+                Vec::new()
+            } else {
+                split_args(args)
+            };
+            if args.len() > body.params.len() {
+                return Err(line.error(
+                    args[body.params.len()],
+                    AsmErrorKind::MacroArgs(String::from(word), body.params.len(), args.len()),
+                ));
+            }
+
+            // `@labels` in the body belong to this expansion alone
+            let scope = self.scope.replace(format!("{}#{}", word, self.expansions));
+            self.expansions += 1;
+            self.replay(line, &body.body, (&body.params, &args))?;
+            self.scope = scope;
+            return Ok(());
+        }
+
+        if word.eq_ignore_ascii_case(".include") {
+            self.list(line, start);
+            let Some(name) = string_arg(args) else {
+                return Err(line.error(
+                    args,
+                    AsmErrorKind::Syntax(String::from("expected a file name in quotes")),
+                ));
+            };
+            // relative to the including file
+            let included = path.parent().unwrap_or(Path::new("")).join(name);
+            return self.file(&included, Some((line, args)));
+        }
+
+        if word.starts_with('.') {
+            self.directive(line, word, args)?;
+        } else if !word.is_empty() {
+            self.instruction(line, word, args)?;
+        }
+
+        self.list(line, start);
+        Ok(())
+    }
+
+    fn conditional(
+        &mut self,
+        line: &Line,
+        word: &str,
+        directive: &str,
+        args: &str,
+    ) -> Result<(), AsmError> {
+        let unmatched = || line.error(word, AsmErrorKind::Unmatched(String::from(directive)));
+        let depth = self.depth;
+
+        let condition = match directive {
+            ".if" | ".elseif" => {
+                let expr = self.parse_expr(line, args)?;
+                // only evaluated when it matters, so a skipped branch
+                // can test for things that don't exist
+                move |pass: &Pass| pass.known_value(line, &expr, args).map(|value| value != 0)
+            }
+            // only what's defined above, so both passes agree
+            ".ifdef" | ".ifndef" => {
+                let defined = self
+                    .qualify(args)
+                    .is_some_and(|name| self.defined.contains(&name));
+                let wanted = directive == ".ifdef";
+                return self.open_condition(line, word, move |_| Ok(defined == wanted));
+            }
+            _ => {
+                let parent_active = self.parent_active();
+                let Some(current) = self.conditions.last_mut().filter(|c| c.depth == depth) else {
+                    return Err(unmatched());
+                };
+                if directive == ".endif" {
+                    self.conditions.pop();
+                } else {
+                    if current.seen_else {
+                        return Err(unmatched());
+                    }
+                    current.seen_else = true;
+                    current.active = !current.taken && parent_active;
+                    current.taken = true;
+                }
+                self.list(line, self.pc);
+                return Ok(());
+            }
+        };
+
+        if directive == ".if" {
+            return self.open_condition(line, word, condition);
+        }
+
+        // `.elseif`
+        let parent_active = self.parent_active();
+        let Some(current) = self.conditions.last().filter(|c| c.depth == depth) else {
+            return Err(unmatched());
+        };
+        if current.seen_else {
+            return Err(unmatched());
+        }
+        let active = if current.taken || !parent_active {
+            false
+        } else {
+            condition(self)?
+        };
+        let current = self.conditions.last_mut().unwrap();
+        current.active = active;
+        current.taken |= active;
+        self.list(line, self.pc);
+        Ok(())
+    }
+
+    // whether the block around the innermost condition is active
+    fn parent_active(&self) -> bool {
+        let n = self.conditions.len();
+        n < 2 || self.conditions[n - 2].active
+    }
+
+    fn open_condition(
+        &mut self,
+        line: &Line,
+        word: &str,
+        condition: impl FnOnce(&Pass) -> Result<bool, AsmError>,
+    ) -> Result<(), AsmError> {
+        let active = self.active() && condition(self)?;
+        // a skipped `.if` counts as taken so none of its branches run
+        let taken = active || !self.active();
+        self.conditions.push(Condition {
+            active,
+            taken,
+            seen_else: false,
+            depth: self.depth,
+            unclosed: line.error(word, AsmErrorKind::Unclosed(".if", ".endif")),
+        });
+        self.list(line, self.pc);
+        Ok(())
+    }
+
+    fn open_block(
+        &mut self,
+        line: &Line,
+        word: &str,
+        directive: &str,
+        args: &str,
+    ) -> Result<(), AsmError> {
+        let block = if directive == ".macro" {
+            // `.macro name param, param`
+            let len = ident_len(args);
+            let (name, params) = (&args[..len], args[len..].trim());
+            if name.is_empty()
+                || !(params.is_empty() || args[len..].starts_with(char::is_whitespace))
+            {
+                return Err(line.error(
+                    args,
+                    AsmErrorKind::Syntax(String::from("expected a macro name")),
+                ));
+            }
+            let params: Vec<String> = match params {
+                "" => Vec::new(),
+                params => split_args(params).into_iter().map(String::from).collect(),
+            };
+            if let Some(param) = params
+                .iter()
+                .find(|p| p.is_empty() || ident_len(p) != p.len())
+            {
+                return Err(line.error(
+                    args,
+                    AsmErrorKind::Syntax(format!("invalid parameter name `{}`", param)),
+                ));
+            }
+            Block::Macro(String::from(name), params)
+        } else {
+            let args = split_args(args);
+            let expr = self.parse_expr(line, args[0])?;
+            let count = self.known_value(line, &expr, args[0])?;
+            if count < 0 {
+                return Err(line.error(args[0], AsmErrorKind::OutOfRange(count, "a repeat count")));
+            }
+            let counter = args.get(1).map(|name| String::from(*name));
+            Block::Repeat(count, counter)
+        };
+
+        let unclosed = line.error(
+            word,
+            AsmErrorKind::Unclosed(
+                if directive == ".macro" {
+                    ".macro"
+                } else {
+                    ".repeat"
+                },
+                block.closer(),
+            ),
+        );
+        self.capture = Some(Capture {
+            block,
+            body: Vec::new(),
+            nesting: 0,
+            depth: self.depth,
+            unclosed,
+        });
+        Ok(())
+    }
+
+    fn capture_line(&mut self, line: &Line, path: &Path, directive: &str) -> Result<(), AsmError> {
+        let capture = self.capture.as_mut().unwrap();
+        let (opener, closer) = match capture.block {
+            Block::Macro(..) => (".macro", ".endmacro"),
+            Block::Repeat(..) => (".repeat", ".endrepeat"),
+        };
+
+        if directive == opener {
+            capture.nesting += 1;
+        } else if directive == closer && capture.nesting > 0 {
+            capture.nesting -= 1;
+        } else if directive == closer {
+            let capture = self.capture.take().unwrap();
+            return self.close_block(line, capture);
+        }
+
+        capture.body.push(BodyLine {
+            path: path.to_path_buf(),
+            file: String::from(line.file),
+            number: line.number,
+            text: String::from(line.text),
+        });
+        Ok(())
+    }
+
+    fn close_block(&mut self, line: &Line, capture: Capture) -> Result<(), AsmError> {
+        match capture.block {
+            Block::Macro(name, params) => {
+                if self.macros.contains_key(&name) {
+                    let location = format!("{}:{}", capture.unclosed.file, capture.unclosed.line);
+                    return Err(
+                        line.error(line.text, AsmErrorKind::DuplicateSymbol(name, location))
+                    );
+                }
+                let body = capture.body;
+                self.macros.insert(name, Rc::new(Macro { params, body }));
+                self.list(line, self.pc);
+            }
+            Block::Repeat(count, counter) => {
+                self.list(line, self.pc);
+                for i in 0..count {
+                    if let Some(counter) = &counter {
+                        self.counters.push((counter.clone(), i));
+                    }
+                    let result = self.replay(line, &capture.body, (&[], &[]));
+                    if counter.is_some() {
+                        self.counters.pop();
+                    }
+                    result?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, line: &Line, start: u32) {
+        if !self.second {
+            return;
+        }
+
+        let mut chunks = self.line_bytes.chunks(4);
+        let first: Vec<String> = chunks
+            .next()
+            .unwrap_or_default()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        // lines coming out of a macro or repeat are marked with a `+`
+        let marker = if self.replaying > 0 { '+' } else { ' ' };
+        self.listing += &format!(
+            "{:>5}{} {:04X}  {:<11}  {}\n",
+            line.number,
+            marker,
+            start,
+            first.join(" "),
+            line.text
+        );
+        // long `.byte` lines carry on below
+        let mut addr = start + 4;
+        for chunk in chunks {
+            let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+            self.listing += &format!("       {:04X}  {}\n", addr, bytes.join(" "));
+            addr += 4;
+        }
+    }
+
+    fn define_label(&mut self, line: &Line, name: &str) -> Result<(), AsmError> {
+        if !name.starts_with('@') {
+            self.scope = Some(String::from(name));
+        }
+        let Some(qualified) = self.qualify(name) else {
+            return Err(line.error(name, AsmErrorKind::LocalWithoutScope(String::from(name))));
+        };
+        self.define(line, name, qualified, self.pc as i64, false)
+    }
+
+    fn define(
+        &mut self,
+        line: &Line,
+        at: &str,
+        name: String,
+        value: i64,
+        constant: bool,
+    ) -> Result<(), AsmError> {
+        self.defined.insert(name.clone());
+        match self.symbols.get(&name) {
+            Some(symbol) if !self.second => Err(line.error(
+                at,
+                AsmErrorKind::DuplicateSymbol(name, symbol.location.clone()),
+            )),
+            Some(symbol) if symbol.value != value => {
+                Err(line.error(at, AsmErrorKind::PhaseError(name)))
+            }
+            Some(_) => Ok(()),
+            None => {
+                let location = line.location();
+                let symbol = Symbol {
+                    value,
+                    location,
+                    constant,
+                };
+                self.symbols.insert(name, symbol);
+                Ok(())
+            }
+        }
+    }
+
+    // gives the constants that referred to later symbols their
+    // values, going round while that defines more of them
+    fn resolve_pending(&mut self) -> Result<(), AsmError> {
+        while !self.pending.is_empty() {
+            let count = self.pending.len();
+            let mut waiting = Vec::new();
+            for pending in mem::take(&mut self.pending) {
+                self.pc = pending.pc;
+                self.scope = pending.scope.clone();
+                self.counters = pending.counters.clone();
+                match pending.expr.eval(self) {
+                    Ok(value) => self.define_pending(pending, value)?,
+                    Err(EvalError::Undefined(name)) => waiting.push((pending, name)),
+                    Err(e) => {
+                        return Err(AsmError {
+                            kind: e.into(),
+                            ..pending.error
+                        })
+                    }
+                }
+            }
+            if waiting.len() == count {
+                let (pending, name) = waiting.remove(0);
+                return Err(AsmError {
+                    kind: AsmErrorKind::Undefined(name),
+                    ..pending.error
+                });
+            }
+            self.pending = waiting.into_iter().map(|(pending, _)| pending).collect();
+        }
+        Ok(())
+    }
+
+    fn define_pending(&mut self, pending: Pending, value: i64) -> Result<(), AsmError> {
+        let error = pending.error;
+        if let Some(symbol) = self.symbols.get(&pending.name) {
+            return Err(AsmError {
+                kind: AsmErrorKind::DuplicateSymbol(pending.name, symbol.location.clone()),
+                ..error
+            });
+        }
+        let location = format!("{}:{}", error.file, error.line);
+        let symbol = Symbol {
+            value,
+            location,
+            constant: true,
+        };
+        self.symbols.insert(pending.name, symbol);
+        Ok(())
+    }
+
+    // None for a symbol that isn't defined yet on the first pass
+    fn value(&self, line: &Line, expr: &Expr, at: &str) -> Result<Option<i64>, AsmError> {
+        match expr.eval(self) {
+            Ok(value) => Ok(Some(value)),
+            Err(EvalError::Undefined(_)) if !self.second => Ok(None),
+            Err(e) => Err(line.error(at, e.into())),
+        }
+    }
+
+    // a value that has to be known on the first pass already
+    fn known_value(&self, line: &Line, expr: &Expr, at: &str) -> Result<i64, AsmError> {
+        expr.eval(self).map_err(|e| line.error(at, e.into()))
+    }
+
+    fn parse_expr(&self, line: &Line, text: &str) -> Result<Expr, AsmError> {
+        expression::parse(text).map_err(|e| {
+            let at = text.get(e.offset..).unwrap_or(text);
+            line.error(at, AsmErrorKind::Syntax(e.message))
+        })
+    }
+
+    fn emit(&mut self, line: &Line, at: &str, bytes: &[u8]) -> Result<(), AsmError> {
+        for &byte in bytes {
+            if self.pc > 0xFFFF {
+                return Err(line.error(
+                    at,
+                    AsmErrorKind::OutOfRange(self.pc as i64, "the address space"),
+                ));
+            }
+            if self.second {
+                let cell = &mut self.memory[self.pc as usize];
+                if cell.is_some() {
+                    return Err(line.error(at, AsmErrorKind::Overlap(self.pc as u16)));
+                }
+                *cell = Some(byte);
+                self.note_source();
+            }
+            self.line_bytes.push(byte);
+            self.pc += 1;
+        }
+        Ok(())
+    }
+
+    // counts the byte about to go at PC towards the current line
+    fn note_source(&mut self) {
+        let Some((file, number)) = &self.source else {
+            return;
+        };
+        let pc = self.pc as u16;
+        match self.lines.last_mut() {
+            Some(last)
+                if last.file == *file
+                    && last.line == *number
+                    && last.addr as u32 + last.size as u32 == self.pc =>
+            {
+                last.size += 1;
+            }
+            _ => self.lines.push(SourceLine {
+                file: file.clone(),
+                line: *number,
+                addr: pc,
+                size: 1,
+            }),
+        }
+    }
+
+    fn directive(&mut self, line: &Line, word: &str, args: &str) -> Result<(), AsmError> {
+        match word.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let expr = self.parse_expr(line, args)?;
+                let value = self.known_value(line, &expr, args)?;
+                if !(0..=0xFFFF).contains(&value) {
+                    return Err(line.error(args, AsmErrorKind::OutOfRange(value, "16 bits")));
+                }
+                self.pc = value as u32;
+            }
+            ".byte" | ".db" => {
+                for arg in split_args(args) {
+                    if let Some(text) = string_arg(arg) {
+                        self.emit(line, arg, text.as_bytes())?;
+                        continue;
+                    }
+                    let expr = self.parse_expr(line, arg)?;
+                    let value = self.value(line, &expr, arg)?.unwrap_or(0);
+                    if !(-128..=255).contains(&value) {
+                        return Err(line.error(arg, AsmErrorKind::OutOfRange(value, "a byte")));
+                    }
+                    self.emit(line, arg, &[value as u8])?;
+                }
+            }
+            ".word" | ".dw" => {
+                for arg in split_args(args) {
+                    let expr = self.parse_expr(line, arg)?;
+                    let value = self.value(line, &expr, arg)?.unwrap_or(0);
+                    if !(-0x8000..=0xFFFF).contains(&value) {
+                        return Err(line.error(arg, AsmErrorKind::OutOfRange(value, "a word")));
+                    }
+                    self.emit(line, arg, &(value as u16).to_le_bytes())?;
+                }
+            }
+            ".setcpu" => {
+                let variant = string_arg(args)
+                    .and_then(|name| name.parse::<Variant>().ok())
+                    .ok_or_else(|| {
+                        line.error(
+                            args,
+                            AsmErrorKind::Syntax(String::from(
+                                "expected \"6502\", \"2a03\" or \"65c02\"",
+                            )),
+                        )
+                    })?;
+                self.optable = optable::optable(variant);
+            }
+            // there's no linker to place segments, everything
+            // goes where `.org` says
+            ".segment" => {
+                if string_arg(args).is_none() {
+                    return Err(line.error(
+                        args,
+                        AsmErrorKind::Syntax(String::from("expected a segment name in quotes")),
+                    ));
+                }
+            }
+            _ => {
+                return Err(line.error(word, AsmErrorKind::UnknownDirective(String::from(word))));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_operand<'t>(
+        &self,
+        line: &Line,
+        text: &'t str,
+    ) -> Result<(Operand<'t>, Size), AsmError> {
+        let syntax =
+            |at: &str, message: &str| line.error(at, AsmErrorKind::Syntax(String::from(message)));
+
+        if text.is_empty() {
+            return Ok((Operand::None, Size::Auto));
+        }
+        if text.eq_ignore_ascii_case("a") {
+            return Ok((Operand::Accumulator, Size::Auto));
+        }
+        if let Some(value) = text.strip_prefix('#') {
+            let value = value.trim_start();
+            return Ok((
+                Operand::Immediate(self.parse_expr(line, value)?, value),
+                Size::Auto,
+            ));
+        }
+
+        let (size, text) = match text.get(..2).map(|prefix| prefix.to_ascii_lowercase()) {
+            Some(prefix) if prefix == "a:" => (Size::Abs, text[2..].trim_start()),
+            Some(prefix) if prefix == "z:" => (Size::Zp, text[2..].trim_start()),
+            _ => (Size::Auto, text),
+        };
+
+        // `(expr),Y` and friends, unless the parentheses
+        // turn out to be part of a bigger expression
+        if let Some(inner) = text.strip_prefix('(') {
+            let (expr, end) = expression::parse_prefix(inner).map_err(|e| {
+                let at = inner.get(e.offset..).unwrap_or(inner);
+                syntax(at, &e.message)
+            })?;
+            let after = &inner[end..];
+            if let Some(index) = after.strip_prefix(',') {
+                let closed = index.trim().strip_suffix(')').map(str::trim);
+                return match closed {
+                    Some("x") | Some("X") => Ok((Operand::Indirect(expr, inner, Some('X')), size)),
+                    _ => Err(syntax(after, "expected `,X)`")),
+                };
+            }
+            if let Some(after) = after.strip_prefix(')') {
+                if after.trim().is_empty() {
+                    return Ok((Operand::Indirect(expr, inner, None), size));
+                }
+                if let Ok(Some('Y')) = index_suffix(after) {
+                    return Ok((Operand::Indirect(expr, inner, Some('Y')), size));
+                }
+            }
+        }
+
+        let (expr, end) = expression::parse_prefix(text).map_err(|e| {
+            let at = text.get(e.offset..).unwrap_or(text);
+            syntax(at, &e.message)
+        })?;
+        match index_suffix(&text[end..]) {
+            Ok(index) => Ok((Operand::Direct(expr, text, index), size)),
+            Err(()) => Err(syntax(
+                &text[end..],
+                "expected `,X`, `,Y` or the end of the line",
+            )),
+        }
+    }
+
+    fn instruction(&mut self, line: &Line, word: &str, args: &str) -> Result<(), AsmError> {
+        let unknown = || line.error(word, AsmErrorKind::UnknownInstruction(String::from(word)));
+        let instruction: Instruction = word.parse().map_err(|_| unknown())?;
+        let modes = optable::modes_for(self.optable, instruction);
+        if modes.is_empty() {
+            return Err(unknown());
+        }
+        let has = |mode| modes.contains(&mode);
+        let invalid = |at: &str, what| line.error(at, AsmErrorKind::InvalidMode(instruction, what));
+        if has(AddrMode::Zpr) {
+            return self.bit_branch(line, instruction, word, args);
+        }
+
+        let (operand, size) = self.parse_operand(line, args)?;
+        let (mode, value, at) = match operand {
+            Operand::None if has(AddrMode::Imp) => (AddrMode::Imp, None, args),
+            Operand::None | Operand::Accumulator if has(AddrMode::Acc) => {
+                (AddrMode::Acc, None, args)
+            }
+            Operand::None => return Err(invalid(word, "no operand")),
+            Operand::Accumulator => return Err(invalid(args, "the accumulator")),
+            Operand::Immediate(expr, at) if has(AddrMode::Imm) => {
+                (AddrMode::Imm, self.value(line, &expr, at)?, at)
+            }
+            Operand::Immediate(_, at) => return Err(invalid(at, "an immediate operand")),
+            Operand::Direct(expr, at, None) if has(AddrMode::Rel) => {
+                (AddrMode::Rel, self.value(line, &expr, at)?, at)
+            }
+            Operand::Direct(expr, at, index) => {
+                let (zp, abs, what) = match index {
+                    None => (AddrMode::Zp, AddrMode::Abs, "an address"),
+                    Some('X') => (AddrMode::Zpx, AddrMode::Absx, "`,X` indexing"),
+                    _ => (AddrMode::Zpy, AddrMode::Absy, "`,Y` indexing"),
+                };
+                let value = self.value(line, &expr, at)?;
+                match pick_size(value, size, has(zp), has(abs)) {
+                    Some(Size::Zp) => (zp, value, at),
+                    Some(_) => (abs, value, at),
+                    None => return Err(invalid(at, what)),
+                }
+            }
+            Operand::Indirect(expr, at, index) => {
+                let mode = match index {
+                    None if has(AddrMode::Ind) => AddrMode::Ind,
+                    None if has(AddrMode::Zpi) => AddrMode::Zpi,
+                    Some('X') if has(AddrMode::Indx) => AddrMode::Indx,
+                    Some('X') if has(AddrMode::Iabsx) => AddrMode::Iabsx,
+                    Some('Y') if has(AddrMode::Indy) => AddrMode::Indy,
+                    _ => return Err(invalid(at, "indirect addressing")),
+                };
+                (mode, self.value(line, &expr, at)?, at)
+            }
+        };
+
+        // the second pass sticks to the first one's choice
+        let key = (String::from(line.file), line.number);
+        let occurrence = self.occurrences.entry(key.clone()).or_default();
+        let index = *occurrence;
+        *occurrence += 1;
+        let mode = if self.second {
+            match self.modes.get(&key).and_then(|modes| modes.get(index)) {
+                Some(&(first, mode)) if first == instruction => mode,
+                _ => return Err(line.error(word, AsmErrorKind::PassMismatch(instruction))),
+            }
+        } else {
+            self.modes.entry(key).or_default().push((instruction, mode));
+            mode
+        };
+
+        let opcode = optable::opcode_for(self.optable, instruction, mode)
+            .ok_or_else(|| invalid(at, "that addressing mode"))?;
+        let value = value.unwrap_or(0);
+        let mut bytes = vec![opcode];
+        match mode {
+            AddrMode::Imp | AddrMode::Acc => {}
+            AddrMode::Imm => {
+                if !(-128..=255).contains(&value) {
+                    return Err(line.error(at, AsmErrorKind::OutOfRange(value, "a byte")));
+                }
+                bytes.push(value as u8);
+            }
+            AddrMode::Rel => {
+                let offset = value - (self.pc as i64 + 2);
+                if self.second && !(-128..=127).contains(&offset) {
+                    return Err(line.error(at, AsmErrorKind::BranchOutOfRange(offset)));
+                }
+                bytes.push(offset as u8);
+            }
+            AddrMode::Zp
+            | AddrMode::Zpx
+            | AddrMode::Zpy
+            | AddrMode::Indx
+            | AddrMode::Indy
+            | AddrMode::Zpi => {
+                if !(0..=0xFF).contains(&value) {
+                    return Err(line.error(at, AsmErrorKind::OutOfRange(value, "the zero page")));
+                }
+                bytes.push(value as u8);
+            }
+            AddrMode::Abs | AddrMode::Absx | AddrMode::Absy | AddrMode::Ind | AddrMode::Iabsx => {
+                if !(0..=0xFFFF).contains(&value) {
+                    return Err(line.error(at, AsmErrorKind::OutOfRange(value, "16 bits")));
+                }
+                bytes.extend((value as u16).to_le_bytes());
+            }
+            AddrMode::Zpr => unreachable!("BBR and BBS go through bit_branch"),
+        }
+
+        self.emit(line, word, &bytes)
+    }
+
+    // BBRn and BBSn take two operands, the zero page
+    // byte to test and where to branch to
+    fn bit_branch(
+        &mut self,
+        line: &Line,
+        instruction: Instruction,
+        word: &str,
+        args: &str,
+    ) -> Result<(), AsmError> {
+        let syntax =
+            |at: &str, message: &str| line.error(at, AsmErrorKind::Syntax(String::from(message)));
+
+        let (zp, end) = expression::parse_prefix(args).map_err(|e| {
+            let at = args.get(e.offset..).unwrap_or(args);
+            syntax(at, &e.message)
+        })?;
+        let Some(target_text) = args[end..].trim_start().strip_prefix(',') else {
+            return Err(syntax(&args[end..], "expected `,` and a branch target"));
+        };
+        let target_text = target_text.trim_start();
+        let target = self.parse_expr(line, target_text)?;
+
+        let zp = self.value(line, &zp, args)?.unwrap_or(0);
+        if !(0..=0xFF).contains(&zp) {
+            return Err(line.error(args, AsmErrorKind::OutOfRange(zp, "the zero page")));
+        }
+        let target = self.value(line, &target, target_text)?.unwrap_or(0);
+        let offset = target - (self.pc as i64 + 3);
+        if self.second && !(-128..=127).contains(&offset) {
+            return Err(line.error(target_text, AsmErrorKind::BranchOutOfRange(offset)));
+        }
+
+        let opcode = optable::opcode_for(self.optable, instruction, AddrMode::Zpr)
+            .expect("the caller checked for the mode");
+        self.emit(line, word, &[opcode, zp as u8, offset as u8])
+    }
+}
+
+// zero page when the value is known to fit (or asked for with
+// `z:`) and the instruction has a zero page form; None when the
+// instruction lacks the size that was asked for
+fn pick_size(value: Option<i64>, size: Size, zp: bool, abs: bool) -> Option<Size> {
+    let fits = value.is_some_and(|value| (0..=0xFF).contains(&value));
+    match size {
+        Size::Auto if zp && (fits || !abs) => Some(Size::Zp),
+        Size::Auto | Size::Abs if abs => Some(Size::Abs),
+        Size::Zp if zp => Some(Size::Zp),
+        _ => None,
+    }
+}
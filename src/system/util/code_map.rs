@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+use crate::system::cpu::{AddrMode, Instruction};
+use crate::system::util::disassembler::{DisasmLine, Disassembler, Memory};
+use crate::system::util::symbols::SymbolTable;
+
+const VECTORS: [u16; 3] = [0xFFFA, 0xFFFC, 0xFFFE];
+
+// ordered so a subroutine label wins over a local one
+// when the same address is both called and branched to
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LabelKind {
+    Sub,
+    Local,
+}
+
+// which bytes of an image were reached as code by following
+// the control flow from a set of entry points
+pub struct CodeMap {
+    pub code: BTreeMap<u16, DisasmLine>,
+    pub labels: BTreeMap<u16, LabelKind>,
+    span: Range<u32>,
+    // bytes taken by an instruction other than its opcode
+    inside: Vec<bool>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Item {
+    Org(u16),
+    Label(String),
+    // the text already refers to labels instead of addresses
+    Instruction(DisasmLine),
+    Bytes(u16, Vec<u8>),
+}
+
+// a source-like rendering of a traced image
+pub struct Listing {
+    pub items: Vec<Item>,
+    // every label that made it into the listing, by address
+    pub labels: BTreeMap<u16, String>,
+}
+
+// the NMI, RESET and IRQ handlers, when the image covers the vectors
+pub fn vectors<M: Memory>(disassembler: &Disassembler<M>) -> Vec<u16> {
+    let memory = &disassembler.memory;
+    let span = memory.span();
+    if !span.contains(&0xFFFA) || !span.contains(&0xFFFF) {
+        return Vec::new();
+    }
+
+    let mut entries: Vec<u16> = VECTORS
+        .iter()
+        .filter_map(|&v| Some(u16::from_le_bytes([memory.peek(v)?, memory.peek(v + 1)?])))
+        .collect();
+    entries.dedup();
+    entries
+}
+
+impl CodeMap {
+    pub fn trace<M: Memory>(disassembler: &Disassembler<M>, entries: &[u16]) -> CodeMap {
+        let mut map = CodeMap {
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            span: disassembler.memory.span(),
+            inside: vec![false; 0x10000],
+        };
+
+        let mut pending = Vec::new();
+        for &entry in entries {
+            map.add_label(entry, LabelKind::Sub);
+            pending.push(entry);
+        }
+
+        while let Some(mut addr) = pending.pop() {
+            loop {
+                if map.code.contains_key(&addr) || map.inside[addr as usize] {
+                    break;
+                }
+                let Some(line) = disassembler.decode(addr) else {
+                    break;
+                };
+                let end = addr as u32 + line.bytes.len() as u32;
+                if line.is_truncated() || end > 0x10000 || map.overlaps(addr, end) {
+                    break;
+                }
+
+                let instruction = line.op.instruction;
+                let mode = line.op.address_mode;
+                let target = line.target();
+                // whether execution can carry on to the next instruction
+                let falls_through = match (instruction, mode) {
+                    (Instruction::Jsr, _) => {
+                        if let Some(target) = target {
+                            map.add_label(target, LabelKind::Sub);
+                            pending.push(target);
+                        }
+                        true
+                    }
+                    (Instruction::Jmp, AddrMode::Abs) => {
+                        if let Some(target) = target {
+                            map.add_label(target, LabelKind::Local);
+                            pending.push(target);
+                        }
+                        false
+                    }
+                    (_, AddrMode::Rel | AddrMode::Zpr) => {
+                        if let Some(target) = target {
+                            map.add_label(target, LabelKind::Local);
+                            pending.push(target);
+                        }
+                        instruction != Instruction::Bra
+                    }
+                    // indirect jumps go wherever memory says at run time
+                    (Instruction::Jmp, _) => false,
+                    (Instruction::Rts | Instruction::Rti | Instruction::Brk, _) => false,
+                    (Instruction::Jam | Instruction::Stp, _) => false,
+                    _ => true,
+                };
+
+                for i in addr as u32 + 1..end {
+                    map.inside[i as usize] = true;
+                }
+                map.code.insert(addr, line);
+
+                if !falls_through || end == 0x10000 {
+                    break;
+                }
+                addr = end as u16;
+            }
+        }
+
+        map
+    }
+
+    fn add_label(&mut self, addr: u16, kind: LabelKind) {
+        let entry = self.labels.entry(addr).or_insert(kind);
+        *entry = (*entry).min(kind);
+    }
+
+    fn overlaps(&self, start: u16, end: u32) -> bool {
+        (start as u32..end).any(|i| {
+            self.inside[i as usize] || (i > start as u32 && self.code.contains_key(&(i as u16)))
+        })
+    }
+
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.code.contains_key(&addr)
+    }
+
+    // the label for `addr`, as long as it can be placed in a
+    // listing: inside the image and not in the middle of an instruction
+    pub fn label(&self, addr: u16) -> Option<String> {
+        let kind = self.labels.get(&addr)?;
+        if !self.placeable(addr) {
+            return None;
+        }
+        Some(match kind {
+            LabelKind::Sub => format!("sub_{:04X}", addr),
+            LabelKind::Local => format!("L_{:04X}", addr),
+        })
+    }
+
+    fn placeable(&self, addr: u16) -> bool {
+        self.span.contains(&(addr as u32)) && !self.inside[addr as usize]
+    }
+
+    // the label a listing uses for `addr`: a loaded symbol when it's
+    // a name any assembler would take, otherwise a generated one
+    fn name(&self, addr: u16, symbols: Option<&SymbolTable>) -> Option<String> {
+        let loaded = symbols
+            .and_then(|symbols| symbols.name(addr))
+            .filter(|name| is_plain_name(name));
+        match loaded {
+            Some(name) if self.placeable(addr) => Some(String::from(name)),
+            _ => self.label(addr),
+        }
+    }
+
+    // the whole image as code, labels and `.byte` runs
+    // for whatever was never reached
+    pub fn listing<M: Memory>(&self, disassembler: &Disassembler<M>) -> Listing {
+        let symbols = disassembler.symbols;
+        let name = |addr: u16| self.name(addr, symbols);
+        let mut items = Vec::new();
+        let mut addr = self.span.start;
+        if addr < self.span.end {
+            items.push(Item::Org(addr as u16));
+        }
+
+        while addr < self.span.end {
+            if let Some(label) = name(addr as u16) {
+                items.push(Item::Label(label));
+            }
+
+            if let Some(line) = self.code.get(&(addr as u16)) {
+                let mut line = line.clone();
+                // addresses outside the listing can still be described
+                line.text =
+                    line.format(&|target| name(target).or_else(|| symbols?.describe(target)));
+                addr += line.bytes.len() as u32;
+                items.push(Item::Instruction(line));
+                continue;
+            }
+
+            // a run of data, up to 8 bytes a line, cut short by
+            // code or by anything that needs a label
+            let start = addr;
+            let mut bytes = Vec::new();
+            while addr < self.span.end && bytes.len() < 8 {
+                if addr != start && (self.is_code(addr as u16) || name(addr as u16).is_some()) {
+                    break;
+                }
+                bytes.push(disassembler.memory.peek(addr as u16).unwrap_or(0));
+                addr += 1;
+            }
+            items.push(Item::Bytes(start as u16, bytes));
+        }
+
+        let loaded = symbols.into_iter().flat_map(|symbols| symbols.iter());
+        let labels = self
+            .labels
+            .keys()
+            .copied()
+            .chain(loaded.map(|(addr, _)| addr))
+            .filter_map(|addr| Some((addr, name(addr)?)))
+            .collect();
+
+        Listing { items, labels }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            match item {
+                Item::Org(addr) => writeln!(f, "        .org ${:04X}", addr)?,
+                Item::Label(label) => {
+                    // subroutines get some room to breathe
+                    if label.starts_with("sub_") {
+                        writeln!(f)?;
+                    }
+                    writeln!(f, "{}:", label)?
+                }
+                Item::Instruction(line) => writeln!(f, "        {}", line.text)?,
+                Item::Bytes(_, bytes) => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+                    writeln!(f, "        .byte {}", bytes.join(", "))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// `[A-Za-z_][A-Za-z0-9_]*`, which leaves out e.g. `reset@loop`
+fn is_plain_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
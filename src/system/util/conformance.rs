@@ -0,0 +1,159 @@
+use std::fmt;
+
+use crate::bus::Ram;
+use crate::system::cpu::{Cpu, OpInfo, Variant};
+
+// B and the unused bit only exist on the stack,
+// they can't change in P itself
+const P_BITS: u8 = 0b1100_1111;
+
+// xorshift64*, plenty for shaking out flag bugs
+// and reproducible from the seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    pub fn next_u16(&mut self) -> u16 {
+        (self.next_u64() >> 48) as u16
+    }
+}
+
+// renders a P mask as e.g. "NV--DIZC" with the unset bits as dashes
+pub fn flag_names(mask: u8) -> String {
+    "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, name)| if mask & (0x80 >> i) != 0 { name } else { '-' })
+        .collect()
+}
+
+// an opcode that changed flags its table entry doesn't declare
+#[derive(Debug, Clone, Copy)]
+pub struct Violation {
+    pub opcode: u8,
+    pub info: OpInfo,
+    // the undeclared bits that changed
+    pub changed: u8,
+    pub p_before: u8,
+    pub p_after: u8,
+    // how many of the runs went wrong
+    pub failures: usize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#04x} {} {}: changed {} but declares {} (P {:08b} -> {:08b}, {} failing runs)",
+            self.opcode,
+            self.info.instruction.mnemonic(),
+            self.info.address_mode.name(),
+            flag_names(self.changed),
+            flag_names(self.info.flags),
+            self.p_before,
+            self.p_after,
+            self.failures,
+        )
+    }
+}
+
+pub struct Checker {
+    pub variant: Variant,
+    pub iterations: usize,
+    pub seed: u64,
+}
+
+impl Checker {
+    pub fn new(variant: Variant) -> Checker {
+        Checker {
+            variant,
+            iterations: 1000,
+            seed: 0x6502,
+        }
+    }
+
+    // runs every opcode `iterations` times from random register
+    // and memory state, returning the ones that changed flags
+    // their table entry doesn't declare
+    pub fn run(&self) -> Vec<Violation> {
+        let mut rng = Rng::new(self.seed);
+
+        let mut ram = Ram::new();
+        for byte in ram.data.iter_mut() {
+            *byte = rng.next_u8();
+        }
+        let mut cpu = Cpu::with_variant(self.variant, Box::new(ram));
+
+        let mut violations = Vec::new();
+        for opcode in 0..=0xFF {
+            let mut violation: Option<Violation> = None;
+
+            for _ in 0..self.iterations {
+                // the zero page and stack feed pointers and pulled
+                // values, so they get fresh values for every run
+                for addr in 0x0000..0x0200 {
+                    cpu.bus.write(addr, rng.next_u8());
+                }
+
+                let pc = 0x0200 + rng.next_u16() % 0xFC00;
+                cpu.bus.write(pc, opcode);
+                cpu.bus.write(pc + 1, rng.next_u8());
+                cpu.bus.write(pc + 2, rng.next_u8());
+
+                cpu.regs.a = rng.next_u8();
+                cpu.regs.x = rng.next_u8();
+                cpu.regs.y = rng.next_u8();
+                cpu.regs.s = rng.next_u8();
+                cpu.regs.p = rng.next_u8() & P_BITS;
+                cpu.regs.pc = pc;
+                cpu.jammed = false;
+                cpu.waiting = false;
+
+                let p_before = cpu.regs.p;
+                cpu.step();
+                let p_after = cpu.regs.p;
+
+                let info = cpu.op(opcode).info;
+                let changed = (p_before ^ p_after) & !info.flags & P_BITS;
+                if changed == 0 {
+                    continue;
+                }
+
+                match violation.as_mut() {
+                    Some(v) => {
+                        v.changed |= changed;
+                        v.failures += 1;
+                    }
+                    None => {
+                        violation = Some(Violation {
+                            opcode,
+                            info,
+                            changed,
+                            p_before,
+                            p_after,
+                            failures: 1,
+                        });
+                    }
+                }
+            }
+
+            violations.extend(violation);
+        }
+
+        violations
+    }
+}
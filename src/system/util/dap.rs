@@ -0,0 +1,1004 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use crate::system::cpu::Variant;
+use crate::system::util::debugger::{self, Debugger, Stop, CHUNK};
+use crate::system::util::expression;
+use crate::system::util::json::Json;
+
+// A server for the debug adapter protocol, which is how editors
+// talk to debuggers: JSON messages with a `Content-Length` header,
+// usually over the adapter's stdin and stdout. Requests map onto a
+// `Debugger`, with source lines coming from the debug files its
+// symbol table loaded and stack frames from the JSRs it tracks.
+// There's one thread, the CPU.
+
+const THREAD: i64 = 1;
+
+// the variables references of the scopes every frame shares
+const REGISTERS: i64 = 1;
+const FLAGS: i64 = 2;
+const ZERO_PAGE: i64 = 3;
+const STACK: i64 = 4;
+
+// the next message, or None once the input ends
+pub fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Json>> {
+    let Some(body) = read_body(input)? else {
+        return Ok(None);
+    };
+    parse_body(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// the body of the next message, as framed by its Content-Length,
+// or None once the input ends
+fn read_body(input: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        // other headers are allowed, and ignored
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                let value = value.trim();
+                length = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| invalid(format!("invalid Content-Length `{}`", value)))?,
+                );
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid(String::from("no Content-Length")))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn parse_body(body: &[u8]) -> Result<Json, String> {
+    let text = std::str::from_utf8(body).map_err(|e| e.to_string())?;
+    Json::parse(text).map_err(|e| e.to_string())
+}
+
+pub fn write_message(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let text = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+    output.flush()
+}
+
+// how long the program runs between requests
+enum Until {
+    // a breakpoint, a pause or anything else that stops it
+    Stopped,
+    // the return from the call that started with S at this value
+    Return(u8),
+}
+
+pub struct DapServer {
+    // None until a `launch`; `attach` needs one already
+    pub debugger: Option<Debugger>,
+    seq: i64,
+    // the debugger's breakpoints that came from requests, with the
+    // id the client knows each by and the reason it stops for
+    breakpoints: HashMap<usize, (usize, &'static str)>,
+    // the debugger's breakpoints each request last set, by source
+    // path, or `instructions` and `functions`
+    groups: HashMap<String, Vec<usize>>,
+    stop_on_entry: bool,
+    running: Option<Until>,
+    done: bool,
+    // to send after the response to the current request
+    events: Vec<Json>,
+}
+
+impl DapServer {
+    pub fn new(debugger: Option<Debugger>) -> DapServer {
+        DapServer {
+            debugger,
+            seq: 1,
+            breakpoints: HashMap::new(),
+            groups: HashMap::new(),
+            stop_on_entry: false,
+            running: None,
+            done: false,
+            events: Vec::new(),
+        }
+    }
+
+    // handles requests from `input` until a `disconnect` or the end
+    // of the input; `input` is read on a thread of its own, so a
+    // `pause` can get through while the program runs. A body that
+    // isn't JSON is reported and skipped, only broken framing ends
+    // the session
+    pub fn serve(
+        &mut self,
+        input: Box<dyn BufRead + Send>,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = input;
+            loop {
+                let message = read_body(&mut *input);
+                let last = !matches!(message, Ok(Some(_)));
+                if sender.send(message).is_err() || last {
+                    break;
+                }
+            }
+        });
+
+        while !self.done {
+            let message = match self.running {
+                Some(_) => match receiver.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                },
+                None => match receiver.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                },
+            };
+            match message {
+                Some(body) => match body? {
+                    Some(body) => match parse_body(&body) {
+                        Ok(message) => self.handle(&message, output)?,
+                        Err(e) => self.event(
+                            "output",
+                            Json::object([
+                                ("category", Json::from("stderr")),
+                                ("output", Json::from(format!("ignored a message: {}\n", e))),
+                            ]),
+                        ),
+                    },
+                    None => break,
+                },
+                None => self.keep_running(),
+            }
+            for event in mem::take(&mut self.events) {
+                self.send(output, event)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, output: &mut dyn Write, mut message: Json) -> io::Result<()> {
+        message.set("seq", Json::from(self.seq));
+        self.seq += 1;
+        write_message(output, &message)
+    }
+
+    fn event(&mut self, name: &str, body: Json) {
+        self.events.push(Json::object([
+            ("type", Json::from("event")),
+            ("event", Json::from(name)),
+            ("body", body),
+        ]));
+    }
+
+    fn handle(&mut self, message: &Json, output: &mut dyn Write) -> io::Result<()> {
+        if message.get("type").and_then(Json::as_str) != Some("request") {
+            return Ok(());
+        }
+        let command = message.get("command").and_then(Json::as_str).unwrap_or("");
+        let args = message.get("arguments").cloned().unwrap_or(Json::Null);
+        let result = self.request(command, &args);
+
+        let mut response = Json::object([
+            ("type", Json::from("response")),
+            (
+                "request_seq",
+                message.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success", Json::from(result.is_ok())),
+            ("command", Json::from(command)),
+        ]);
+        match result {
+            Ok(Json::Null) => {}
+            Ok(body) => response.set("body", body),
+            Err(e) => response.set("message", Json::from(e)),
+        }
+        self.send(output, response)
+    }
+
+    // the body of the response, or the message for an error
+    fn request(&mut self, command: &str, args: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => {
+                self.event("initialized", Json::Null);
+                Ok(capabilities())
+            }
+            "launch" => {
+                self.debugger = Some(load(args)?);
+                self.stop_on_entry = flag(args, "stopOnEntry").unwrap_or(false);
+                Ok(Json::Null)
+            }
+            "attach" => {
+                if self.debugger.is_none() {
+                    return Err(String::from(
+                        "nothing to attach to, start the adapter with a program or launch one",
+                    ));
+                }
+                self.stop_on_entry = flag(args, "stopOnEntry").unwrap_or(true);
+                Ok(Json::Null)
+            }
+            "configurationDone" => {
+                self.debugger()?;
+                match self.stop_on_entry {
+                    true => self.stopped_event("entry", None, None),
+                    false => self.running = Some(Until::Stopped),
+                }
+                Ok(Json::Null)
+            }
+            "disconnect" => {
+                self.done = true;
+                Ok(Json::Null)
+            }
+            "terminate" => {
+                self.event("terminated", Json::object([]));
+                Ok(Json::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "setExceptionBreakpoints" => {
+                Ok(Json::object([("breakpoints", Json::Array(Vec::new()))]))
+            }
+            "threads" => Ok(Json::object([(
+                "threads",
+                Json::from(vec![Json::object([
+                    ("id", Json::from(THREAD)),
+                    ("name", Json::from("6502")),
+                ])]),
+            )])),
+            "continue" => {
+                self.debugger()?;
+                self.running = Some(Until::Stopped);
+                Ok(Json::object([("allThreadsContinued", Json::from(true))]))
+            }
+            "pause" => {
+                if self.running.take().is_some() {
+                    self.stopped_event("pause", None, None);
+                }
+                Ok(Json::Null)
+            }
+            "next" | "stepIn" => {
+                let stop = self.step(command == "next", args)?;
+                self.stopped(stop);
+                Ok(Json::Null)
+            }
+            "stepOut" => {
+                let debugger = self.debugger()?;
+                // from the outermost code, there's nothing to return to
+                self.running = Some(match debugger.calls.last() {
+                    Some(call) => Until::Return(call.s),
+                    None => Until::Stopped,
+                });
+                Ok(Json::Null)
+            }
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => Ok(scopes()),
+            "variables" => {
+                let reference = args.get("variablesReference").and_then(Json::as_i64);
+                let variables = self.variables(reference.unwrap_or(0))?;
+                Ok(Json::object([("variables", Json::from(variables))]))
+            }
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "readMemory" => self.read_memory(args),
+            "writeMemory" => self.write_memory(args),
+            "disassemble" => self.disassemble(args),
+            _ => Err(format!("`{}` isn't supported", command)),
+        }
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| String::from("no program, launch one first"))
+    }
+
+    // runs the next chunk of a `continue` or `stepOut`
+    fn keep_running(&mut self) {
+        let (Some(debugger), Some(until)) = (self.debugger.as_mut(), self.running.as_ref()) else {
+            self.running = None;
+            return;
+        };
+        let stop = match *until {
+            Until::Stopped => debugger.run(CHUNK, &|_| false),
+            Until::Return(s) => debugger.run(CHUNK, &|cpu| cpu.regs.s >= s),
+        };
+        if stop != Stop::Limit {
+            self.running = None;
+            self.stopped(stop);
+        }
+    }
+
+    // a source line, or an instruction with `granularity` set to
+    // `instruction` or no line info; `over` steps over JSRs
+    fn step(&mut self, over: bool, args: &Json) -> Result<Stop, String> {
+        let debugger = self.debugger()?;
+        let line = match args.get("granularity").and_then(Json::as_str) {
+            Some("instruction") => None,
+            _ => debugger.symbols.line(debugger.cpu.regs.pc).cloned(),
+        };
+
+        for _ in 0..debugger.limit {
+            let stop = match over {
+                true => debugger.step_over(),
+                false => match debugger.run(1, &|_| false) {
+                    Stop::Limit => Stop::Done,
+                    stop => stop,
+                },
+            };
+            let pc = debugger.cpu.regs.pc;
+            if stop != Stop::Done || !line.as_ref().is_some_and(|line| line.contains(pc)) {
+                return Ok(stop);
+            }
+        }
+        Ok(Stop::Limit)
+    }
+
+    // tells the client why the program stopped, writing what the
+    // debugger had to say about it to the console
+    fn stopped(&mut self, stop: Stop) {
+        let (reason, id) = match &stop {
+            Stop::Done => ("step", None),
+            Stop::Breakpoint(id) => match self.breakpoints.get(id) {
+                Some(&(dap_id, reason)) => (reason, Some(dap_id)),
+                None => ("breakpoint", None),
+            },
+            Stop::Watch { .. } => ("data breakpoint", None),
+            Stop::BadCondition { .. } | Stop::Jammed => ("exception", None),
+            Stop::Trap | Stop::Limit => ("pause", None),
+        };
+        let Some(debugger) = self.debugger.as_mut() else {
+            return;
+        };
+        let text = (stop != Stop::Done).then(|| debugger.report(stop));
+        if let Some(text) = &text {
+            self.event(
+                "output",
+                Json::object([
+                    ("category", Json::from("console")),
+                    ("output", Json::from(format!("{}\n", text))),
+                ]),
+            );
+        }
+        let description = text.and_then(|text| text.lines().next().map(String::from));
+        self.stopped_event(reason, id, description);
+    }
+
+    fn stopped_event(&mut self, reason: &str, id: Option<usize>, description: Option<String>) {
+        let mut body = Json::object([
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD)),
+            ("allThreadsStopped", Json::from(true)),
+        ]);
+        if let Some(id) = id {
+            body.set("hitBreakpointIds", Json::from(vec![Json::from(id)]));
+        }
+        if let Some(description) = description {
+            body.set("description", Json::from(description));
+        }
+        self.event("stopped", body);
+    }
+
+    // drops the breakpoints `group` set last time
+    fn clear_group(&mut self, group: &str) {
+        let ids = self.groups.remove(group).unwrap_or_default();
+        if let Some(debugger) = self.debugger.as_mut() {
+            for id in ids {
+                self.breakpoints.remove(&id);
+                let _ = debugger.delete_breakpoint(id);
+            }
+        }
+    }
+
+    // a breakpoint at each of `addrs`, all known to the client by
+    // the id of the first, with the condition and hit condition of
+    // `spec`; the hit condition is the hit to stop on first
+    fn add_breakpoints(
+        &mut self,
+        group: &str,
+        addrs: &[u16],
+        spec: &Json,
+        reason: &'static str,
+    ) -> Result<usize, String> {
+        let condition = spec
+            .get("condition")
+            .and_then(Json::as_str)
+            .filter(|condition| !condition.trim().is_empty());
+        let ignore = match spec.get("hitCondition").and_then(Json::as_str) {
+            Some(text) if !text.trim().is_empty() => match text.trim().parse::<u64>() {
+                Ok(n) => n.saturating_sub(1),
+                Err(_) => return Err(format!("hit condition `{}` isn't a number", text)),
+            },
+            _ => 0,
+        };
+
+        let debugger = self.debugger()?;
+        let mut ids = Vec::new();
+        for &addr in addrs {
+            let id = match debugger.add_breakpoint(Some(addr), condition, false) {
+                Ok(id) => id,
+                Err(e) => {
+                    for id in ids {
+                        let _ = debugger.delete_breakpoint(id);
+                    }
+                    return Err(e.to_string());
+                }
+            };
+            if let Some(breakpoint) = debugger.breakpoints.iter_mut().find(|b| b.id == id) {
+                breakpoint.ignore = ignore;
+            }
+            ids.push(id);
+        }
+
+        let dap_id = ids[0];
+        for &id in &ids {
+            self.breakpoints.insert(id, (dap_id, reason));
+        }
+        self.groups
+            .entry(String::from(group))
+            .or_default()
+            .extend(ids);
+        Ok(dap_id)
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let source = args.get("source").cloned().unwrap_or(Json::Null);
+        let path = source
+            .get("path")
+            .or_else(|| source.get("name"))
+            .and_then(Json::as_str)
+            .ok_or("setBreakpoints needs a source path")?;
+        let group = format!("source {}", path);
+        self.clear_group(&group);
+
+        let specs = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default();
+        let mut results = Vec::new();
+        for spec in specs {
+            let line = spec.get("line").and_then(Json::as_i64).unwrap_or(0).max(0) as usize;
+            let found = self.debugger()?.symbols.find_line(path, line);
+            let Some((line, addrs)) = found else {
+                results.push(Json::object([
+                    ("verified", Json::from(false)),
+                    ("line", Json::from(line)),
+                    ("message", Json::from("no code at or after this line")),
+                ]));
+                continue;
+            };
+            results.push(
+                match self.add_breakpoints(&group, &addrs, spec, "breakpoint") {
+                    Ok(id) => Json::object([
+                        ("id", Json::from(id)),
+                        ("verified", Json::from(true)),
+                        ("line", Json::from(line)),
+                        ("source", source.clone()),
+                        ("instructionReference", address(addrs[0])),
+                    ]),
+                    Err(e) => Json::object([
+                        ("verified", Json::from(false)),
+                        ("line", Json::from(line)),
+                        ("message", Json::from(e)),
+                    ]),
+                },
+            );
+        }
+        Ok(Json::object([("breakpoints", Json::from(results))]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        self.clear_group("instructions");
+        let specs = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default();
+        let mut results = Vec::new();
+        for spec in specs {
+            let reference = spec.get("instructionReference").and_then(Json::as_str);
+            let offset = spec.get("offset").and_then(Json::as_i64).unwrap_or(0);
+            let result = self.eval(reference.unwrap_or("")).and_then(|addr| {
+                let addr = addr
+                    .checked_add(offset)
+                    .and_then(|addr| u16::try_from(addr).ok())
+                    .ok_or_else(|| format!("{:#x} + {} isn't an address", addr, offset))?;
+                let id =
+                    self.add_breakpoints("instructions", &[addr], spec, "instruction breakpoint")?;
+                Ok((id, addr))
+            });
+            results.push(breakpoint_result(result));
+        }
+        Ok(Json::object([("breakpoints", Json::from(results))]))
+    }
+
+    // the names are expressions, so `reset+3` works as well as `reset`
+    fn set_function_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        self.clear_group("functions");
+        let specs = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default();
+        let mut results = Vec::new();
+        for spec in specs {
+            let name = spec.get("name").and_then(Json::as_str).unwrap_or("");
+            let result = self.eval(name).and_then(|addr| {
+                let addr =
+                    u16::try_from(addr).map_err(|_| format!("{:#x} isn't an address", addr))?;
+                let id = self.add_breakpoints("functions", &[addr], spec, "function breakpoint")?;
+                Ok((id, addr))
+            });
+            results.push(breakpoint_result(result));
+        }
+        Ok(Json::object([("breakpoints", Json::from(results))]))
+    }
+
+    // an expression with the registers, flags and symbols in it
+    fn eval(&mut self, text: &str) -> Result<i64, String> {
+        let debugger = self.debugger()?;
+        let expr = expression::parse_debugger(text).map_err(|e| e.message)?;
+        expr.eval(&*debugger).map_err(|e| e.to_string())
+    }
+
+    // PC, then the JSR each subroutine that hasn't returned was
+    // called from, each named after the subroutine it's in
+    fn stack_trace(&mut self, args: &Json) -> Result<Json, String> {
+        let debugger = self.debugger()?;
+        let calls = &debugger.calls;
+        let mut frames = vec![(debugger.cpu.regs.pc, calls.last().map(|call| call.to))];
+        for (i, call) in calls.iter().enumerate().rev() {
+            let entry = i.checked_sub(1).map(|caller| calls[caller].to);
+            frames.push((call.from, entry));
+        }
+
+        let start = args
+            .get("startFrame")
+            .and_then(Json::as_i64)
+            .unwrap_or(0)
+            .max(0) as usize;
+        let levels = match args.get("levels").and_then(Json::as_i64) {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => frames.len(),
+        };
+        let symbols = &debugger.symbols;
+        let name = |addr: u16| {
+            symbols
+                .describe(addr)
+                .unwrap_or_else(|| format!("${:04X}", addr))
+        };
+        let stack_frames: Vec<Json> = frames
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, &(addr, entry))| {
+                let mut frame = Json::object([
+                    ("id", Json::from(id)),
+                    ("name", Json::from(name(entry.unwrap_or(addr)))),
+                    ("line", Json::from(0)),
+                    ("column", Json::from(0)),
+                    ("instructionPointerReference", address(addr)),
+                ]);
+                if let Some(line) = symbols.line(addr) {
+                    frame.set("source", source(&line.file));
+                    frame.set("line", Json::from(line.line));
+                    frame.set("column", Json::from(1));
+                }
+                frame
+            })
+            .collect();
+        Ok(Json::object([
+            ("stackFrames", Json::from(stack_frames)),
+            ("totalFrames", Json::from(frames.len())),
+        ]))
+    }
+
+    // names and values, with a memory reference for addresses
+    fn variables(&mut self, reference: i64) -> Result<Vec<Json>, String> {
+        let debugger = self.debugger()?;
+        let cpu = &debugger.cpu;
+        let variable = |name: String, value: String| {
+            Json::object([
+                ("name", Json::from(name)),
+                ("value", Json::from(value)),
+                ("variablesReference", Json::from(0)),
+            ])
+        };
+
+        let variables = match reference {
+            REGISTERS => {
+                let regs = &cpu.regs;
+                let pc = match debugger.symbols.describe(regs.pc) {
+                    Some(name) => format!("${:04X} ({})", regs.pc, name),
+                    None => format!("${:04X}", regs.pc),
+                };
+                let mut pc = variable(String::from("PC"), pc);
+                pc.set("memoryReference", address(regs.pc));
+                vec![
+                    variable(String::from("A"), format!("${:02X}", regs.a)),
+                    variable(String::from("X"), format!("${:02X}", regs.x)),
+                    variable(String::from("Y"), format!("${:02X}", regs.y)),
+                    variable(String::from("S"), format!("${:02X}", regs.s)),
+                    variable(String::from("P"), format!("${:02X}", regs.p)),
+                    pc,
+                    variable(String::from("cycles"), cpu.cycles.to_string()),
+                ]
+            }
+            FLAGS => debugger::FLAGS
+                .iter()
+                .map(|&(name, flag)| {
+                    variable(
+                        name.to_ascii_uppercase(),
+                        (cpu.get_flag(flag) as u8).to_string(),
+                    )
+                })
+                .collect(),
+            // 16 bytes a row
+            ZERO_PAGE => (0..0x100u16)
+                .step_by(16)
+                .map(|row| {
+                    let bytes: Vec<String> = (row..row + 16)
+                        .map(|addr| format!("{:02X}", cpu.bus.peek(addr)))
+                        .collect();
+                    let mut variable = variable(format!("${:02X}", row), bytes.join(" "));
+                    variable.set("memoryReference", address(row));
+                    variable
+                })
+                .collect(),
+            // what's been pushed, top first
+            STACK => (cpu.regs.s as u16 + 0x101..0x200)
+                .map(|addr| {
+                    variable(
+                        format!("${:04X}", addr),
+                        format!("${:02X}", cpu.bus.peek(addr)),
+                    )
+                })
+                .collect(),
+            _ => return Err(format!("no variables {}", reference)),
+        };
+        Ok(variables)
+    }
+
+    // through the debugger's `set` and `deposit`, so values are
+    // expressions like anywhere else
+    fn set_variable(&mut self, args: &Json) -> Result<Json, String> {
+        let reference = args
+            .get("variablesReference")
+            .and_then(Json::as_i64)
+            .unwrap_or(0);
+        let name = args.get("name").and_then(Json::as_str).unwrap_or("");
+        let value = args.get("value").and_then(Json::as_str).unwrap_or("");
+
+        let command = match reference {
+            REGISTERS | FLAGS if name != "cycles" => format!("set {} {}", name, value),
+            STACK => format!("deposit {} {}", name, value),
+            // a row of hex bytes, as shown
+            ZERO_PAGE => {
+                let bytes: Vec<String> = value
+                    .split_whitespace()
+                    .map(|byte| match byte.starts_with('$') {
+                        true => String::from(byte),
+                        false => format!("${}", byte),
+                    })
+                    .collect();
+                format!("deposit {} {}", name, bytes.join(" "))
+            }
+            _ => return Err(format!("{} can't be changed", name)),
+        };
+        self.debugger()?
+            .command(&command)
+            .map_err(|e| e.to_string())?;
+
+        let variables = self.variables(reference)?;
+        let value = variables
+            .iter()
+            .find(|variable| variable.get("name").and_then(Json::as_str) == Some(name))
+            .and_then(|variable| variable.get("value"))
+            .cloned()
+            .unwrap_or(Json::Null);
+        Ok(Json::object([("value", value)]))
+    }
+
+    // debugger commands in the console, expressions anywhere else
+    fn evaluate(&mut self, args: &Json) -> Result<Json, String> {
+        let text = args.get("expression").and_then(Json::as_str).unwrap_or("");
+        let result = match args.get("context").and_then(Json::as_str) {
+            Some("repl") => {
+                let debugger = self.debugger()?;
+                let before = (debugger.cpu.regs.pc, debugger.cpu.cycles);
+                let text = debugger.command(text).map_err(|e| e.to_string())?;
+                let after = (debugger.cpu.regs.pc, debugger.cpu.cycles);
+                if debugger.is_done() {
+                    self.event("terminated", Json::object([]));
+                } else if before != after {
+                    // the client has to look again at everything
+                    self.stopped_event("step", None, None);
+                }
+                text
+            }
+            _ => show_value(self.eval(text)?),
+        };
+        Ok(Json::object([
+            ("result", Json::from(result)),
+            ("variablesReference", Json::from(0)),
+        ]))
+    }
+
+    // the address a memory reference and byte offset point at
+    fn memory_address(&mut self, args: &Json) -> Result<i64, String> {
+        let reference = args.get("memoryReference").and_then(Json::as_str);
+        let offset = args.get("offset").and_then(Json::as_i64).unwrap_or(0);
+        let addr = self.eval(reference.unwrap_or(""))?;
+        addr.checked_add(offset)
+            .ok_or_else(|| format!("{:#x} + {} isn't an address", addr, offset))
+    }
+
+    fn read_memory(&mut self, args: &Json) -> Result<Json, String> {
+        let start = self.memory_address(args)?;
+        let count = args.get("count").and_then(Json::as_i64).unwrap_or(0).max(0);
+        let end = start.saturating_add(count).min(0x10000);
+        let start = start.max(0);
+
+        let debugger = self.debugger()?;
+        let bytes: Vec<u8> = (start..end.max(start))
+            .map(|addr| debugger.cpu.bus.peek(addr as u16))
+            .collect();
+        Ok(Json::object([
+            ("address", Json::from(format!("0x{:04X}", start))),
+            ("data", Json::from(base64_encode(&bytes))),
+            ("unreadableBytes", Json::from(count - bytes.len() as i64)),
+        ]))
+    }
+
+    fn write_memory(&mut self, args: &Json) -> Result<Json, String> {
+        let start = self.memory_address(args)?;
+        let data = args.get("data").and_then(Json::as_str).unwrap_or("");
+        let bytes = base64_decode(data).ok_or("the data isn't base64")?;
+        if start < 0 || start.saturating_add(bytes.len() as i64) > 0x10000 {
+            return Err(String::from("the data doesn't fit in memory"));
+        }
+
+        let debugger = self.debugger()?;
+        for (i, &byte) in bytes.iter().enumerate() {
+            debugger.cpu.bus.write((start as usize + i) as u16, byte);
+        }
+        Ok(Json::object([("bytesWritten", Json::from(bytes.len()))]))
+    }
+
+    // `instructionCount` instructions, starting `instructionOffset`
+    // instructions away from the one at the memory reference, with
+    // placeholders where there's nothing to decode
+    fn disassemble(&mut self, args: &Json) -> Result<Json, String> {
+        let addr = self.memory_address(args)?;
+        let addr = u16::try_from(addr).map_err(|_| format!("{:#x} isn't an address", addr))?;
+        // there can't be more instructions than bytes either way
+        let first = args
+            .get("instructionOffset")
+            .and_then(Json::as_i64)
+            .unwrap_or(0)
+            .clamp(-0x10000, 0x10000);
+        let count = args
+            .get("instructionCount")
+            .and_then(Json::as_i64)
+            .unwrap_or(0)
+            .clamp(0, 0x10000);
+
+        let debugger = self.debugger()?;
+        let before = (-first).max(0) as usize;
+        let after = (first + count - 1).max(0) as usize;
+        let lines = debugger.disassembler().around(addr, before, after);
+        let leading = lines.iter().take_while(|line| line.addr < addr).count() as i64;
+
+        let symbols = &debugger.symbols;
+        let instructions: Vec<Json> = (first..first + count)
+            .map(
+                |i| match usize::try_from(leading + i).ok().and_then(|i| lines.get(i)) {
+                    Some(line) => {
+                        let bytes: Vec<String> =
+                            line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                        let mut instruction = Json::object([
+                            ("address", address(line.addr)),
+                            ("instructionBytes", Json::from(bytes.join(" "))),
+                            ("instruction", Json::from(line.text.as_str())),
+                        ]);
+                        if let Some(name) = symbols.name(line.addr) {
+                            instruction.set("symbol", Json::from(name));
+                        }
+                        if let Some(source_line) = symbols.line(line.addr) {
+                            instruction.set("location", source(&source_line.file));
+                            instruction.set("line", Json::from(source_line.line));
+                        }
+                        instruction
+                    }
+                    None => Json::object([
+                        (
+                            "address",
+                            address((addr as i64 + i).clamp(0, 0xFFFF) as u16),
+                        ),
+                        ("instruction", Json::from("")),
+                        ("presentationHint", Json::from("invalid")),
+                    ]),
+                },
+            )
+            .collect();
+        Ok(Json::object([("instructions", Json::from(instructions))]))
+    }
+}
+
+fn capabilities() -> Json {
+    let supported = [
+        "supportsConfigurationDoneRequest",
+        "supportsFunctionBreakpoints",
+        "supportsConditionalBreakpoints",
+        "supportsHitConditionalBreakpoints",
+        "supportsEvaluateForHovers",
+        "supportsSetVariable",
+        "supportsReadMemoryRequest",
+        "supportsWriteMemoryRequest",
+        "supportsDisassembleRequest",
+        "supportsInstructionBreakpoints",
+        "supportsSteppingGranularity",
+        "supportsTerminateRequest",
+    ];
+    Json::Object(
+        supported
+            .iter()
+            .map(|&name| (String::from(name), Json::from(true)))
+            .collect(),
+    )
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64, expensive: bool| {
+        Json::object([
+            ("name", Json::from(name)),
+            ("variablesReference", Json::from(reference)),
+            ("expensive", Json::from(expensive)),
+        ])
+    };
+    Json::object([(
+        "scopes",
+        Json::from(vec![
+            scope("Registers", REGISTERS, false),
+            scope("Flags", FLAGS, false),
+            scope("Zero page", ZERO_PAGE, true),
+            scope("Stack", STACK, false),
+        ]),
+    )])
+}
+
+// the CPU and memory a `launch` asks for: `program` loaded at
+// `origin`, starting at `start`, with `labels` loaded, like the
+// `debug` command's arguments
+fn load(args: &Json) -> Result<Debugger, String> {
+    let program = args
+        .get("program")
+        .and_then(Json::as_str)
+        .ok_or("launch needs a program")?;
+    let data = fs::read(program).map_err(|e| format!("error reading {}: {}", program, e))?;
+    let variant = match args.get("variant").and_then(Json::as_str) {
+        Some(name) => name.parse()?,
+        None => Variant::default(),
+    };
+    let origin = args.get("origin").map(number).transpose()?;
+    let start = args.get("start").map(number).transpose()?;
+
+    let mut debugger = Debugger::with_image(variant, &data, origin.unwrap_or(0), start);
+    let labels = match args.get("labels") {
+        Some(Json::Array(labels)) => labels.iter().filter_map(Json::as_str).collect(),
+        Some(Json::String(path)) => vec![path.as_str()],
+        _ => Vec::new(),
+    };
+    for path in labels {
+        debugger.symbols.load(path).map_err(|e| e.to_string())?;
+    }
+    Ok(debugger)
+}
+
+// an address as a number, or as text: decimal, 0x.. or $..
+fn number(value: &Json) -> Result<u16, String> {
+    let parsed = match value {
+        Json::String(text) => match text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+            Some(hex) => i64::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        },
+        value => value.as_i64(),
+    };
+    parsed
+        .and_then(|n| u16::try_from(n).ok())
+        .ok_or_else(|| format!("invalid address {}", value))
+}
+
+fn flag(args: &Json, name: &str) -> Option<bool> {
+    args.get(name).and_then(Json::as_bool)
+}
+
+fn address(addr: u16) -> Json {
+    Json::from(format!("0x{:04X}", addr))
+}
+
+fn source(file: &str) -> Json {
+    // clients want a full path to open the file
+    let path = fs::canonicalize(file)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| String::from(file));
+    let name = Path::new(file)
+        .file_name()
+        .map_or(file, |name| name.to_str().unwrap_or(file));
+    Json::object([("name", Json::from(name)), ("path", Json::from(path))])
+}
+
+fn breakpoint_result(result: Result<(usize, u16), String>) -> Json {
+    match result {
+        Ok((id, addr)) => Json::object([
+            ("id", Json::from(id)),
+            ("verified", Json::from(true)),
+            ("instructionReference", address(addr)),
+        ]),
+        Err(e) => Json::object([("verified", Json::from(false)), ("message", Json::from(e))]),
+    }
+}
+
+// `$07 (7)`, `$C000 (49152)`, or just the number when it's
+// negative or too big for an address
+fn show_value(value: i64) -> String {
+    match value {
+        0..=0xFF => format!("${:02X} ({})", value, value),
+        0x100..=0xFFFF => format!("${:04X} ({})", value, value),
+        _ => value.to_string(),
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(n >> (18 - 6 * i)) as usize & 0x3F] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::new();
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        n = n << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,863 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::rc::Rc;
+
+use crate::bus::{Access, Bus, Ram, WatchKind, Watched, Watches};
+use crate::system::cpu::{Cpu, Flag, Instruction, Variant};
+use crate::system::optable;
+use crate::system::util::disassembler::{DisasmLine, Disassembler};
+use crate::system::util::expression::{self, Context, EvalError, Expr};
+use crate::system::util::symbols::SymbolTable;
+
+const PROMPT: &str = "(vanilla) ";
+
+const HELP: &str = "\
+step [n]                 s   run n instructions (default 1)
+next                     n   run one instruction, stepping over subroutines
+continue                 c   run until a breakpoint or a trap
+regs                     r   show the registers and flags
+set <reg> <value>            change a, x, y, s, p, pc or a flag (n v d i z c)
+examine <addr> [len]     x   dump memory (default 64 bytes)
+deposit <addr> <byte>... d   write bytes to memory
+list [addr] [count]      l   disassemble around PC or from addr
+backtrace                bt  show the subroutines that haven't returned
+break [addr] [if <cond>] b   add a breakpoint, or list them
+log [addr] [if <cond>]       show the registers when hit and carry on
+ignore <id> <n>              let the next n hits of a breakpoint pass
+delete <id|all>              remove breakpoints
+watch [<kind> <addr> [end]]  stop on a read, write or change of a value
+                             between addr and end, or list watchpoints
+unwatch <id|all>             remove watchpoints
+help                     h   show this
+quit                     q   leave the debugger
+addresses, values and conditions are expressions: $C000, 0x10, reset+3,
+*+2, or e.g. pc == $C123 && a > #$10 && [$0200] != 0 && cycles > 100000
+using a, x, y, s, p, pc, cycles, the flags n, v, d, i, z and c, symbols
+and [addr] for the byte in memory at addr
+an empty line repeats the last command";
+
+// the P bits from N down to C, as `regs` shows them
+const P_BITS: [char; 8] = ['N', 'V', '-', 'B', 'D', 'I', 'Z', 'C'];
+
+// how many instructions the servers run between checks for a
+// pause from their client
+pub const CHUNK: u64 = 10_000;
+
+pub const FLAGS: [(&str, Flag); 6] = [
+    ("n", Flag::N),
+    ("v", Flag::V),
+    ("d", Flag::D),
+    ("i", Flag::I),
+    ("z", Flag::Z),
+    ("c", Flag::C),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Unknown(String),
+    // how the command should have been used
+    Usage(&'static str),
+    Syntax(String),
+    Eval(EvalError),
+    // a value and what it had to fit in
+    OutOfRange(i64, &'static str),
+    UnknownRegister(String),
+    NoBreakpoint(usize),
+    NoWatchpoint(usize),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Unknown(s) => write!(f, "unknown command `{}`, try `help`", s),
+            CommandError::Usage(usage) => write!(f, "usage: {}", usage),
+            CommandError::Syntax(e) => write!(f, "{}", e),
+            CommandError::Eval(e) => write!(f, "{}", e),
+            CommandError::OutOfRange(value, what) => {
+                write!(f, "{} ({:#x}) doesn't fit in {}", value, value, what)
+            }
+            CommandError::UnknownRegister(s) => write!(f, "unknown register or flag `{}`", s),
+            CommandError::NoBreakpoint(id) => write!(f, "no breakpoint {}", id),
+            CommandError::NoWatchpoint(id) => write!(f, "no watchpoint {}", id),
+        }
+    }
+}
+
+impl From<EvalError> for CommandError {
+    fn from(e: EvalError) -> CommandError {
+        CommandError::Eval(e)
+    }
+}
+
+// why execution stopped
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Stop {
+    // ran as far as it was asked to
+    Done,
+    // the id of the breakpoint
+    Breakpoint(usize),
+    // a breakpoint whose condition couldn't be evaluated
+    BadCondition { id: usize, error: EvalError },
+    // what the instruction at `pc` did to set off watchpoints
+    Watch { pc: u16, hits: Vec<Access> },
+    // stuck in a `JMP *` or a branch to itself
+    Trap,
+    Jammed,
+    // ran `limit` instructions without any other reason to stop
+    Limit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    // None to check the condition after every instruction
+    pub addr: Option<u16>,
+    // the text as given, to show it back
+    pub condition: Option<(String, Expr)>,
+    // how many times it was reached with its condition true
+    pub hits: u64,
+    // how many more hits to let pass
+    pub ignore: u64,
+    // log the registers and carry on instead of stopping
+    pub log: bool,
+}
+
+// a JSR that hasn't returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Call {
+    // the JSR instruction and the subroutine it went to
+    pub from: u16,
+    pub to: u16,
+    // S before the return address went on the stack
+    pub s: u8,
+}
+
+pub struct Debugger {
+    pub cpu: Cpu,
+    pub symbols: SymbolTable,
+    pub breakpoints: Vec<Breakpoint>,
+    // innermost last, as seen by `run`
+    pub calls: Vec<Call>,
+    // shared with the bus `new` wraps around the CPU's
+    pub watches: Rc<RefCell<Watches>>,
+    // the most instructions `continue` and `next` run in one go
+    pub limit: u64,
+    next_id: usize,
+    // what an empty line repeats
+    last: String,
+    // what log breakpoints had to say since the last report
+    logged: Vec<String>,
+    quit: bool,
+}
+
+// registers, flags and symbols, `*` for PC and memory as the CPU
+// sees it, all without side effects
+impl Context for Debugger {
+    fn symbol(&self, name: &str) -> Option<i64> {
+        let regs = &self.cpu.regs;
+        let lower = name.to_ascii_lowercase();
+        let value = match lower.as_str() {
+            "a" => regs.a as i64,
+            "x" => regs.x as i64,
+            "y" => regs.y as i64,
+            "s" | "sp" => regs.s as i64,
+            "p" => regs.p as i64,
+            "pc" => regs.pc as i64,
+            "cycles" => self.cpu.cycles as i64,
+            _ => match FLAGS.iter().find(|(flag, _)| *flag == lower) {
+                Some(&(_, flag)) => self.cpu.get_flag(flag) as i64,
+                None => return self.symbols.addr(name).map(i64::from),
+            },
+        };
+        Some(value)
+    }
+
+    fn pc(&self) -> Option<i64> {
+        Some(self.cpu.regs.pc as i64)
+    }
+
+    fn memory(&self, addr: i64) -> Option<i64> {
+        let addr = u16::try_from(addr).ok()?;
+        Some(self.cpu.bus.peek(addr) as i64)
+    }
+}
+
+impl Debugger {
+    pub fn new(mut cpu: Cpu) -> Debugger {
+        // the RAM only holds the bus's place while it's moved
+        let bus = Watched::new(mem::replace(&mut cpu.bus, Box::new(Ram::new())));
+        let watches = bus.watches.clone();
+        cpu.bus = Box::new(bus);
+
+        Debugger {
+            cpu,
+            symbols: SymbolTable::new(),
+            breakpoints: Vec::new(),
+            calls: Vec::new(),
+            watches,
+            limit: 10_000_000,
+            next_id: 1,
+            last: String::new(),
+            logged: Vec::new(),
+            quit: false,
+        }
+    }
+
+    // `data` in RAM at `origin`, starting at `start`, or with a
+    // reset if `data` covers the RESET vector and at `origin` if not
+    pub fn with_image(variant: Variant, data: &[u8], origin: u16, start: Option<u16>) -> Debugger {
+        // both bytes of the vector, not just the end of memory
+        let covers_reset = origin <= 0xFFFC && origin as usize + data.len() >= 0x10000;
+        let mut ram = Ram::new();
+        ram.load(origin, data);
+        let mut cpu = Cpu::with_variant(variant, Box::new(ram));
+        match start {
+            Some(start) => {
+                cpu.regs.s = 0xFD;
+                cpu.regs.pc = start;
+            }
+            None if covers_reset => cpu.reset(),
+            None => {
+                cpu.regs.s = 0xFD;
+                cpu.regs.pc = origin;
+            }
+        }
+        Debugger::new(cpu)
+    }
+
+    // whether `quit` was given
+    pub fn is_done(&self) -> bool {
+        self.quit
+    }
+
+    // reads commands from `input` until it ends or `quit`,
+    // writing the results and errors to `output`
+    pub fn repl(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<()> {
+        writeln!(output, "{}", self.current())?;
+        while !self.quit {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            match self.command(&line) {
+                Ok(text) if text.is_empty() => {}
+                Ok(text) => writeln!(output, "{}", text)?,
+                Err(e) => writeln!(output, "error: {}", e)?,
+            }
+        }
+        Ok(())
+    }
+
+    // runs one command line, returning what it has to say
+    pub fn command(&mut self, line: &str) -> Result<String, CommandError> {
+        let mut line = line.trim();
+        if line.is_empty() {
+            line = &self.last;
+        }
+        let line = String::from(line);
+        self.last = line.clone();
+
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(String::new());
+        };
+        let args: Vec<&str> = words.collect();
+        // for commands that take an expression with spaces in it
+        let rest = line[command.len()..].trim();
+
+        match command {
+            "step" | "s" => {
+                let count = match args.as_slice() {
+                    [] => 1,
+                    [count] => self.eval(count)?.max(0) as u64,
+                    _ => return Err(CommandError::Usage("step [n]")),
+                };
+                let stop = match self.run(count, &|_| false) {
+                    Stop::Limit => Stop::Done,
+                    stop => stop,
+                };
+                Ok(self.report(stop))
+            }
+            "next" | "n" => {
+                let stop = self.step_over();
+                Ok(self.report(stop))
+            }
+            "continue" | "c" => {
+                let stop = self.run(self.limit, &|_| false);
+                Ok(self.report(stop))
+            }
+            "regs" | "r" => Ok(self.regs()),
+            "set" => match args.as_slice() {
+                [name, value] => {
+                    let value = self.eval(value)?;
+                    self.set(name, value)?;
+                    Ok(self.regs())
+                }
+                _ => Err(CommandError::Usage("set <reg> <value>")),
+            },
+            "examine" | "x" => match args.as_slice() {
+                [addr] => Ok(self.examine(self.addr(addr)?, 64)),
+                [addr, len] => {
+                    let len = self.eval(len)?.clamp(0, 0x10000) as usize;
+                    Ok(self.examine(self.addr(addr)?, len))
+                }
+                _ => Err(CommandError::Usage("examine <addr> [len]")),
+            },
+            "deposit" | "d" => {
+                let [addr, bytes @ ..] = args.as_slice() else {
+                    return Err(CommandError::Usage("deposit <addr> <byte>..."));
+                };
+                if bytes.is_empty() {
+                    return Err(CommandError::Usage("deposit <addr> <byte>..."));
+                }
+                let addr = self.addr(addr)?;
+                let mut values = Vec::new();
+                for byte in bytes {
+                    values.push(self.byte(byte)?);
+                }
+                for (i, &value) in values.iter().enumerate() {
+                    self.cpu.bus.write(addr.wrapping_add(i as u16), value);
+                }
+                Ok(self.examine(addr, values.len()))
+            }
+            "list" | "l" => match args.as_slice() {
+                [] => Ok(self.list_around(self.cpu.regs.pc)),
+                [addr] => Ok(self.list(self.addr(addr)?, 10)),
+                [addr, count] => {
+                    let count = self.eval(count)?.max(0) as usize;
+                    Ok(self.list(self.addr(addr)?, count))
+                }
+                _ => Err(CommandError::Usage("list [addr] [count]")),
+            },
+            "backtrace" | "bt" => Ok(self.backtrace()),
+            "break" | "b" if args.is_empty() => Ok(self.list_breakpoints()),
+            "break" | "b" | "log" => {
+                let log = command == "log";
+                let usage = match log {
+                    true => CommandError::Usage("log [addr] [if <condition>]"),
+                    false => CommandError::Usage("break [addr] [if <condition>]"),
+                };
+                let (addr, condition) = match rest.split_once(char::is_whitespace) {
+                    _ if rest.is_empty() => return Err(usage),
+                    Some(("if", condition)) => (None, Some(condition)),
+                    Some((addr, rest)) => match rest.trim_start().strip_prefix("if ") {
+                        Some(condition) => (Some(addr), Some(condition)),
+                        None => return Err(usage),
+                    },
+                    None => (Some(rest), None),
+                };
+                let addr = addr.map(|addr| self.addr(addr)).transpose()?;
+
+                let id = self.add_breakpoint(addr, condition, log)?;
+                Ok(self.describe_breakpoint(id))
+            }
+            "ignore" => match args.as_slice() {
+                [id, count] => {
+                    let id = self.eval(id)?.max(0) as usize;
+                    let count = self.eval(count)?.max(0) as u64;
+                    let Some(breakpoint) = self.breakpoints.iter_mut().find(|b| b.id == id) else {
+                        return Err(CommandError::NoBreakpoint(id));
+                    };
+                    breakpoint.ignore = count;
+                    Ok(self.describe_breakpoint(id))
+                }
+                _ => Err(CommandError::Usage("ignore <id> <n>")),
+            },
+            "delete" => match args.as_slice() {
+                ["all"] => {
+                    self.breakpoints.clear();
+                    Ok(String::new())
+                }
+                [id] => {
+                    let id = self.eval(id)?.max(0) as usize;
+                    self.delete_breakpoint(id)?;
+                    Ok(String::new())
+                }
+                _ => Err(CommandError::Usage("delete <id|all>")),
+            },
+            "watch" => {
+                let usage = CommandError::Usage("watch [read|write|change <addr> [end]]");
+                let (kind, range) = match args.as_slice() {
+                    [] => return Ok(self.list_watchpoints()),
+                    [kind, addr] => (kind, (addr, addr)),
+                    [kind, start, end] => (kind, (start, end)),
+                    _ => return Err(usage),
+                };
+                let kind = match *kind {
+                    "read" => WatchKind::Read,
+                    "write" => WatchKind::Write,
+                    "change" => WatchKind::Change,
+                    _ => return Err(usage),
+                };
+                let (start, end) = (self.addr(range.0)?, self.addr(range.1)?);
+                // a reversed range would never match anything
+                if start > end {
+                    return Err(CommandError::Usage(
+                        "watch [read|write|change <addr> [end]], with end at or after addr",
+                    ));
+                }
+                let id = self.watches.borrow_mut().add(start..=end, kind);
+                Ok(format!(
+                    "watchpoint {} on {}",
+                    id,
+                    self.describe_range(start, end)
+                ))
+            }
+            "unwatch" => match args.as_slice() {
+                ["all"] => {
+                    self.watches.borrow_mut().points.clear();
+                    Ok(String::new())
+                }
+                [id] => {
+                    let id = self.eval(id)?.max(0) as usize;
+                    if !self.watches.borrow_mut().remove(id) {
+                        return Err(CommandError::NoWatchpoint(id));
+                    }
+                    Ok(String::new())
+                }
+                _ => Err(CommandError::Usage("unwatch <id|all>")),
+            },
+            "help" | "h" => Ok(String::from(HELP)),
+            "quit" | "q" => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            _ => Err(CommandError::Unknown(String::from(command))),
+        }
+    }
+
+    // stops at `addr` if given, whenever `condition` holds if given
+    pub fn add_breakpoint(
+        &mut self,
+        addr: Option<u16>,
+        condition: Option<&str>,
+        log: bool,
+    ) -> Result<usize, CommandError> {
+        let condition = match condition.map(str::trim) {
+            Some(text) => {
+                let expr = expression::parse_debugger(text)
+                    .map_err(|e| CommandError::Syntax(e.message))?;
+                // a typo should show up now rather than on the first hit
+                if let Err(EvalError::Undefined(name)) = expr.eval(self) {
+                    return Err(CommandError::Eval(EvalError::Undefined(name)));
+                }
+                Some((String::from(text), expr))
+            }
+            None => None,
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr,
+            condition,
+            hits: 0,
+            ignore: 0,
+            log,
+        });
+        Ok(id)
+    }
+
+    pub fn delete_breakpoint(&mut self, id: usize) -> Result<(), CommandError> {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        if self.breakpoints.len() == before {
+            return Err(CommandError::NoBreakpoint(id));
+        }
+        Ok(())
+    }
+
+    // runs up to `max` instructions, stopping early once `until`
+    // holds or at a breakpoint; one at the starting PC is ignored
+    // so execution can carry on from it
+    pub fn run(&mut self, max: u64, until: &dyn Fn(&Cpu) -> bool) -> Stop {
+        // anything left over came from the debugger's own writes
+        self.watches.borrow_mut().hits.clear();
+
+        for _ in 0..max {
+            if self.cpu.jammed {
+                return Stop::Jammed;
+            }
+
+            let (pc, s) = (self.cpu.regs.pc, self.cpu.regs.s);
+            self.cpu.step();
+            self.track_calls(pc, s);
+            let hits = mem::take(&mut self.watches.borrow_mut().hits);
+            if !hits.is_empty() {
+                return Stop::Watch { pc, hits };
+            }
+            if self.cpu.jammed {
+                return Stop::Jammed;
+            }
+            if self.cpu.regs.pc == pc {
+                return Stop::Trap;
+            }
+            if until(&self.cpu) {
+                return Stop::Done;
+            }
+
+            if let Some(stop) = self.check_breakpoints() {
+                return stop;
+            }
+        }
+        Stop::Limit
+    }
+
+    // keeps `calls` up to date after a step from `pc` with the
+    // stack pointer at `s`
+    fn track_calls(&mut self, pc: u16, s: u8) {
+        // an RTS, or anything else that takes the stack back above
+        // where a call started, leaves that call
+        while self
+            .calls
+            .last()
+            .is_some_and(|call| self.cpu.regs.s >= call.s)
+        {
+            self.calls.pop();
+        }
+        // going by what the step did rather than the opcode at `pc`,
+        // as an interrupt may have been taken instead: only a JSR
+        // pushes two bytes, the address of its own last byte
+        let stack = |offset: u8| self.cpu.bus.peek(0x0100 | s.wrapping_sub(offset) as u16);
+        let pushed = u16::from_le_bytes([stack(1), stack(0)]);
+        if self.cpu.regs.s == s.wrapping_sub(2) && pushed == pc.wrapping_add(2) {
+            self.calls.push(Call {
+                from: pc,
+                to: self.cpu.regs.pc,
+                s,
+            });
+        }
+    }
+
+    // counts the hits at the new PC, logging or stopping for them
+    fn check_breakpoints(&mut self) -> Option<Stop> {
+        let pc = self.cpu.regs.pc;
+        for i in 0..self.breakpoints.len() {
+            let breakpoint = &self.breakpoints[i];
+            let id = breakpoint.id;
+            if breakpoint.addr.is_some_and(|addr| addr != pc) {
+                continue;
+            }
+            if let Some((_, expr)) = &breakpoint.condition {
+                match expr.eval(self) {
+                    Ok(0) => continue,
+                    Ok(_) => {}
+                    Err(error) => return Some(Stop::BadCondition { id, error }),
+                }
+            }
+
+            let breakpoint = &mut self.breakpoints[i];
+            breakpoint.hits += 1;
+            if breakpoint.ignore > 0 {
+                breakpoint.ignore -= 1;
+            } else if breakpoint.log {
+                let line = format!("log {} at {}: {}", id, self.describe(pc), self.regs());
+                self.logged.push(line);
+            } else {
+                return Some(Stop::Breakpoint(id));
+            }
+        }
+        None
+    }
+
+    // a single instruction, except that a JSR runs until the
+    // subroutine returns to the instruction after it
+    pub fn step_over(&mut self) -> Stop {
+        let line = self.disassembler().decode(self.cpu.regs.pc);
+        match line {
+            Some(line) if line.op.instruction == Instruction::Jsr => {
+                let (ret, s) = (line.next_addr(), self.cpu.regs.s);
+                self.run(self.limit, &|cpu| cpu.regs.pc == ret && cpu.regs.s == s)
+            }
+            _ => match self.run(1, &|_| false) {
+                Stop::Limit => Stop::Done,
+                stop => stop,
+            },
+        }
+    }
+
+    pub fn disassembler(&self) -> Disassembler<'_, &dyn Bus> {
+        let mut disassembler =
+            Disassembler::with_memory(&*self.cpu.bus, optable::optable(self.cpu.variant));
+        disassembler.symbols = Some(&self.symbols);
+        disassembler
+    }
+
+    fn eval(&self, text: &str) -> Result<i64, CommandError> {
+        let expr = expression::parse_debugger(text).map_err(|e| CommandError::Syntax(e.message))?;
+        Ok(expr.eval(self)?)
+    }
+
+    fn addr(&self, text: &str) -> Result<u16, CommandError> {
+        let value = self.eval(text)?;
+        u16::try_from(value).map_err(|_| CommandError::OutOfRange(value, "an address"))
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, CommandError> {
+        let value = self.eval(text)?;
+        // negative bytes are fine, as two's complement
+        match value {
+            -0x80..=0xFF => Ok(value as u8),
+            _ => Err(CommandError::OutOfRange(value, "a byte")),
+        }
+    }
+
+    fn set(&mut self, name: &str, value: i64) -> Result<(), CommandError> {
+        let name = name.to_ascii_lowercase();
+        if let Some(&(_, flag)) = FLAGS.iter().find(|(flag, _)| *flag == name) {
+            match value {
+                0 | 1 => self.cpu.set_flag(flag, value == 1),
+                _ => return Err(CommandError::OutOfRange(value, "a flag")),
+            }
+            return Ok(());
+        }
+
+        let regs = &mut self.cpu.regs;
+        let reg = match name.as_str() {
+            "pc" => {
+                regs.pc =
+                    u16::try_from(value).map_err(|_| CommandError::OutOfRange(value, "PC"))?;
+                return Ok(());
+            }
+            "a" => &mut regs.a,
+            "x" => &mut regs.x,
+            "y" => &mut regs.y,
+            "s" | "sp" => &mut regs.s,
+            "p" => &mut regs.p,
+            _ => return Err(CommandError::UnknownRegister(name)),
+        };
+        *reg = u8::try_from(value).map_err(|_| CommandError::OutOfRange(value, "a register"))?;
+        Ok(())
+    }
+
+    // `$0010`, or `$0010-$001F` with the symbols for both ends
+    fn describe_range(&self, start: u16, end: u16) -> String {
+        match start == end {
+            true => self.describe(start),
+            false => format!("{}-{}", self.describe(start), self.describe(end)),
+        }
+    }
+
+    // `$C000 (reset)`, or just `$C000` with no symbol nearby
+    fn describe(&self, addr: u16) -> String {
+        match self.symbols.describe(addr) {
+            Some(name) => format!("${:04X} ({})", addr, name),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    // e.g. `PC=C000 A=00 X=00 Y=00 S=FD P=24 [nv-bdIzc] cycles=7`
+    pub fn regs(&self) -> String {
+        let regs = &self.cpu.regs;
+        let flags: String = P_BITS
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| match regs.p & (0x80 >> i) {
+                _ if c == '-' => c,
+                0 => c.to_ascii_lowercase(),
+                _ => c,
+            })
+            .collect();
+        format!(
+            "PC={:04X} A={:02X} X={:02X} Y={:02X} S={:02X} P={:02X} [{}] cycles={}",
+            regs.pc, regs.a, regs.x, regs.y, regs.s, regs.p, flags, self.cpu.cycles
+        )
+    }
+
+    // 16 bytes a line, with the printable ones on the right
+    fn examine(&self, addr: u16, len: usize) -> String {
+        let mut lines = Vec::new();
+        for start in (0..len).step_by(16) {
+            let line_addr = addr.wrapping_add(start as u16);
+            let bytes: Vec<u8> = (0..(len - start).min(16))
+                .map(|i| self.cpu.bus.peek(line_addr.wrapping_add(i as u16)))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&b| match b {
+                    0x20..=0x7E => b as char,
+                    _ => '.',
+                })
+                .collect();
+            lines.push(format!(
+                "{:04X}  {:<47}  {}",
+                line_addr,
+                hex.join(" "),
+                text
+            ));
+        }
+        lines.join("\n")
+    }
+
+    // marks the instruction at PC, with its effective address
+    fn show(&self, line: &DisasmLine) -> String {
+        let mut line = line.clone();
+        if line.addr == self.cpu.regs.pc {
+            line.text = line.annotated(&self.cpu);
+            return format!("> {}", line);
+        }
+        format!("  {}", line)
+    }
+
+    // the instruction at PC, with its label if it has one
+    pub fn current(&self) -> String {
+        let pc = self.cpu.regs.pc;
+        let line = match self.disassembler().decode(pc) {
+            Some(line) => self.show(&line),
+            None => format!("> {:04X}", pc),
+        };
+        match self.symbols.name(pc) {
+            Some(name) => format!("{}:\n{}", name, line),
+            None => line,
+        }
+    }
+
+    fn list_lines(&self, lines: Vec<DisasmLine>) -> String {
+        let mut out = Vec::new();
+        for line in &lines {
+            if let Some(name) = self.symbols.name(line.addr) {
+                out.push(format!("{}:", name));
+            }
+            out.push(self.show(line));
+        }
+        out.join("\n")
+    }
+
+    fn list_around(&self, addr: u16) -> String {
+        self.list_lines(self.disassembler().around(addr, 5, 5))
+    }
+
+    fn list(&self, addr: u16, count: usize) -> String {
+        let lines = self.disassembler().range(addr..).take(count).collect();
+        self.list_lines(lines)
+    }
+
+    // e.g. `breakpoint 1 at $C000 (reset) if a > 3`
+    fn describe_breakpoint(&self, id: usize) -> String {
+        let Some(breakpoint) = self.breakpoints.iter().find(|b| b.id == id) else {
+            return String::new();
+        };
+        let mut text = match breakpoint.log {
+            true => format!("log {}", id),
+            false => format!("breakpoint {}", id),
+        };
+        if let Some(addr) = breakpoint.addr {
+            text += &format!(" at {}", self.describe(addr));
+        }
+        if let Some((condition, _)) = &breakpoint.condition {
+            text += &format!(" if {}", condition);
+        }
+        if breakpoint.ignore > 0 {
+            text += &format!(", ignoring the next {} hits", breakpoint.ignore);
+        }
+        text
+    }
+
+    // PC, then each JSR still waiting for its subroutine to return
+    fn backtrace(&self) -> String {
+        let frames =
+            std::iter::once(self.cpu.regs.pc).chain(self.calls.iter().rev().map(|call| call.from));
+        let lines: Vec<String> = frames
+            .enumerate()
+            .map(|(i, addr)| format!("#{:<2} {}", i, self.describe(addr)))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return String::from("no breakpoints");
+        }
+        let lines: Vec<String> = self
+            .breakpoints
+            .iter()
+            .map(|breakpoint| {
+                format!(
+                    "{:>3}  {}, hit {} times",
+                    breakpoint.id,
+                    self.describe_breakpoint(breakpoint.id),
+                    breakpoint.hits
+                )
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    fn list_watchpoints(&self) -> String {
+        let watches = self.watches.borrow();
+        if watches.points.is_empty() {
+            return String::from("no watchpoints");
+        }
+        let lines: Vec<String> = watches
+            .points
+            .iter()
+            .map(|point| {
+                let kind = match point.kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::Change => "change",
+                };
+                let range = self.describe_range(*point.range.start(), *point.range.end());
+                format!("{:>3}  {:<6}  {}", point.id, kind, range)
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    // e.g. `watchpoint 1 at $0408: $0010 changed from $00 to $07`
+    fn describe_hit(&self, pc: u16, hit: &Access) -> String {
+        let what = match hit.kind {
+            WatchKind::Read => format!("read {} = ${:02X}", self.describe(hit.addr), hit.new),
+            WatchKind::Write => format!(
+                "wrote ${:02X} to {}, was ${:02X}",
+                hit.new,
+                self.describe(hit.addr),
+                hit.old
+            ),
+            WatchKind::Change => format!(
+                "{} changed from ${:02X} to ${:02X}",
+                self.describe(hit.addr),
+                hit.old,
+                hit.new
+            ),
+        };
+        format!("watchpoint {} at {}: {}", hit.id, self.describe(pc), what)
+    }
+
+    // anything logged on the way, why execution stopped and where
+    pub fn report(&mut self, stop: Stop) -> String {
+        let pc = self.cpu.regs.pc;
+        let reason = match stop {
+            Stop::Done => None,
+            Stop::Breakpoint(id) => Some(format!("breakpoint {} at {}", id, self.describe(pc))),
+            Stop::BadCondition { id, error } => Some(format!(
+                "breakpoint {} at {}: can't check the condition: {}",
+                id,
+                self.describe(pc),
+                error
+            )),
+            Stop::Watch { pc, hits } => {
+                let lines: Vec<String> =
+                    hits.iter().map(|hit| self.describe_hit(pc, hit)).collect();
+                Some(lines.join("\n"))
+            }
+            Stop::Trap => Some(format!(
+                "stuck at {}, it jumps to itself",
+                self.describe(pc)
+            )),
+            Stop::Jammed => Some(String::from("the CPU is jammed")),
+            Stop::Limit => Some(format!("stopped after {} instructions", self.limit)),
+        };
+        let mut lines = mem::take(&mut self.logged);
+        lines.extend(reason);
+        lines.push(self.current());
+        lines.join("\n")
+    }
+}
@@ -1,85 +1,390 @@
-use crate::system::cpu::{AddrMode, Op};
+use std::fmt;
+use std::ops::{Bound, Range, RangeBounds};
 
-pub struct Disassembler<'a> {
+use crate::bus::Bus;
+use crate::system::cpu::{AddrMode, Cpu, Instruction, Op, OpInfo};
+use crate::system::util::symbols::SymbolTable;
+
+// where the disassembler gets its bytes from
+pub trait Memory {
+    // the byte at `addr`, or None outside of `span`
+    fn peek(&self, addr: u16) -> Option<u8>;
+
+    // the addresses that can be peeked, as u32 so it can reach $FFFF
+    fn span(&self) -> Range<u32>;
+}
+
+// a binary file mapped at a fixed address
+pub struct Image {
     pub data: Vec<u8>,
-    pub optable: &'a Vec<Op>,
-}
-
-fn get_arg_count(op: &Op) -> u32 {
-    match op.info.address_mode {
-        AddrMode::Imp | AddrMode::Acc => 0,
-
-        AddrMode::Zp
-        | AddrMode::Imm
-        | AddrMode::Indx
-        | AddrMode::Indy
-        | AddrMode::Zpx
-        | AddrMode::Zpy
-        | AddrMode::Rel => 1,
-
-        AddrMode::Ind | AddrMode::Abs | AddrMode::Absx | AddrMode::Absy => 2,
-    }
-}
-
-fn get_op_fmt_string(op: &Op, arg: u16) -> String {
-    let instr_string: String = format!("{:?}", op.info.instruction).to_uppercase();
-    let arg_string: String = match op.info.address_mode {
-        AddrMode::Imp => format!(""),
-        AddrMode::Acc => format!(" A"),
-        AddrMode::Imm => format!(" #{:#04x}", arg),
-        AddrMode::Rel => format!(" ${:#04x}", arg),
-        AddrMode::Zp => format!(" ${:#04x}", arg),
-        AddrMode::Zpx => format!(" ${:#04x}", arg),
-        AddrMode::Zpy => format!(" ${:#04x}", arg),
-        AddrMode::Ind => format!(" (${:#06x})", arg),
-        AddrMode::Abs => format!(" ${:#06x}", arg),
-        AddrMode::Absx => format!(" ${:#06x}, X", arg),
-        AddrMode::Absy => format!(" ${:#06x}, Y", arg),
-        AddrMode::Indx => format!(" (${:#04x}, X)", arg),
-        AddrMode::Indy => format!(" (${:#04x}), Y", arg),
+    // the address `data[0]` lives at
+    pub origin: u16,
+}
+
+impl Memory for Image {
+    fn peek(&self, addr: u16) -> Option<u8> {
+        let index = (addr as usize).checked_sub(self.origin as usize)?;
+        self.data.get(index).copied()
+    }
+
+    fn span(&self) -> Range<u32> {
+        let end = (self.origin as u32 + self.data.len() as u32).min(0x10000);
+        self.origin as u32..end
+    }
+}
+
+// live memory, read through `Bus::peek` so disassembling
+// never disturbs a device
+impl<B: Bus + ?Sized> Memory for &B {
+    fn peek(&self, addr: u16) -> Option<u8> {
+        Some(Bus::peek(*self, addr))
+    }
+
+    fn span(&self) -> Range<u32> {
+        0..0x10000
+    }
+}
+
+pub struct Disassembler<'a, M = Image> {
+    pub memory: M,
+    pub optable: &'a [Op],
+    // names for operand addresses, if any were loaded
+    pub symbols: Option<&'a SymbolTable>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Operand {
+    None,
+    Byte(u8),
+    Word(u16),
+    // the data ended before the operand did
+    Truncated,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DisasmLine {
+    pub addr: u16,
+    // the opcode followed by whatever operand bytes there were
+    pub bytes: Vec<u8>,
+    pub op: OpInfo,
+    pub operand: Operand,
+    pub text: String,
+}
+
+impl DisasmLine {
+    pub fn is_truncated(&self) -> bool {
+        self.operand == Operand::Truncated
+    }
+
+    // the address of the instruction that follows this one
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.op.bytes as u16)
+    }
+
+    // where a branch or jump goes, or the operand of any other
+    // instruction that takes an absolute address
+    pub fn target(&self) -> Option<u16> {
+        match (self.op.address_mode, self.operand) {
+            // relative to the instruction that follows the branch
+            (AddrMode::Rel, Operand::Byte(offset)) => {
+                Some(self.addr.wrapping_add(2).wrapping_add(offset as i8 as u16))
+            }
+            // the zero page byte to test comes first
+            (AddrMode::Zpr, Operand::Word(arg)) => {
+                let offset = (arg >> 8) as u8 as i8;
+                Some(self.addr.wrapping_add(3).wrapping_add(offset as u16))
+            }
+            (_, Operand::Word(arg)) => Some(arg),
+            _ => None,
+        }
+    }
+
+    // renders the instruction, letting `name` replace addresses
+    // in the operand with symbols
+    pub fn format(&self, name: &dyn Fn(u16) -> Option<String>) -> String {
+        let mnemonic = match self.op.undocumented {
+            true => format!("*{}", self.op.instruction.mnemonic()),
+            false => self.op.instruction.mnemonic(),
+        };
+
+        match self.operand {
+            Operand::Truncated => format!(
+                "{} <truncated: {} of {} bytes>",
+                mnemonic,
+                self.bytes.len(),
+                self.op.bytes
+            ),
+            _ => mnemonic + &format_operand(self, name),
+        }
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:04X}  {:<8}  {}",
+            self.addr,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+// where an instruction's operand ends up pointing given the
+// current registers and memory, e.g. for `LDA ($20),Y`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Effective {
+    pub addr: u16,
+    // None for jumps, which only care about the address
+    pub value: Option<u8>,
+}
+
+impl DisasmLine {
+    // None for modes with nothing to resolve (implied, immediate,
+    // branches, plain absolute jumps) and for truncated lines
+    pub fn effective(&self, cpu: &Cpu) -> Option<Effective> {
+        let bus = &*cpu.bus;
+        let regs = &cpu.regs;
+        let word = |lo: u16, hi: u16| u16::from_le_bytes([bus.peek(lo), bus.peek(hi)]);
+        let zp_word = |ptr: u8| word(ptr as u16, ptr.wrapping_add(1) as u16);
+
+        let addr = match (self.op.address_mode, self.operand) {
+            (AddrMode::Zp, Operand::Byte(arg)) => arg as u16,
+            (AddrMode::Zpx, Operand::Byte(arg)) => arg.wrapping_add(regs.x) as u16,
+            (AddrMode::Zpy, Operand::Byte(arg)) => arg.wrapping_add(regs.y) as u16,
+            (AddrMode::Abs, Operand::Word(arg)) => arg,
+            (AddrMode::Absx, Operand::Word(arg)) => arg.wrapping_add(regs.x as u16),
+            (AddrMode::Absy, Operand::Word(arg)) => arg.wrapping_add(regs.y as u16),
+            (AddrMode::Indx, Operand::Byte(arg)) => zp_word(arg.wrapping_add(regs.x)),
+            (AddrMode::Indy, Operand::Byte(arg)) => zp_word(arg).wrapping_add(regs.y as u16),
+            (AddrMode::Zpi, Operand::Byte(arg)) => zp_word(arg),
+            (AddrMode::Zpr, Operand::Word(arg)) => arg & 0x00FF,
+            (AddrMode::Ind, Operand::Word(arg)) => {
+                // same page-wrapping bug as `Cpu::ind`
+                let next = if cpu.variant.is_cmos() {
+                    arg.wrapping_add(1)
+                } else {
+                    (arg & 0xFF00) | (arg.wrapping_add(1) & 0x00FF)
+                };
+                word(arg, next)
+            }
+            (AddrMode::Iabsx, Operand::Word(arg)) => {
+                let ptr = arg.wrapping_add(regs.x as u16);
+                word(ptr, ptr.wrapping_add(1))
+            }
+            _ => return None,
+        };
+
+        let jump = matches!(self.op.instruction, Instruction::Jmp | Instruction::Jsr);
+        if jump && self.op.address_mode == AddrMode::Abs {
+            return None;
+        }
+        let value = if jump { None } else { Some(bus.peek(addr)) };
+
+        Some(Effective { addr, value })
+    }
+
+    // the text followed by the effective address and the value
+    // there, e.g. `LDA ($20),Y @ $0634 = #$55`
+    pub fn annotated(&self, cpu: &Cpu) -> String {
+        let Some(effective) = self.effective(cpu) else {
+            return self.text.clone();
+        };
+
+        let mut text = self.text.clone();
+        // plain zero page and absolute operands already are the address
+        if !matches!(
+            self.op.address_mode,
+            AddrMode::Zp | AddrMode::Abs | AddrMode::Zpr
+        ) {
+            text += &format!(" @ ${:04X}", effective.addr);
+        }
+        if let Some(value) = effective.value {
+            text += &format!(" = #${:02X}", value);
+        }
+        text
+    }
+}
+
+// `name` gets the chance to replace each address in the operand
+// with a symbol, e.g. turning `JSR $8123` into `JSR sub_8123`
+fn format_operand(line: &DisasmLine, name: &dyn Fn(u16) -> Option<String>) -> String {
+    let arg = match line.operand {
+        Operand::None => 0,
+        Operand::Byte(arg) => arg as u16,
+        Operand::Word(arg) => arg,
+        Operand::Truncated => return String::new(),
+    };
+    let zp = || name(arg).unwrap_or_else(|| format!("${:02X}", arg));
+    let abs = || name(arg).unwrap_or_else(|| format!("${:04X}", arg));
+    let target = || {
+        let target = line.target().unwrap_or_default();
+        name(target).unwrap_or_else(|| format!("${:04X}", target))
     };
 
-    format!("{} {}", instr_string, arg_string)
+    match line.op.address_mode {
+        AddrMode::Imp => String::new(),
+        AddrMode::Acc => String::from(" A"),
+        AddrMode::Imm => format!(" #${:02X}", arg),
+        AddrMode::Rel => format!(" {}", target()),
+        AddrMode::Zp => format!(" {}", zp()),
+        AddrMode::Zpx => format!(" {},X", zp()),
+        AddrMode::Zpy => format!(" {},Y", zp()),
+        AddrMode::Ind => format!(" ({})", abs()),
+        AddrMode::Abs => format!(" {}", abs()),
+        AddrMode::Absx => format!(" {},X", abs()),
+        AddrMode::Absy => format!(" {},Y", abs()),
+        AddrMode::Indx => format!(" ({},X)", zp()),
+        AddrMode::Indy => format!(" ({}),Y", zp()),
+        AddrMode::Zpi => format!(" ({})", zp()),
+        AddrMode::Iabsx => format!(" ({},X)", abs()),
+        AddrMode::Zpr => {
+            let arg = arg & 0x00FF;
+            let zp = name(arg).unwrap_or_else(|| format!("${:02X}", arg));
+            format!(" {},{}", zp, target())
+        }
+    }
 }
 
 impl<'a> Disassembler<'a> {
-    pub fn new(data: Vec<u8>, optable: &'a Vec<Op>) -> Disassembler {
-        Disassembler { data, optable }
+    pub fn new(data: Vec<u8>, optable: &'a [Op]) -> Disassembler<'a> {
+        Disassembler::with_origin(data, optable, 0)
     }
 
-    pub fn read(&mut self, offset: usize) {
-        let mut it = self.data.iter().enumerate();
-        while let Some((pc, &byte)) = it.next() {
-            // TODO: maybe do this more elegantly?
-            if pc < offset {
-                continue;
+    pub fn with_origin(data: Vec<u8>, optable: &'a [Op], origin: u16) -> Disassembler<'a> {
+        Disassembler::with_memory(Image { data, origin }, optable)
+    }
+}
+
+impl<'a, M: Memory> Disassembler<'a, M> {
+    // e.g. `Disassembler::with_memory(&*cpu.bus, optable)` to look
+    // at memory as the CPU currently sees it
+    pub fn with_memory(memory: M, optable: &'a [Op]) -> Disassembler<'a, M> {
+        Disassembler {
+            memory,
+            optable,
+            symbols: None,
+        }
+    }
+
+    fn byte_at(&self, addr: u32) -> Option<u8> {
+        if !self.memory.span().contains(&addr) {
+            return None;
+        }
+        self.memory.peek(addr as u16)
+    }
+
+    // decodes the instruction at `addr`, or None if `addr`
+    // is outside the data
+    pub fn decode(&self, addr: u16) -> Option<DisasmLine> {
+        let opcode = self.byte_at(addr as u32)?;
+        let op = self.optable[opcode as usize].info;
+
+        let mut bytes = vec![opcode];
+        for i in 1..op.bytes as u32 {
+            match self.byte_at(addr as u32 + i) {
+                Some(byte) => bytes.push(byte),
+                None => break,
             }
+        }
 
-            let op = self.optable.get(byte as usize).unwrap();
-            let opcode = byte;
-            let arg_count = get_arg_count(op);
-            let arg: u16 = match arg_count {
-                0 => 0,
-                1 => {
-                    let (_, &arg) = it.next().expect("Unexpected EOF when parsing");
-                    arg as u16
-                }
+        let operand = match (op.bytes, bytes.len()) {
+            (1, _) => Operand::None,
+            (2, 2) => Operand::Byte(bytes[1]),
+            (3, 3) => Operand::Word(u16::from_le_bytes([bytes[1], bytes[2]])),
+            _ => Operand::Truncated,
+        };
 
-                2 => {
-                    let (_, &arg_lo) = it.next().expect("Unexpected EOF when parsing");
+        let mut line = DisasmLine {
+            addr,
+            bytes,
+            op,
+            operand,
+            text: String::new(),
+        };
+        line.text = line.format(&|addr| self.symbols?.describe(addr));
+        Some(line)
+    }
 
-                    let (_, &arg_hi) = it.next().expect("Unexpected EOF when parsing");
+    // every instruction from the start of the data
+    pub fn lines(&self) -> Lines<'_, 'a, M> {
+        self.range(..)
+    }
 
-                    ((arg_hi as u16) << 8) + (arg_lo as u16)
-                }
+    // the instructions starting inside `range`; the last one may
+    // run past its end, but never past the end of the data
+    pub fn range<R: RangeBounds<u16>>(&self, range: R) -> Lines<'_, 'a, M> {
+        let span = self.memory.span();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start as u32,
+            Bound::Excluded(&start) => start as u32 + 1,
+            Bound::Unbounded => span.start,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end as u32 + 1,
+            Bound::Excluded(&end) => end as u32,
+            Bound::Unbounded => span.end,
+        };
+
+        Lines {
+            disassembler: self,
+            addr: start.max(span.start),
+            end: end.min(span.end),
+        }
+    }
+
+    // up to `before` instructions leading up to `addr`, the one
+    // at `addr` and `after` more, e.g. the code around PC
+    pub fn around(&self, addr: u16, before: usize, after: usize) -> Vec<DisasmLine> {
+        let mut lines = self.leading_up_to(addr, before);
+        lines.extend(self.range(addr..).take(after + 1));
+        lines
+    }
 
-                _ => {
-                    panic!("Parsing error: invalid arg_count");
-                }
-            };
+    // code can't be decoded backwards, so this tries every start
+    // far enough back and keeps the earliest one whose instructions
+    // line up with `addr`; the longer the run that syncs, the more
+    // likely it is to be real code
+    fn leading_up_to(&self, addr: u16, count: usize) -> Vec<DisasmLine> {
+        let span = self.memory.span();
+        let lowest = (addr as u32)
+            .saturating_sub(count as u32 * 3)
+            .max(span.start);
 
-            let line = get_op_fmt_string(op, arg);
-            println!("{:#06X}: {}", pc, line);
+        for start in lowest..addr as u32 {
+            let lines: Vec<DisasmLine> = self.range(start as u16..addr).collect();
+            let synced = lines
+                .last()
+                .is_some_and(|line| line.addr as u32 + line.bytes.len() as u32 == addr as u32);
+            if synced {
+                let skip = lines.len().saturating_sub(count);
+                return lines.into_iter().skip(skip).collect();
+            }
+        }
+
+        Vec::new()
+    }
+}
+
+pub struct Lines<'d, 'a, M> {
+    disassembler: &'d Disassembler<'a, M>,
+    // u32 so the walk can step past $FFFF and stop
+    addr: u32,
+    end: u32,
+}
+
+impl<M: Memory> Iterator for Lines<'_, '_, M> {
+    type Item = DisasmLine;
+
+    fn next(&mut self) -> Option<DisasmLine> {
+        if self.addr >= self.end {
+            return None;
         }
+
+        let line = self.disassembler.decode(self.addr as u16)?;
+        self.addr += line.bytes.len() as u32;
+        Some(line)
     }
 }
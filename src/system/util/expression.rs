@@ -0,0 +1,372 @@
+use std::fmt;
+
+// Arithmetic expressions as used by the assembler and the
+// debugger: numbers, symbols, `*` for the program counter and
+// C-like operators, plus `<` and `>` for the low and high byte
+// of a word. The debugger also takes `[addr]` for the byte in
+// memory at `addr`, and a `#` before a value that changes
+// nothing, so `a > #$10` reads like code.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    // the address of the current statement
+    Pc,
+    // the byte at an address
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    LogicalNot,
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Mod,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Xor,
+    Or,
+    LogicalAnd,
+    LogicalOr,
+}
+
+// from the loosest binding to the tightest
+const PRECEDENCE: [&[(&str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::LogicalOr)],
+    &[("&&", BinaryOp::LogicalAnd)],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Mod),
+    ],
+];
+
+// `offset` is the byte offset into the parsed text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    Undefined(String),
+    NoPc,
+    NoMemory,
+    DivideByZero,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Undefined(name) => write!(f, "undefined symbol `{}`", name),
+            EvalError::NoPc => write!(f, "`*` has no value here"),
+            EvalError::NoMemory => write!(f, "memory can't be read here"),
+            EvalError::DivideByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+// what symbols, `*` and `[addr]` evaluate to
+pub trait Context {
+    fn symbol(&self, name: &str) -> Option<i64>;
+
+    fn pc(&self) -> Option<i64> {
+        None
+    }
+
+    fn memory(&self, _addr: i64) -> Option<i64> {
+        None
+    }
+}
+
+pub fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@'
+}
+
+pub fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// parses the longest expression at the start of `input`,
+// returning it with the number of bytes it took up
+pub fn parse_prefix(input: &str) -> Result<(Expr, usize), SyntaxError> {
+    let mut parser = Parser {
+        input,
+        pos: 0,
+        debugger: false,
+    };
+    let expr = parser.binary(0)?;
+    parser.skip_spaces();
+    Ok((expr, parser.pos))
+}
+
+// parses `input` as a single expression
+pub fn parse(input: &str) -> Result<Expr, SyntaxError> {
+    whole(Parser {
+        input,
+        pos: 0,
+        debugger: false,
+    })
+}
+
+// like `parse`, but with `[addr]` and `#` allowed
+pub fn parse_debugger(input: &str) -> Result<Expr, SyntaxError> {
+    whole(Parser {
+        input,
+        pos: 0,
+        debugger: true,
+    })
+}
+
+fn whole(mut parser: Parser) -> Result<Expr, SyntaxError> {
+    let expr = parser.binary(0)?;
+    parser.skip_spaces();
+    if parser.pos < parser.input.len() {
+        return parser.error(format!("unexpected `{}`", parser.rest()));
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    // whether `[addr]` and `#` are allowed
+    debugger: bool,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, SyntaxError> {
+        Err(SyntaxError {
+            offset: self.pos,
+            message,
+        })
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            return true;
+        }
+        false
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, SyntaxError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        'operators: loop {
+            self.skip_spaces();
+            for &(token, op) in PRECEDENCE[level] {
+                // keep `<` from eating the first half of `<<` or `<=`
+                // and `|`/`&` from eating `||`/`&&`
+                let longer = PRECEDENCE
+                    .iter()
+                    .flat_map(|ops| ops.iter())
+                    .any(|&(other, _)| {
+                        other.len() > token.len()
+                            && other.starts_with(token)
+                            && self.rest().starts_with(other)
+                    });
+                if !longer && self.rest().starts_with(token) {
+                    self.pos += token.len();
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, SyntaxError> {
+        self.skip_spaces();
+        let op = match self.rest().chars().next() {
+            Some('-') => UnaryOp::Neg,
+            Some('~') => UnaryOp::Not,
+            Some('!') => UnaryOp::LogicalNot,
+            Some('<') => UnaryOp::Low,
+            Some('>') => UnaryOp::High,
+            Some('#') if self.debugger => {
+                self.pos += 1;
+                return self.unary();
+            }
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, SyntaxError> {
+        self.skip_spaces();
+        let rest = self.rest();
+        let Some(c) = rest.chars().next() else {
+            return self.error(String::from("expected an expression"));
+        };
+
+        if c == '(' {
+            self.pos += 1;
+            let expr = self.binary(0)?;
+            if !self.eat(")") {
+                return self.error(String::from("expected `)`"));
+            }
+            return Ok(expr);
+        }
+        if c == '[' && self.debugger {
+            self.pos += 1;
+            let expr = self.binary(0)?;
+            if !self.eat("]") {
+                return self.error(String::from("expected `]`"));
+            }
+            return Ok(Expr::Memory(Box::new(expr)));
+        }
+        if c == '*' {
+            self.pos += 1;
+            return Ok(Expr::Pc);
+        }
+        if c == '\'' {
+            let mut chars = rest[1..].chars();
+            return match (chars.next(), chars.next()) {
+                (Some(value), Some('\'')) if value.is_ascii() => {
+                    self.pos += 3;
+                    Ok(Expr::Number(value as i64))
+                }
+                _ => self.error(String::from("expected a character like 'A'")),
+            };
+        }
+        if is_ident_start(c) {
+            let len = 1 + rest[1..]
+                .find(|c: char| !is_ident_char(c))
+                .unwrap_or(rest.len() - 1);
+            let name = String::from(&rest[..len]);
+            self.pos += len;
+            return Ok(Expr::Symbol(name));
+        }
+
+        let (radix, skip) = if rest.starts_with('$') {
+            (16, 1)
+        } else if rest.starts_with("0x") || rest.starts_with("0X") {
+            (16, 2)
+        } else if rest.starts_with('%') {
+            (2, 1)
+        } else if c.is_ascii_digit() {
+            (10, 0)
+        } else {
+            return self.error(format!("unexpected `{}`", c));
+        };
+        let digits = &rest[skip..];
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        match i64::from_str_radix(&digits[..len], radix) {
+            Ok(value) => {
+                self.pos += skip + len;
+                Ok(Expr::Number(value))
+            }
+            Err(_) => self.error(format!("invalid number `{}`", &rest[..skip + len])),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, context: &dyn Context) -> Result<i64, EvalError> {
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Symbol(name) => context
+                .symbol(name)
+                .ok_or_else(|| EvalError::Undefined(name.clone()))?,
+            Expr::Pc => context.pc().ok_or(EvalError::NoPc)?,
+            Expr::Memory(addr) => {
+                let addr = addr.eval(context)?;
+                context.memory(addr).ok_or(EvalError::NoMemory)?
+            }
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(context)?;
+                match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                    UnaryOp::LogicalNot => (value == 0) as i64,
+                    UnaryOp::Low => value & 0xFF,
+                    UnaryOp::High => (value >> 8) & 0xFF,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(context)?;
+                let rhs = rhs.eval(context)?;
+                match op {
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Mod if rhs == 0 => {
+                        return Err(EvalError::DivideByZero)
+                    }
+                    BinaryOp::Div => lhs.wrapping_div(rhs),
+                    BinaryOp::Mod => lhs.wrapping_rem(rhs),
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BinaryOp::Lt => (lhs < rhs) as i64,
+                    BinaryOp::Gt => (lhs > rhs) as i64,
+                    BinaryOp::Le => (lhs <= rhs) as i64,
+                    BinaryOp::Ge => (lhs >= rhs) as i64,
+                    BinaryOp::Eq => (lhs == rhs) as i64,
+                    BinaryOp::Ne => (lhs != rhs) as i64,
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::LogicalAnd => (lhs != 0 && rhs != 0) as i64,
+                    BinaryOp::LogicalOr => (lhs != 0 || rhs != 0) as i64,
+                }
+            }
+        })
+    }
+}
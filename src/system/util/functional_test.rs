@@ -0,0 +1,92 @@
+use crate::bus::Ram;
+use crate::system::cpu::{Cpu, Variant};
+
+// Klaus Dormann's 6502 functional test: the image covers the whole
+// address space, execution starts at $0400 and every failed check
+// ends in a "JMP *" (or branch-to-self) trap. The test currently
+// running is kept at $0200, and reaching the success trap means
+// every test passed.
+pub const LOAD_ADDR: u16 = 0x0000;
+pub const START_ADDR: u16 = 0x0400;
+pub const SUCCESS_ADDR: u16 = 0x3469;
+pub const TEST_CASE_ADDR: u16 = 0x0200;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Outcome {
+    Passed,
+    // stuck in a trap other than the success one
+    Failed { test_case: u8, trap: u16 },
+    // didn't reach any trap within the instruction budget
+    Timeout { test_case: u8, pc: u16 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+pub struct FunctionalTest {
+    pub image: Vec<u8>,
+    pub load_addr: u16,
+    pub start_addr: u16,
+    pub success_addr: u16,
+    pub max_instructions: u64,
+    pub variant: Variant,
+}
+
+impl FunctionalTest {
+    pub fn new(image: Vec<u8>) -> FunctionalTest {
+        FunctionalTest {
+            image,
+            load_addr: LOAD_ADDR,
+            start_addr: START_ADDR,
+            success_addr: SUCCESS_ADDR,
+            max_instructions: 100_000_000,
+            variant: Variant::Nmos6502,
+        }
+    }
+
+    // runs the image until it traps, returning the CPU so the
+    // caller can inspect its state after a failure
+    pub fn run(&self) -> (Report, Cpu) {
+        let mut ram = Ram::new();
+        ram.load(self.load_addr, &self.image);
+
+        let mut cpu = Cpu::with_variant(self.variant, Box::new(ram));
+        cpu.regs.s = 0xFF;
+        cpu.regs.pc = self.start_addr;
+
+        let mut instructions = 0;
+        let outcome = loop {
+            if instructions == self.max_instructions {
+                break Outcome::Timeout {
+                    test_case: cpu.bus.peek(TEST_CASE_ADDR),
+                    pc: cpu.regs.pc,
+                };
+            }
+
+            let pc = cpu.regs.pc;
+            cpu.step();
+            instructions += 1;
+
+            if cpu.regs.pc == pc {
+                if pc == self.success_addr {
+                    break Outcome::Passed;
+                }
+                break Outcome::Failed {
+                    test_case: cpu.bus.peek(TEST_CASE_ADDR),
+                    trap: pc,
+                };
+            }
+        };
+
+        let report = Report {
+            outcome,
+            instructions,
+            cycles: cpu.cycles,
+        };
+        (report, cpu)
+    }
+}
@@ -0,0 +1,525 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use crate::bus::WatchKind;
+use crate::system::util::debugger::{Debugger, Stop, CHUNK};
+
+// A server for gdb's remote serial protocol, so gdb (or anything
+// else that speaks it) can drive a `Debugger` over a socket. The
+// registers are described to the client by `TARGET_XML`, in the
+// order `g` packets carry them: A, X, Y, S, P and the 16-bit PC.
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.vanilla.m6502.core">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="s" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// the largest packet we take, as told to the client
+const PACKET_SIZE: usize = 0x1000;
+
+// Ctrl-C from the client, sent outside of any packet
+const INTERRUPT: u8 = 0x03;
+
+// a connection the server can check for an interrupt on while
+// the program runs
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+pub struct GdbServer {
+    pub debugger: Debugger,
+    // the client asked for no `+` acks
+    no_ack: bool,
+    // the reply to `?`
+    last_stop: String,
+    breakpoints: HashMap<u16, usize>,
+    // watchpoint ids by Z packet type, address and length
+    watchpoints: HashMap<(u8, u16, u16), Vec<usize>>,
+    // whether a watchpoint id is half of an access (`Z4`) one
+    access: Vec<usize>,
+    // bytes read ahead while looking for an interrupt
+    pending: VecDeque<u8>,
+}
+
+// what handling a packet leads to
+enum Reply {
+    Packet(String),
+    // `D`, which still gets an `OK`
+    Detach,
+    // `k`, which gets nothing
+    Kill,
+}
+
+impl GdbServer {
+    pub fn new(debugger: Debugger) -> GdbServer {
+        GdbServer {
+            debugger,
+            no_ack: false,
+            last_stop: String::from("S05"),
+            breakpoints: HashMap::new(),
+            watchpoints: HashMap::new(),
+            access: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    // talks to one client until it detaches, kills the target or
+    // hangs up
+    pub fn serve(&mut self, stream: &mut dyn Connection) -> io::Result<()> {
+        self.no_ack = false;
+        self.pending.clear();
+
+        while let Some(packet) = self.read_packet(stream)? {
+            match self.packet(&packet, stream)? {
+                Reply::Packet(reply) => self.write_packet(stream, &reply)?,
+                Reply::Detach => {
+                    self.write_packet(stream, "OK")?;
+                    break;
+                }
+                Reply::Kill => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn next_byte(&mut self, stream: &mut dyn Connection) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0];
+        match stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // the data of the next packet with a good checksum, or None
+    // once the client is gone
+    fn read_packet(&mut self, stream: &mut dyn Connection) -> io::Result<Option<String>> {
+        loop {
+            // acks, stray interrupts and noise between packets
+            match self.next_byte(stream)? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.next_byte(stream)? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in &mut checksum {
+                match self.next_byte(stream)? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            if expected != Some(sum) {
+                if !self.no_ack {
+                    stream.write_all(b"-")?;
+                }
+                continue;
+            }
+            if !self.no_ack {
+                stream.write_all(b"+")?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+        }
+    }
+
+    fn write_packet(&mut self, stream: &mut dyn Connection, data: &str) -> io::Result<()> {
+        let data = escape(data.as_bytes());
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let mut packet = vec![b'$'];
+        packet.extend(&data);
+        packet.extend(format!("#{:02x}", sum).bytes());
+
+        loop {
+            stream.write_all(&packet)?;
+            stream.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // resend on `-`, anything but an ack is left for later
+            match self.next_byte(stream)? {
+                Some(b'+') | None => return Ok(()),
+                Some(b'-') => continue,
+                Some(byte) => {
+                    self.pending.push_front(byte);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn packet(&mut self, packet: &str, stream: &mut dyn Connection) -> io::Result<Reply> {
+        let reply = |text: &str| Ok(Reply::Packet(String::from(text)));
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        match command {
+            "?" => reply(&self.last_stop.clone()),
+            "g" => reply(&self.read_registers()),
+            "G" => match self.write_registers(args) {
+                Some(()) => reply("OK"),
+                None => reply("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| self.register(n))
+            {
+                Some(value) => reply(&value),
+                None => reply("E01"),
+            },
+            "P" => match self.set_register(args) {
+                Some(()) => reply("OK"),
+                None => reply("E01"),
+            },
+            "m" => match self.read_memory(args) {
+                Some(hex) => reply(&hex),
+                None => reply("E01"),
+            },
+            "M" => match self.write_memory(args) {
+                Some(()) => reply("OK"),
+                None => reply("E01"),
+            },
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(addr) => self.debugger.cpu.regs.pc = addr,
+                        Err(_) => return reply("E01"),
+                    }
+                }
+                let stop = self.resume(command == "s", stream)?;
+                reply(&stop)
+            }
+            "v" if args == "Cont?" => reply("vCont;c;C;s;S"),
+            "v" if args.starts_with("Cont;") => {
+                // one thread, so the first action is the only one
+                let step = args[5..].starts_with(['s', 'S']);
+                let stop = self.resume(step, stream)?;
+                reply(&stop)
+            }
+            "Z" | "z" => match self.set_point(command == "Z", args) {
+                Some(true) => reply("OK"),
+                Some(false) => reply(""),
+                None => reply("E01"),
+            },
+            "q" | "Q" => reply(&self.query(packet)),
+            // one thread, whichever one gets picked
+            "H" => reply("OK"),
+            "T" => reply("OK"),
+            "D" => Ok(Reply::Detach),
+            "k" => Ok(Reply::Kill),
+            _ => reply(""),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            );
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = args.split_once(',') else {
+                return String::from("E01");
+            };
+            let (Ok(offset), Ok(length)) = (
+                usize::from_str_radix(offset, 16),
+                usize::from_str_radix(length, 16),
+            ) else {
+                return String::from("E01");
+            };
+            let chunk = TARGET_XML.get(offset..).unwrap_or("");
+            let end = chunk.len().min(length);
+            // `l` for the last chunk, `m` for more to come
+            let more = if end < chunk.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, &chunk[..end]);
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            "qOffsets" => String::from("Text=0;Data=0;Bss=0"),
+            "qSymbol::" => String::from("OK"),
+            _ => String::new(),
+        }
+    }
+
+    // runs one instruction or until something stops it, returning
+    // the stop reply
+    fn resume(&mut self, step: bool, stream: &mut dyn Connection) -> io::Result<String> {
+        let stop = if step {
+            match self.debugger.run(1, &|_| false) {
+                Stop::Limit => Stop::Done,
+                stop => stop,
+            }
+        } else {
+            loop {
+                let stop = self.debugger.run(CHUNK, &|_| false);
+                if stop != Stop::Limit {
+                    break stop;
+                }
+                if self.interrupted(stream)? {
+                    self.last_stop = String::from("S02");
+                    return Ok(self.last_stop.clone());
+                }
+            }
+        };
+
+        self.last_stop = match stop {
+            Stop::Watch { hits, .. } => {
+                let hit = hits[0];
+                let kind = match hit.kind {
+                    _ if self.access.contains(&hit.id) => "awatch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Write | WatchKind::Change => "watch",
+                };
+                format!("T05{}:{:04x};", kind, hit.addr)
+            }
+            // SIGILL
+            Stop::Jammed => String::from("S04"),
+            // SIGTRAP
+            _ => String::from("S05"),
+        };
+        Ok(self.last_stop.clone())
+    }
+
+    // whether the client sent an interrupt, without waiting for one
+    fn interrupted(&mut self, stream: &mut dyn Connection) -> io::Result<bool> {
+        stream.set_nonblocking(true)?;
+        let mut buffer = [0; 64];
+        let read = stream.read(&mut buffer);
+        stream.set_nonblocking(false)?;
+
+        match read {
+            Ok(0) => Ok(false),
+            Ok(n) => {
+                let interrupt = buffer[..n].contains(&INTERRUPT);
+                self.pending
+                    .extend(buffer[..n].iter().filter(|&&b| b != INTERRUPT));
+                Ok(interrupt)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn register(&self, n: usize) -> Option<String> {
+        let regs = &self.debugger.cpu.regs;
+        Some(match n {
+            0 => format!("{:02x}", regs.a),
+            1 => format!("{:02x}", regs.x),
+            2 => format!("{:02x}", regs.y),
+            3 => format!("{:02x}", regs.s),
+            4 => format!("{:02x}", regs.p),
+            5 => hex(&regs.pc.to_le_bytes()),
+            _ => return None,
+        })
+    }
+
+    fn read_registers(&self) -> String {
+        (0..6).filter_map(|n| self.register(n)).collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<()> {
+        let bytes = unhex(args)?;
+        let [a, x, y, s, p, pc_lo, pc_hi] = bytes[..] else {
+            return None;
+        };
+        let regs = &mut self.debugger.cpu.regs;
+        (regs.a, regs.x, regs.y, regs.s, regs.p) = (a, x, y, s, p);
+        regs.pc = u16::from_le_bytes([pc_lo, pc_hi]);
+        Some(())
+    }
+
+    // `n=value`, with the value in target byte order
+    fn set_register(&mut self, args: &str) -> Option<()> {
+        let (n, value) = args.split_once('=')?;
+        let bytes = unhex(value)?;
+        let regs = &mut self.debugger.cpu.regs;
+        match (usize::from_str_radix(n, 16).ok()?, bytes.as_slice()) {
+            (0, &[value]) => regs.a = value,
+            (1, &[value]) => regs.x = value,
+            (2, &[value]) => regs.y = value,
+            (3, &[value]) => regs.s = value,
+            (4, &[value]) => regs.p = value,
+            (5, &[lo, hi]) => regs.pc = u16::from_le_bytes([lo, hi]),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // `addr,length`, read without side effects
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, length) = parse_range(args)?;
+        let bus = &self.debugger.cpu.bus;
+        let bytes: Vec<u8> = (0..length)
+            .map(|i| bus.peek(addr.wrapping_add(i as u16)))
+            .collect();
+        Some(hex(&bytes))
+    }
+
+    // `addr,length:bytes`
+    fn write_memory(&mut self, args: &str) -> Option<()> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, length) = parse_range(range)?;
+        let bytes = unhex(data)?;
+        if bytes.len() != length {
+            return None;
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.debugger
+                .cpu
+                .bus
+                .write(addr.wrapping_add(i as u16), byte);
+        }
+        Some(())
+    }
+
+    // `type,addr,kind` for Z and z packets: None for a malformed
+    // packet, false for an unsupported type
+    fn set_point(&mut self, insert: bool, args: &str) -> Option<bool> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?.parse::<u8>().ok()?;
+        let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let length = u16::from_str_radix(fields.next()?, 16).ok()?;
+
+        match (kind, insert) {
+            // software and hardware breakpoints are all the same here
+            (0 | 1, true) => {
+                if !self.breakpoints.contains_key(&addr) {
+                    let id = self.debugger.add_breakpoint(Some(addr), None, false).ok()?;
+                    self.breakpoints.insert(addr, id);
+                }
+            }
+            (0 | 1, false) => {
+                if let Some(id) = self.breakpoints.remove(&addr) {
+                    self.debugger.delete_breakpoint(id).ok()?;
+                }
+            }
+            (2..=4, true) => {
+                // a repeated insert mustn't leave watches behind
+                // that a single remove can't reach
+                if self.watchpoints.contains_key(&(kind, addr, length)) {
+                    return Some(true);
+                }
+                let range = addr..=addr.saturating_add(length.max(1) - 1);
+                let kinds: &[WatchKind] = match kind {
+                    2 => &[WatchKind::Write],
+                    3 => &[WatchKind::Read],
+                    _ => &[WatchKind::Read, WatchKind::Write],
+                };
+                let mut watches = self.debugger.watches.borrow_mut();
+                let ids: Vec<usize> = kinds
+                    .iter()
+                    .map(|&watch| watches.add(range.clone(), watch))
+                    .collect();
+                if kind == 4 {
+                    self.access.extend(&ids);
+                }
+                self.watchpoints.insert((kind, addr, length), ids);
+            }
+            (2..=4, false) => {
+                let ids = self.watchpoints.remove(&(kind, addr, length))?;
+                let mut watches = self.debugger.watches.borrow_mut();
+                for id in ids {
+                    watches.remove(id);
+                    self.access.retain(|&access| access != id);
+                }
+            }
+            _ => return Some(false),
+        }
+        Some(true)
+    }
+}
+
+// `addr,length` in hex
+fn parse_range(args: &str) -> Option<(u16, usize)> {
+    let (addr, length) = args.split_once(',')?;
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+    (length <= PACKET_SIZE).then_some((addr, length))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+// `#`, `$`, `}` and `*` can't appear as they are in a packet,
+// so they're sent as `}` followed by the byte XOR $20
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &byte in data {
+        if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+            out.extend([b'}', byte ^ 0x20]);
+        } else {
+            out.push(byte);
+        }
+    }
+    out
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => out.extend(bytes.next().map(|b| b ^ 0x20)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
@@ -0,0 +1,129 @@
+// Rules of the instruction set files that build.rs needs too; it
+// includes this file by path, so it can only use std.
+
+// the flags column lists the P bits from C up to N, skipping
+// the unused one; uppercase means the instruction changes it
+const FLAG_BITS: [(char, u8); 7] = [
+    ('C', 0),
+    ('Z', 1),
+    ('I', 2),
+    ('D', 3),
+    ('B', 4),
+    ('V', 6),
+    ('N', 7),
+];
+
+// parses e.g. "CZidbvN" into a mask of the affected P bits
+pub fn parse_flags(value: &str) -> Option<u8> {
+    if value.chars().count() != FLAG_BITS.len() {
+        return None;
+    }
+
+    let mut mask = 0;
+    for (c, (name, bit)) in value.chars().zip(FLAG_BITS) {
+        if c == name {
+            mask |= 1 << bit;
+        } else if c != name.to_ascii_lowercase() {
+            return None;
+        }
+    }
+    Some(mask)
+}
+
+// operand bytes following the opcode for the addressing mode
+// named e.g. "ABSX", None for a name the files don't use
+pub fn operand_bytes(addr_mode: &str) -> Option<u8> {
+    match addr_mode {
+        "IMP" | "ACC" => Some(0),
+        "IMM" | "ZP" | "ZPX" | "ZPY" | "ZPI" | "INDX" | "INDY" | "REL" => Some(1),
+        "ABS" | "ABSX" | "ABSY" | "IND" | "IABSX" | "ZPR" => Some(2),
+        _ => None,
+    }
+}
+
+// columns of an instruction set file
+pub const FIELDS: [&str; 6] = [
+    "opcode",
+    "mnemonic",
+    "addressing mode",
+    "bytes",
+    "cycles",
+    "flags",
+];
+
+// a line of an instruction set file, checked against everything
+// but the mnemonic, which only the caller knows the meaning of
+pub struct Row {
+    pub opcode: u8,
+    pub mnemonic: String,
+    // a leading * marks opcodes outside the official set
+    pub undocumented: bool,
+    pub addr_mode: String,
+    pub bytes: u8,
+    // branches list their cycles as "2/3", the extra
+    // cycles are accounted for by the CPU itself
+    pub cycles: u8,
+    pub flags: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowError {
+    FieldCount(usize),
+    InvalidOpcode(String),
+    InvalidBytes(String),
+    // the addressing mode and the length given for it
+    BytesMismatch(String, u8),
+    InvalidCycles(String),
+    InvalidFlags(String),
+    UnknownAddrMode(String),
+}
+
+// blank lines and the header
+pub fn is_blank(line: &str) -> bool {
+    line.trim().is_empty() || line.starts_with(FIELDS[0])
+}
+
+// on error, also returns the index of the offending field
+pub fn parse_row(line: &str) -> Result<Row, (Option<usize>, RowError)> {
+    let tokens: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [opcode, mnemonic, addr_mode, bytes, cycles, flags] = tokens.as_slice() else {
+        return Err((None, RowError::FieldCount(tokens.len())));
+    };
+
+    let opcode = match opcode.strip_prefix("0x").map(|n| u8::from_str_radix(n, 16)) {
+        Some(Ok(n)) => n,
+        _ => return Err((Some(0), RowError::InvalidOpcode(opcode.to_string()))),
+    };
+    let (mnemonic, undocumented) = match mnemonic.strip_prefix('*') {
+        Some(mnemonic) => (mnemonic, true),
+        None => (*mnemonic, false),
+    };
+    let Ok(cycles) = cycles.split('/').next().unwrap().parse() else {
+        return Err((Some(4), RowError::InvalidCycles(cycles.to_string())));
+    };
+    let Some(operand_bytes) = operand_bytes(addr_mode) else {
+        return Err((Some(2), RowError::UnknownAddrMode(addr_mode.to_string())));
+    };
+    let Ok(bytes) = bytes.parse() else {
+        return Err((Some(3), RowError::InvalidBytes(bytes.to_string())));
+    };
+    if bytes != 1 + operand_bytes {
+        return Err((
+            Some(3),
+            RowError::BytesMismatch(addr_mode.to_string(), bytes),
+        ));
+    }
+    let Some(flags) = parse_flags(flags) else {
+        return Err((Some(5), RowError::InvalidFlags(flags.to_string())));
+    };
+
+    Ok(Row {
+        opcode,
+        mnemonic: mnemonic.to_string(),
+        undocumented,
+        addr_mode: addr_mode.to_string(),
+        bytes,
+        cycles,
+        flags,
+    })
+}
@@ -1,29 +1,109 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::usize;
 
 use crate::system::cpu::{AddrMode, Cpu, Instruction, Op, OpInfo};
+use crate::system::util::instr_set_format::{is_blank, parse_row, RowError, FIELDS};
+
+type AddrModeEntry = (AddrMode, fn(cpu: &mut Cpu));
+type InstrEntry = (Instruction, fn(cpu: &mut Cpu));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    Io(String),
+    FieldCount(usize),
+    InvalidOpcode(String),
+    InvalidBytes(String),
+    // the addressing mode implies a different length
+    BytesMismatch(AddrMode, u8),
+    InvalidCycles(String),
+    InvalidFlags(String),
+    UnknownInstruction(String),
+    UnknownAddrMode(String),
+    // the line the opcode was first defined on
+    DuplicateOpcode(u8, usize),
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::Io(e) => write!(f, "{}", e),
+            ParseErrorKind::FieldCount(n) => {
+                write!(f, "expected {} fields, found {}", FIELDS.len(), n)
+            }
+            ParseErrorKind::InvalidOpcode(s) => write!(f, "invalid opcode `{}`", s),
+            ParseErrorKind::InvalidBytes(s) => write!(f, "invalid byte count `{}`", s),
+            ParseErrorKind::BytesMismatch(mode, bytes) => write!(
+                f,
+                "{} instructions are {} bytes long, not {}",
+                mode.name(),
+                1 + mode.operand_bytes(),
+                bytes
+            ),
+            ParseErrorKind::InvalidCycles(s) => write!(f, "invalid cycle count `{}`", s),
+            ParseErrorKind::InvalidFlags(s) => {
+                write!(f, "invalid flags `{}`, expected e.g. `CZidbvN`", s)
+            }
+            ParseErrorKind::UnknownInstruction(s) => write!(f, "unknown instruction `{}`", s),
+            ParseErrorKind::UnknownAddrMode(s) => write!(f, "unknown addressing mode `{}`", s),
+            ParseErrorKind::DuplicateOpcode(opcode, line) => {
+                write!(f, "opcode {:#04x} already defined on line {}", opcode, line)
+            }
+        }
+    }
+}
+
+// line and column are 1-based, column 0 means the whole line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}", self.file, self.kind);
+        }
+
+        write!(
+            f,
+            "{}:{}:{}: {}\n    {}",
+            self.file, self.line, self.column, self.kind, self.text
+        )?;
+        if self.column > 0 {
+            write!(f, "\n    {}^", " ".repeat(self.column - 1))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ParseMode {
+    // any bad line fails the whole parse
+    #[default]
+    Strict,
+    // bad lines are skipped (duplicates overwrite the
+    // earlier definition) and reported in `warnings`
+    Lenient,
+}
 
 pub struct InstrSetParser {
-    pub addr_mode_map: HashMap<&'static str, (AddrMode, fn(cpu: &mut Cpu))>,
-    pub instr_map: HashMap<&'static str, (Instruction, fn(cpu: &mut Cpu))>,
-    pub optable: Vec<Op>,
+    pub addr_mode_map: HashMap<&'static str, AddrModeEntry>,
+    pub instr_map: HashMap<&'static str, InstrEntry>,
     pub filepath: String,
+    pub mode: ParseMode,
+    pub warnings: Vec<ParseError>,
 }
 
 impl InstrSetParser {
     pub fn new(filepath: &str) -> InstrSetParser {
-        let nop = Op {
-            info: OpInfo {
-                address_mode: AddrMode::Imp,
-                instruction: Instruction::Nop,
-            },
-
-            address_mode: Cpu::imp,
-            instruction: Cpu::nop,
-            cycles: 1,
-        };
-
         InstrSetParser {
             addr_mode_map: HashMap::from([
                 ("ACC", (AddrMode::Acc, Cpu::acc as fn(&mut Cpu))),
@@ -39,11 +119,35 @@ impl InstrSetParser {
                 ("INDX", (AddrMode::Indx, Cpu::indx)),
                 ("INDY", (AddrMode::Indy, Cpu::indy)),
                 ("IND", (AddrMode::Ind, Cpu::ind)),
+                ("ZPI", (AddrMode::Zpi, Cpu::zpi)),
+                ("IABSX", (AddrMode::Iabsx, Cpu::iabsx)),
+                ("ZPR", (AddrMode::Zpr, Cpu::zpr)),
             ]),
             instr_map: HashMap::from([
                 ("ADC", (Instruction::Adc, Cpu::adc as fn(&mut Cpu))),
+                ("AHX", (Instruction::Ahx, Cpu::ahx)),
+                ("ALR", (Instruction::Alr, Cpu::alr)),
+                ("ANC", (Instruction::Anc, Cpu::anc)),
                 ("AND", (Instruction::And, Cpu::and)),
+                ("ARR", (Instruction::Arr, Cpu::arr)),
                 ("ASL", (Instruction::Asl, Cpu::asl)),
+                ("AXS", (Instruction::Axs, Cpu::axs)),
+                ("BBR0", (Instruction::Bbr0, Cpu::bbr0)),
+                ("BBR1", (Instruction::Bbr1, Cpu::bbr1)),
+                ("BBR2", (Instruction::Bbr2, Cpu::bbr2)),
+                ("BBR3", (Instruction::Bbr3, Cpu::bbr3)),
+                ("BBR4", (Instruction::Bbr4, Cpu::bbr4)),
+                ("BBR5", (Instruction::Bbr5, Cpu::bbr5)),
+                ("BBR6", (Instruction::Bbr6, Cpu::bbr6)),
+                ("BBR7", (Instruction::Bbr7, Cpu::bbr7)),
+                ("BBS0", (Instruction::Bbs0, Cpu::bbs0)),
+                ("BBS1", (Instruction::Bbs1, Cpu::bbs1)),
+                ("BBS2", (Instruction::Bbs2, Cpu::bbs2)),
+                ("BBS3", (Instruction::Bbs3, Cpu::bbs3)),
+                ("BBS4", (Instruction::Bbs4, Cpu::bbs4)),
+                ("BBS5", (Instruction::Bbs5, Cpu::bbs5)),
+                ("BBS6", (Instruction::Bbs6, Cpu::bbs6)),
+                ("BBS7", (Instruction::Bbs7, Cpu::bbs7)),
                 ("BCC", (Instruction::Bcc, Cpu::bcc)),
                 ("BCS", (Instruction::Bcs, Cpu::bcs)),
                 ("BEQ", (Instruction::Beq, Cpu::beq)),
@@ -51,6 +155,7 @@ impl InstrSetParser {
                 ("BMI", (Instruction::Bmi, Cpu::bmi)),
                 ("BNE", (Instruction::Bne, Cpu::bne)),
                 ("BPL", (Instruction::Bpl, Cpu::bpl)),
+                ("BRA", (Instruction::Bra, Cpu::bra)),
                 ("BRK", (Instruction::Brk, Cpu::brk)),
                 ("BVC", (Instruction::Bvc, Cpu::bvc)),
                 ("BVS", (Instruction::Bvs, Cpu::bvs)),
@@ -61,6 +166,7 @@ impl InstrSetParser {
                 ("CMP", (Instruction::Cmp, Cpu::cmp)),
                 ("CPX", (Instruction::Cpx, Cpu::cpx)),
                 ("CPY", (Instruction::Cpy, Cpu::cpy)),
+                ("DCP", (Instruction::Dcp, Cpu::dcp)),
                 ("DEC", (Instruction::Dec, Cpu::dec)),
                 ("DEX", (Instruction::Dex, Cpu::dex)),
                 ("DEY", (Instruction::Dey, Cpu::dey)),
@@ -68,8 +174,12 @@ impl InstrSetParser {
                 ("INC", (Instruction::Inc, Cpu::inc)),
                 ("INX", (Instruction::Inx, Cpu::inx)),
                 ("INY", (Instruction::Iny, Cpu::iny)),
+                ("ISC", (Instruction::Isc, Cpu::isc)),
+                ("JAM", (Instruction::Jam, Cpu::jam)),
                 ("JMP", (Instruction::Jmp, Cpu::jmp)),
                 ("JSR", (Instruction::Jsr, Cpu::jsr)),
+                ("LAS", (Instruction::Las, Cpu::las)),
+                ("LAX", (Instruction::Lax, Cpu::lax)),
                 ("LDA", (Instruction::Lda, Cpu::lda)),
                 ("LDX", (Instruction::Ldx, Cpu::ldx)),
                 ("LDY", (Instruction::Ldy, Cpu::ldy)),
@@ -78,120 +188,198 @@ impl InstrSetParser {
                 ("ORA", (Instruction::Ora, Cpu::ora)),
                 ("PHA", (Instruction::Pha, Cpu::pha)),
                 ("PHP", (Instruction::Php, Cpu::php)),
+                ("PHX", (Instruction::Phx, Cpu::phx)),
+                ("PHY", (Instruction::Phy, Cpu::phy)),
                 ("PLA", (Instruction::Pla, Cpu::pla)),
                 ("PLP", (Instruction::Plp, Cpu::plp)),
+                ("PLX", (Instruction::Plx, Cpu::plx)),
+                ("PLY", (Instruction::Ply, Cpu::ply)),
+                ("RLA", (Instruction::Rla, Cpu::rla)),
+                ("RMB0", (Instruction::Rmb0, Cpu::rmb0)),
+                ("RMB1", (Instruction::Rmb1, Cpu::rmb1)),
+                ("RMB2", (Instruction::Rmb2, Cpu::rmb2)),
+                ("RMB3", (Instruction::Rmb3, Cpu::rmb3)),
+                ("RMB4", (Instruction::Rmb4, Cpu::rmb4)),
+                ("RMB5", (Instruction::Rmb5, Cpu::rmb5)),
+                ("RMB6", (Instruction::Rmb6, Cpu::rmb6)),
+                ("RMB7", (Instruction::Rmb7, Cpu::rmb7)),
                 ("ROL", (Instruction::Rol, Cpu::rol)),
                 ("ROR", (Instruction::Ror, Cpu::ror)),
+                ("RRA", (Instruction::Rra, Cpu::rra)),
                 ("RTI", (Instruction::Rti, Cpu::rti)),
                 ("RTS", (Instruction::Rts, Cpu::rts)),
+                ("SAX", (Instruction::Sax, Cpu::sax)),
                 ("SBC", (Instruction::Sbc, Cpu::sbc)),
                 ("SEC", (Instruction::Sec, Cpu::sec)),
                 ("SED", (Instruction::Sed, Cpu::sed)),
                 ("SEI", (Instruction::Sei, Cpu::sei)),
+                ("SHX", (Instruction::Shx, Cpu::shx)),
+                ("SHY", (Instruction::Shy, Cpu::shy)),
+                ("SLO", (Instruction::Slo, Cpu::slo)),
+                ("SMB0", (Instruction::Smb0, Cpu::smb0)),
+                ("SMB1", (Instruction::Smb1, Cpu::smb1)),
+                ("SMB2", (Instruction::Smb2, Cpu::smb2)),
+                ("SMB3", (Instruction::Smb3, Cpu::smb3)),
+                ("SMB4", (Instruction::Smb4, Cpu::smb4)),
+                ("SMB5", (Instruction::Smb5, Cpu::smb5)),
+                ("SMB6", (Instruction::Smb6, Cpu::smb6)),
+                ("SMB7", (Instruction::Smb7, Cpu::smb7)),
+                ("SRE", (Instruction::Sre, Cpu::sre)),
                 ("STA", (Instruction::Sta, Cpu::sta)),
+                ("STP", (Instruction::Stp, Cpu::stp)),
                 ("STX", (Instruction::Stx, Cpu::stx)),
                 ("STY", (Instruction::Sty, Cpu::sty)),
+                ("STZ", (Instruction::Stz, Cpu::stz)),
+                ("TAS", (Instruction::Tas, Cpu::tas)),
                 ("TAX", (Instruction::Tax, Cpu::tax)),
                 ("TAY", (Instruction::Tay, Cpu::tay)),
+                ("TRB", (Instruction::Trb, Cpu::trb)),
+                ("TSB", (Instruction::Tsb, Cpu::tsb)),
                 ("TSX", (Instruction::Tsx, Cpu::tsx)),
                 ("TXA", (Instruction::Txa, Cpu::txa)),
                 ("TXS", (Instruction::Txs, Cpu::txs)),
                 ("TYA", (Instruction::Tya, Cpu::tya)),
+                ("WAI", (Instruction::Wai, Cpu::wai)),
+                ("XAA", (Instruction::Xaa, Cpu::xaa)),
             ]),
-            optable: vec![nop; 0x100],
             filepath: String::from(filepath),
+            mode: ParseMode::default(),
+            warnings: Vec::new(),
         }
     }
 
-    fn parse_opcode(&self, value: &str) -> Result<usize, String> {
-        let value_stripped = match value.strip_prefix("0x") {
-            Some(v) => v,
-            None => {
-                return Err(format!("Invalid opcode: {}", value));
-            }
+    pub fn lenient(filepath: &str) -> InstrSetParser {
+        let mut parser = InstrSetParser::new(filepath);
+        parser.mode = ParseMode::Lenient;
+        parser
+    }
+
+    // on error, also returns the index of the offending field
+    fn parse_line(&self, line: &str) -> Result<(usize, Op), (Option<usize>, ParseErrorKind)> {
+        let row = parse_row(line).map_err(|(field, e)| (field, self.error_kind(e)))?;
+
+        let Some(&(instr, instr_ptr)) = self.instr_map.get(row.mnemonic.as_str()) else {
+            return Err((Some(1), ParseErrorKind::UnknownInstruction(row.mnemonic)));
         };
+        // parse_row only accepts the modes the files use
+        let (addr_mode, addr_mode_ptr) = self.addr_mode_map[row.addr_mode.as_str()];
 
-        match usize::from_str_radix(value_stripped, 16) {
-            Ok(n) => Ok(n),
-            Err(_) => Err(format!("Invalid opcode: {}", value)),
-        }
+        Ok((
+            row.opcode as usize,
+            Op {
+                info: OpInfo {
+                    instruction: instr,
+                    address_mode: addr_mode,
+                    bytes: row.bytes,
+                    flags: row.flags,
+                    undocumented: row.undocumented,
+                },
+                instruction: instr_ptr,
+                address_mode: addr_mode_ptr,
+                cycles: row.cycles,
+            },
+        ))
     }
 
-    fn parse_line(&self, line: &str) -> Result<(usize, Op), String> {
-        let tokens: Vec<_> = line.split(",").collect();
-        match tokens.as_slice() {
-            [opcode, instr, addr_mode, size, cycles, flags] => {
-                // println!("opcode: {}, instr: {}, addr_mode: {}",
-                //          opcode, instr, addr_mode);
+    fn error_kind(&self, e: RowError) -> ParseErrorKind {
+        match e {
+            RowError::FieldCount(n) => ParseErrorKind::FieldCount(n),
+            RowError::InvalidOpcode(s) => ParseErrorKind::InvalidOpcode(s),
+            RowError::InvalidBytes(s) => ParseErrorKind::InvalidBytes(s),
+            RowError::BytesMismatch(mode, bytes) => {
+                ParseErrorKind::BytesMismatch(self.addr_mode_map[mode.as_str()].0, bytes)
+            }
+            RowError::InvalidCycles(s) => ParseErrorKind::InvalidCycles(s),
+            RowError::InvalidFlags(s) => ParseErrorKind::InvalidFlags(s),
+            RowError::UnknownAddrMode(s) => ParseErrorKind::UnknownAddrMode(s),
+        }
+    }
 
-                let opcode = self.parse_opcode(opcode)?;
-                let cycles: u8 = cycles.parse().unwrap();
+    fn error(
+        &self,
+        line: usize,
+        text: &str,
+        field: Option<usize>,
+        kind: ParseErrorKind,
+    ) -> ParseError {
+        // 1-based column where the field starts
+        let column = match field {
+            Some(field) => {
+                1 + text
+                    .split(',')
+                    .take(field)
+                    .map(|token| token.len() + 1)
+                    .sum::<usize>()
+            }
+            None => 0,
+        };
 
-                let (instr, instr_ptr) = match self.instr_map.get(instr) {
-                    Some(value) => value.clone(),
-                    None => {
-                        return Err(format!("Invalid instruction: {}", instr));
-                    }
-                };
+        ParseError {
+            file: self.filepath.clone(),
+            line,
+            column,
+            text: text.to_string(),
+            kind,
+        }
+    }
 
-                let (addr_mode, addr_mode_ptr) = match self.addr_mode_map.get(addr_mode) {
-                    Some(value) => value.clone(),
-                    None => {
-                        return Err(format!("Invalid addressing mode: {}", addr_mode));
-                    }
-                };
-
-                Ok((
-                    opcode,
-                    Op {
-                        info: OpInfo {
-                            instruction: instr,
-                            address_mode: addr_mode,
-                        },
-                        instruction: instr_ptr,
-                        address_mode: addr_mode_ptr,
-                        cycles: cycles,
-                    },
-                ))
+    // fails in strict mode, records a warning otherwise
+    fn report(&mut self, error: ParseError) -> Result<(), ParseError> {
+        match self.mode {
+            ParseMode::Strict => Err(error),
+            ParseMode::Lenient => {
+                self.warnings.push(error);
+                Ok(())
             }
-
-            _ => Err(format!("Invalid line: {}", line)),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Op>, String> {
-        let nop = Op {
-            address_mode: Cpu::imp,
-            instruction: Cpu::nop,
-            cycles: 1,
-            info: OpInfo {
-                address_mode: AddrMode::Imp,
-                instruction: Instruction::Nop,
-            },
-        };
+    pub fn parse(&mut self) -> Result<Vec<Op>, ParseError> {
+        let mut optable: Vec<Op> = vec![Op::nop(); 0x100];
+        self.parse_into(&mut optable)?;
 
-        let mut optable: Vec<Op> = vec![nop; 0x100];
+        Ok(optable)
+    }
+
+    // applies the file on top of an existing table, so
+    // a variant can be described as a base instruction
+    // set plus the opcodes it adds or changes
+    pub fn parse_into(&mut self, optable: &mut [Op]) -> Result<(), ParseError> {
+        let file = fs::read_to_string(self.filepath.as_str()).map_err(|e| ParseError {
+            file: self.filepath.clone(),
+            line: 0,
+            column: 0,
+            text: String::new(),
+            kind: ParseErrorKind::Io(e.to_string()),
+        })?;
 
-        let file =
-            fs::read_to_string(self.filepath.as_str()).expect("Error reading instruction set file");
-        for line in file.lines() {
-            if line.is_empty() {
+        // line each opcode was defined on, duplicates
+        // are only an error within the same file
+        let mut defined: [usize; 0x100] = [0; 0x100];
+
+        for (i, line) in file.lines().enumerate() {
+            let line_no = i + 1;
+            if is_blank(line) {
                 continue;
             }
-            // println!("parsing: {}", line);
 
             match self.parse_line(line) {
                 Ok((opcode, op)) => {
+                    if defined[opcode] != 0 {
+                        let kind = ParseErrorKind::DuplicateOpcode(opcode as u8, defined[opcode]);
+                        let error = self.error(line_no, line, Some(0), kind);
+                        self.report(error)?;
+                    }
+                    defined[opcode] = line_no;
                     optable[opcode] = op;
                 }
-                Err(_) => {
-                    continue;
+                Err((field, kind)) => {
+                    let error = self.error(line_no, line, field, kind);
+                    self.report(error)?;
                 }
             }
-            let (opcode, op) = self.parse_line(line).unwrap();
-            optable[opcode] = op;
         }
 
-        Ok(optable)
+        Ok(())
     }
 }
@@ -1,2 +1,14 @@
+pub mod assembler;
+pub mod code_map;
+pub mod conformance;
+pub mod dap;
+pub mod debugger;
 pub mod disassembler;
+pub mod expression;
+pub mod functional_test;
+pub mod gdb;
+pub mod instr_set_format;
 pub mod instr_set_parser;
+pub mod json;
+pub mod source;
+pub mod symbols;
@@ -0,0 +1,214 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::system::cpu::{AddrMode, Instruction, Variant};
+use crate::system::optable;
+use crate::system::util::code_map::{Item, Listing};
+use crate::system::util::disassembler::{DisasmLine, Operand};
+
+// the assemblers a listing can be written out for
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Dialect {
+    #[default]
+    Ca65,
+    Asm6,
+    Nesasm,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Dialect, Self::Err> {
+        match input.to_ascii_lowercase().as_str() {
+            "ca65" => Ok(Dialect::Ca65),
+            "asm6" => Ok(Dialect::Asm6),
+            "nesasm" => Ok(Dialect::Nesasm),
+            _ => Err(format!("unknown syntax: {}", input)),
+        }
+    }
+}
+
+impl Dialect {
+    fn byte_directive(self) -> &'static str {
+        match self {
+            Dialect::Ca65 => ".byte",
+            Dialect::Asm6 | Dialect::Nesasm => ".db",
+        }
+    }
+
+    fn indirect(self, inner: &str) -> String {
+        match self {
+            // NESASM keeps parentheses for expressions
+            Dialect::Nesasm => format!("[{}]", inner),
+            Dialect::Ca65 | Dialect::Asm6 => format!("({})", inner),
+        }
+    }
+}
+
+const INDENT: &str = "        ";
+
+// whether the instruction exists on the original NMOS part,
+// the only one asm6 and NESASM know about
+fn is_nmos(line: &DisasmLine) -> bool {
+    let info = optable::info(Variant::Nmos6502, line.bytes[0]);
+    !info.undocumented && *info == line.op
+}
+
+// whether an assembler could pick a zero page encoding of the
+// same instruction for an absolute operand below $100
+fn has_zp_form(line: &DisasmLine) -> bool {
+    let zp_mode = match line.op.address_mode {
+        AddrMode::Abs => AddrMode::Zp,
+        AddrMode::Absx => AddrMode::Zpx,
+        AddrMode::Absy => AddrMode::Zpy,
+        _ => return false,
+    };
+    [Variant::Nmos6502, Variant::Wdc65C02]
+        .iter()
+        .any(|&variant| {
+            optable::optable(variant).iter().any(|op| {
+                !op.info.undocumented
+                    && op.info.instruction == line.op.instruction
+                    && op.info.address_mode == zp_mode
+            })
+        })
+}
+
+fn bytes_line(dialect: Dialect, bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+    format!(
+        "{}{} {}",
+        INDENT,
+        dialect.byte_directive(),
+        bytes.join(", ")
+    )
+}
+
+// the operand as `dialect` needs it to pick the same encoding,
+// or None when it has no way to say so and the bytes must be
+// written out instead
+fn operand(line: &DisasmLine, listing: &Listing, dialect: Dialect) -> Option<String> {
+    let arg = match line.operand {
+        Operand::None => 0,
+        Operand::Byte(arg) => arg as u16,
+        Operand::Word(arg) => arg,
+        Operand::Truncated => return None,
+    };
+    let name = |addr: u16| listing.labels.get(&addr).cloned();
+    let word = || name(arg).unwrap_or_else(|| format!("${:04X}", arg));
+    let ptr = || name(arg).unwrap_or_else(|| format!("${:02X}", arg));
+    let target = || {
+        let target = line.target()?;
+        Some(name(target).unwrap_or_else(|| format!("${:04X}", target)))
+    };
+
+    let zp = || match (dialect, name(arg)) {
+        // NESASM only uses zero page when asked to
+        (Dialect::Nesasm, _) => format!("<{}", ptr()),
+        // ca65 assumes labels it hasn't seen yet are absolute
+        (Dialect::Ca65, Some(name)) => format!("z:{}", name),
+        (_, _) => ptr(),
+    };
+    // an absolute operand that fits in a byte would be shrunk to
+    // zero page, so it needs forcing to come out the same
+    let abs = || match dialect {
+        _ if arg >= 0x100 || !has_zp_form(line) => Some(word()),
+        Dialect::Ca65 => Some(format!("a:{}", word())),
+        Dialect::Asm6 => None,
+        Dialect::Nesasm => Some(word()),
+    };
+
+    Some(match line.op.address_mode {
+        AddrMode::Imp => String::new(),
+        AddrMode::Acc => String::from(" A"),
+        AddrMode::Imm => format!(" #${:02X}", arg),
+        AddrMode::Rel => format!(" {}", target()?),
+        AddrMode::Zp => format!(" {}", zp()),
+        AddrMode::Zpx => format!(" {},X", zp()),
+        AddrMode::Zpy => format!(" {},Y", zp()),
+        AddrMode::Abs => format!(" {}", abs()?),
+        AddrMode::Absx => format!(" {},X", abs()?),
+        AddrMode::Absy => format!(" {},Y", abs()?),
+        // there's no zero page form of these to be confused with
+        AddrMode::Ind => format!(" {}", dialect.indirect(&word())),
+        AddrMode::Iabsx => format!(" {}", dialect.indirect(&format!("{},X", word()))),
+        // zero page pointers, nothing else they could be
+        AddrMode::Indx => format!(" {}", dialect.indirect(&format!("{},X", ptr()))),
+        AddrMode::Indy => format!(" {},Y", dialect.indirect(&ptr())),
+        AddrMode::Zpi => format!(" {}", dialect.indirect(&ptr())),
+        // the zero page byte to test, then the branch target
+        AddrMode::Zpr => {
+            let arg = arg & 0x00FF;
+            let zp = name(arg).unwrap_or_else(|| format!("${:02X}", arg));
+            format!(" {},{}", zp, target()?)
+        }
+    })
+}
+
+fn instruction(line: &DisasmLine, listing: &Listing, dialect: Dialect) -> String {
+    let supported = match dialect {
+        Dialect::Ca65 => !line.op.undocumented,
+        Dialect::Asm6 | Dialect::Nesasm => is_nmos(line),
+    };
+    let operand = operand(line, listing, dialect).filter(|_| supported);
+
+    match operand {
+        Some(operand) => format!("{}{}{}", INDENT, line.op.instruction.mnemonic(), operand),
+        // the raw bytes, keeping the instruction as a comment
+        None => format!(
+            "{} ; {}",
+            bytes_line(dialect, &line.bytes),
+            line.format(&|addr| listing.labels.get(&addr).cloned())
+        ),
+    }
+}
+
+// writes `listing` as source that `dialect` assembles back
+// into the same bytes
+pub fn render(listing: &Listing, dialect: Dialect) -> String {
+    let mut out = String::new();
+
+    if dialect == Dialect::Ca65 {
+        let cmos = listing.items.iter().any(|item| match item {
+            Item::Instruction(line) => !line.op.undocumented && !is_nmos(line),
+            _ => false,
+        });
+        // ca65 only takes WAI and STP for WDC's own part
+        let wdc = listing.items.iter().any(|item| match item {
+            Item::Instruction(line) => {
+                matches!(line.op.instruction, Instruction::Wai | Instruction::Stp)
+            }
+            _ => false,
+        });
+        let cpu = match (wdc, cmos) {
+            (true, _) => "W65C02",
+            (false, true) => "65C02",
+            (false, false) => "6502",
+        };
+        writeln!(out, "{}.setcpu \"{}\"", INDENT, cpu).unwrap();
+        writeln!(out, "{}.segment \"CODE\"", INDENT).unwrap();
+    }
+    // NESASM wants to know where in the ROM the code goes,
+    // the `.org` lines then place it within the bank
+    if dialect == Dialect::Nesasm {
+        writeln!(out, "{}.code", INDENT).unwrap();
+        writeln!(out, "{}.bank 0", INDENT).unwrap();
+    }
+
+    for item in &listing.items {
+        match item {
+            Item::Org(addr) => writeln!(out, "{}.org ${:04X}", INDENT, addr),
+            Item::Label(label) => {
+                if label.starts_with("sub_") {
+                    out.push('\n');
+                }
+                writeln!(out, "{}:", label)
+            }
+            Item::Instruction(line) => writeln!(out, "{}", instruction(line, listing, dialect)),
+            Item::Bytes(_, bytes) => writeln!(out, "{}", bytes_line(dialect, bytes)),
+        }
+        .unwrap();
+    }
+
+    out
+}
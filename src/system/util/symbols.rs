@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// how far past a symbol with no known size an address can be and
+// still be shown relative to it: enough for a small table or struct
+const DEFAULT_REACH: u16 = 16;
+
+// the label files `SymbolTable::parse` understands
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LabelFormat {
+    // `name = $addr`, as written by `Output::symbol_file`
+    Plain,
+    // `al 00C000 .name`, also what ld65 writes with `-Ln`
+    Vice,
+    // `$C000#name#comment`
+    Fceux,
+    // ld65's `--dbgfile`
+    Ca65Dbg,
+}
+
+impl LabelFormat {
+    // guesses the format from the first line that says anything
+    pub fn detect(text: &str) -> LabelFormat {
+        let first = text.lines().map(str::trim).find(|line| !line.is_empty());
+        match first {
+            Some(line) if line.starts_with("version") && line.contains("major=") => {
+                LabelFormat::Ca65Dbg
+            }
+            Some(line) if line.starts_with("al ") => LabelFormat::Vice,
+            Some(line) if line.starts_with('$') && line.contains('#') => LabelFormat::Fceux,
+            _ => LabelFormat::Plain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelErrorKind {
+    Io(String),
+    // what a line of the format should look like
+    InvalidLine(&'static str),
+    InvalidAddress(String),
+}
+
+impl fmt::Display for LabelErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LabelErrorKind::Io(e) => write!(f, "{}", e),
+            LabelErrorKind::InvalidLine(expected) => write!(f, "expected `{}`", expected),
+            LabelErrorKind::InvalidAddress(s) => write!(f, "invalid address `{}`", s),
+        }
+    }
+}
+
+// line is 1-based, 0 means the whole file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelError {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+    pub kind: LabelErrorKind,
+}
+
+impl fmt::Display for LabelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}", self.file, self.kind);
+        }
+        write!(
+            f,
+            "{}:{}: {}\n    {}",
+            self.file, self.line, self.kind, self.text
+        )
+    }
+}
+
+impl Error for LabelError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    name: String,
+    // how many bytes it covers, when the file says
+    size: Option<u16>,
+}
+
+// the source line some bytes were assembled from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    // 1-based
+    pub line: usize,
+    pub addr: u16,
+    pub size: u16,
+}
+
+impl SourceLine {
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.addr as u32..self.addr as u32 + self.size as u32).contains(&(addr as u32))
+    }
+}
+
+// names for addresses, loaded from label files, and the source
+// lines behind them when a debug file says
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    by_addr: BTreeMap<u16, Symbol>,
+    by_name: HashMap<String, u16>,
+    lines: BTreeMap<u16, SourceLine>,
+    // how far past a symbol of unknown size `describe` reaches
+    pub reach: u16,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            by_addr: BTreeMap::new(),
+            by_name: HashMap::new(),
+            lines: BTreeMap::new(),
+            reach: DEFAULT_REACH,
+        }
+    }
+
+    // every name can be looked up, but an address shows the first
+    // name it was given
+    pub fn insert(&mut self, name: &str, addr: u16, size: Option<u16>) {
+        self.by_name.insert(String::from(name), addr);
+        self.by_addr.entry(addr).or_insert_with(|| Symbol {
+            name: String::from(name),
+            size,
+        });
+    }
+
+    // the first line to cover an address keeps it
+    pub fn insert_line(&mut self, file: &str, line: usize, addr: u16, size: u16) {
+        if size == 0 || self.line(addr).is_some() {
+            return;
+        }
+        self.lines.entry(addr).or_insert_with(|| SourceLine {
+            file: String::from(file),
+            line,
+            addr,
+            size,
+        });
+    }
+
+    pub fn load(&mut self, path: &str) -> Result<(), LabelError> {
+        let text = fs::read_to_string(path).map_err(|e| LabelError {
+            file: String::from(path),
+            line: 0,
+            text: String::new(),
+            kind: LabelErrorKind::Io(format!("can't read {}: {}", path, e)),
+        })?;
+        self.parse(path, &text)
+    }
+
+    // adds the labels in `text`, whatever format it's in;
+    // `file` is only used for errors
+    pub fn parse(&mut self, file: &str, text: &str) -> Result<(), LabelError> {
+        let parse_line: fn(&str) -> Parsed<'_> = match LabelFormat::detect(text) {
+            LabelFormat::Ca65Dbg => return self.parse_dbg(file, text),
+            LabelFormat::Plain => plain_line,
+            LabelFormat::Vice => vice_line,
+            LabelFormat::Fceux => fceux_line,
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |kind| LabelError {
+                file: String::from(file),
+                line: i + 1,
+                text: String::from(line),
+                kind,
+            };
+            if let Some((name, addr, size)) = parse_line(line.trim()).map_err(error)? {
+                self.insert(name, addr, size);
+            }
+        }
+        Ok(())
+    }
+
+    // `sym` lines, with cheap locals named `parent@local` like the
+    // assembler does; labels win over equates at the same address.
+    // `line` lines are followed through their spans and segments to
+    // addresses, leaving out the ones inside macros, which ca65 also
+    // gives to the line the macro was used on
+    fn parse_dbg(&mut self, file: &str, text: &str) -> Result<(), LabelError> {
+        let mut symbols = Vec::new();
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        let mut lines = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let Some((record, fields)) = line.split_once('\t') else {
+                continue;
+            };
+            let error = |kind| LabelError {
+                file: String::from(file),
+                line: i + 1,
+                text: String::from(line),
+                kind,
+            };
+
+            let fields: HashMap<&str, &str> = fields
+                .split(',')
+                .filter_map(|field| field.split_once('='))
+                .collect();
+            let number = |name: &str| fields.get(name).and_then(|value| parse_number(value));
+            match record {
+                "sym" => {}
+                "file" => {
+                    if let (Some(id), Some(name)) = (fields.get("id"), fields.get("name")) {
+                        files.insert(*id, name.trim_matches('"'));
+                    }
+                    continue;
+                }
+                "seg" => {
+                    if let (Some(id), Some(start)) = (fields.get("id"), number("start")) {
+                        segments.insert(*id, start);
+                    }
+                    continue;
+                }
+                "span" => {
+                    if let (Some(id), Some(seg), Some(start), Some(size)) = (
+                        fields.get("id"),
+                        fields.get("seg"),
+                        number("start"),
+                        number("size"),
+                    ) {
+                        spans.insert(*id, (*seg, start, size));
+                    }
+                    continue;
+                }
+                "line" => {
+                    let macro_line = fields.get("type") == Some(&"2");
+                    if let (false, Some(source), Some(number), Some(span)) = (
+                        macro_line,
+                        fields.get("file"),
+                        number("line"),
+                        fields.get("span"),
+                    ) {
+                        lines.push((*source, number as usize, *span));
+                    }
+                    continue;
+                }
+                _ => continue,
+            }
+
+            let (Some(id), Some(name)) = (fields.get("id"), fields.get("name")) else {
+                return Err(error(LabelErrorKind::InvalidLine(
+                    "sym\tid=<n>,name=\"<name>\",...",
+                )));
+            };
+            // imports have no value of their own
+            let Some(val) = fields.get("val") else {
+                continue;
+            };
+            let Some(value) = parse_number(val).filter(|&value| value <= 0xFFFF) else {
+                return Err(error(LabelErrorKind::InvalidAddress(String::from(*val))));
+            };
+            let size = number("size");
+
+            symbols.push(DbgSymbol {
+                id: String::from(*id),
+                name: String::from(name.trim_matches('"')),
+                parent: fields.get("parent").map(|parent| String::from(*parent)),
+                addr: value as u16,
+                size: size.map(|size| size as u16),
+                label: fields.get("type") == Some(&"lab"),
+            });
+        }
+
+        let names: HashMap<&str, &str> = symbols
+            .iter()
+            .map(|symbol| (symbol.id.as_str(), symbol.name.as_str()))
+            .collect();
+        let mut ordered: Vec<&DbgSymbol> = symbols.iter().collect();
+        ordered.sort_by_key(|symbol| !symbol.label);
+        for symbol in ordered {
+            let name = match symbol.parent.as_deref().and_then(|id| names.get(id)) {
+                Some(parent) => format!("{}{}", parent, symbol.name),
+                None => symbol.name.clone(),
+            };
+            self.insert(&name, symbol.addr, symbol.size);
+        }
+
+        // a line can have several spans, joined with `+`
+        for (source, number, span_ids) in lines {
+            let Some(source) = files.get(source) else {
+                continue;
+            };
+            for span in span_ids.split('+') {
+                let Some(&(seg, start, size)) = spans.get(span) else {
+                    continue;
+                };
+                let Some(base) = segments.get(seg) else {
+                    continue;
+                };
+                if let (Ok(addr), Ok(size)) = (u16::try_from(base + start), u16::try_from(size)) {
+                    self.insert_line(source, number, addr, size);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    // the name given to exactly `addr`
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(|symbol| symbol.name.as_str())
+    }
+
+    // `addr` as a name, or as `name+offset` from the closest
+    // symbol below it if that's within the symbol's size
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let (&start, symbol) = self.by_addr.range(..=addr).next_back()?;
+        let offset = addr - start;
+        if offset == 0 {
+            return Some(symbol.name.clone());
+        }
+        match symbol.size {
+            Some(size) if offset < size => {}
+            None if offset <= self.reach => {}
+            _ => return None,
+        }
+        Some(format!("{}+{}", symbol.name, offset))
+    }
+
+    // every address with a name, in order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.by_addr
+            .iter()
+            .map(|(&addr, symbol)| (addr, symbol.name.as_str()))
+    }
+
+    // the line whose bytes include `addr`
+    pub fn line(&self, addr: u16) -> Option<&SourceLine> {
+        let (_, line) = self.lines.range(..=addr).next_back()?;
+        line.contains(addr).then_some(line)
+    }
+
+    // the first line at or after `line` in `file` that has code,
+    // with every address it starts at; a file given with fewer
+    // directories than the debug file has, or more, still matches
+    pub fn find_line(&self, file: &str, line: usize) -> Option<(usize, Vec<u16>)> {
+        let found = self
+            .lines
+            .values()
+            .filter(|source| source.line >= line && same_file(&source.file, file))
+            .map(|source| source.line)
+            .min()?;
+        let addrs = self
+            .lines
+            .values()
+            .filter(|source| source.line == found && same_file(&source.file, file))
+            .map(|source| source.addr)
+            .collect();
+        Some((found, addrs))
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    let (a, b) = (Path::new(a), Path::new(b));
+    a.ends_with(b) || b.ends_with(a)
+}
+
+struct DbgSymbol {
+    id: String,
+    name: String,
+    parent: Option<String>,
+    addr: u16,
+    size: Option<u16>,
+    label: bool,
+}
+
+// a parsed line: None for blank lines and comments
+type Parsed<'t> = Result<Option<(&'t str, u16, Option<u16>)>, LabelErrorKind>;
+
+// decimal, 0x.. or $..
+fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn parse_addr(text: &str, parsed: Option<u32>) -> Result<u16, LabelErrorKind> {
+    parsed
+        .and_then(|addr| u16::try_from(addr).ok())
+        .ok_or_else(|| LabelErrorKind::InvalidAddress(String::from(text)))
+}
+
+fn plain_line(line: &str) -> Parsed<'_> {
+    let line = line.split(';').next().unwrap_or_default().trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let Some((name, addr)) = line.split_once('=') else {
+        return Err(LabelErrorKind::InvalidLine("name = $addr"));
+    };
+    let (name, addr) = (name.trim(), addr.trim());
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(LabelErrorKind::InvalidLine("name = $addr"));
+    }
+    Ok(Some((name, parse_addr(addr, parse_number(addr))?, None)))
+}
+
+// VICE monitor files can hold other commands, which are left alone
+fn vice_line(line: &str) -> Parsed<'_> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("al") {
+        return Ok(None);
+    }
+    let (Some(addr), Some(name), None) = (words.next(), words.next(), words.next()) else {
+        return Err(LabelErrorKind::InvalidLine("al <addr> .<name>"));
+    };
+    // an optional memory space, e.g. `C:` for the computer
+    let hex = addr.split_once(':').map_or(addr, |(_, hex)| hex);
+    let addr = parse_addr(addr, u32::from_str_radix(hex, 16).ok())?;
+    Ok(Some((name.strip_prefix('.').unwrap_or(name), addr, None)))
+}
+
+fn fceux_line(line: &str) -> Parsed<'_> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let mut fields = line.splitn(3, '#');
+    let (Some(addr), Some(name)) = (fields.next(), fields.next()) else {
+        return Err(LabelErrorKind::InvalidLine("$addr#name#comment"));
+    };
+    if name.is_empty() {
+        return Err(LabelErrorKind::InvalidLine("$addr#name#comment"));
+    }
+    let Some(addr) = addr.strip_prefix('$') else {
+        return Err(LabelErrorKind::InvalidLine("$addr#name#comment"));
+    };
+
+    // arrays come as `$addr/size`, with the size in hex too
+    let (addr, size) = match addr.split_once('/') {
+        Some((addr, size)) => (addr, u16::from_str_radix(size, 16).ok()),
+        None => (addr, None),
+    };
+    let addr = parse_addr(addr, u32::from_str_radix(addr, 16).ok())?;
+    Ok(Some((name, addr, size)))
+}
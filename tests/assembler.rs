@@ -0,0 +1,410 @@
+mod common;
+
+use common::assemble;
+use vanilla::bus::Ram;
+use vanilla::system::cpu::{Cpu, Variant};
+use vanilla::system::optable;
+use vanilla::system::util::assembler::{AsmErrorKind, Assembler};
+use vanilla::system::util::code_map::CodeMap;
+use vanilla::system::util::disassembler::Disassembler;
+use vanilla::system::util::source::{self, Dialect};
+
+#[test]
+fn assembles_labels_and_expressions() {
+    let output = assemble(
+        "        .org $0400
+start:  ldx #end - table
+@loop:  lda table-1,x
+        sta $0200,x
+        dex
+        bne @loop
+        jmp (vector)
+table:  .byte 1, 2, 'A', <start, >start
+end:
+vector: .word start, * + 2",
+    );
+
+    assert_eq!(output.origin, 0x0400);
+    assert_eq!(
+        output.bytes,
+        [
+            0xA2, 0x05, // ldx #5
+            0xBD, 0x0D, 0x04, // lda $040D,x
+            0x9D, 0x00, 0x02, // sta $0200,x
+            0xCA, // dex
+            0xD0, 0xF7, // bne @loop
+            0x6C, 0x13, 0x04, // jmp ($0413)
+            0x01, 0x02, 0x41, 0x00, 0x04, // table
+            0x00, 0x04, 0x17, 0x04, // vector
+        ]
+    );
+    assert_eq!(output.symbols["start@loop"], 0x0402);
+    assert_eq!(output.symbols["end"], 0x0413);
+}
+
+#[test]
+fn forward_references_stay_absolute() {
+    let output = assemble(
+        "        .org $10
+before: .org $0400
+        lda before
+        lda after
+        .org $20
+after:",
+    );
+
+    assert_eq!(output.origin, 0x0400);
+    assert_eq!(output.bytes, [0xA5, 0x10, 0xAD, 0x20, 0x00]);
+}
+
+#[test]
+fn constants_can_refer_to_later_labels() {
+    // `FOO` needs `BAR`, which needs `target`
+    let output = assemble(
+        "        .org $8000
+        lda FOO
+        jmp BAR
+FOO = BAR + 1
+BAR = target
+target: rts",
+    );
+    assert_eq!(output.bytes, [0xAD, 0x07, 0x80, 0x4C, 0x06, 0x80, 0x60]);
+    assert_eq!(output.symbols["FOO"], 0x8007);
+
+    let mut assembler = Assembler::new(optable::optable(Variant::Nmos6502));
+    let e = assembler
+        .assemble_str("bad.s", "  lda one\none = two\ntwo = one\n")
+        .err()
+        .unwrap();
+    assert_eq!(e.line, 2);
+    assert_eq!(e.kind, AsmErrorKind::Undefined(String::from("two")));
+
+    let e = assembler
+        .assemble_str("bad.s", "one = later\none = 1\nlater:\n")
+        .err()
+        .unwrap();
+    assert_eq!(
+        e.kind,
+        AsmErrorKind::DuplicateSymbol(String::from("one"), String::from("bad.s:2"))
+    );
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let mut assembler = Assembler::new(optable::optable(Variant::Nmos6502));
+    assembler.add_source(
+        "src/main.s",
+        "  .org $8000\n  .include \"lib/nop.s\"\n  rts",
+    );
+    assembler.add_source("src/lib/nop.s", "  nop\n  nop");
+
+    let output = assembler.assemble("src/main.s").unwrap();
+    assert_eq!(output.bytes, [0xEA, 0xEA, 0x60]);
+    assert!(output.listing.contains("; src/lib/nop.s"));
+}
+
+#[test]
+fn errors_point_at_the_offending_text() {
+    let mut assembler = Assembler::new(optable::optable(Variant::Nmos6502));
+    let e = assembler
+        .assemble_str("bad.s", "  nop\n  lda missing,x\n")
+        .err()
+        .unwrap();
+    assert_eq!((e.line, e.column), (2, 7));
+    assert_eq!(e.kind, AsmErrorKind::Undefined(String::from("missing")));
+
+    let e = assembler
+        .assemble_str(
+            "bad.s",
+            "  .org $8000\nfar: .byte 0\n  .org $8100\n  beq far\n",
+        )
+        .err()
+        .unwrap();
+    assert_eq!(e.line, 4);
+    assert!(matches!(e.kind, AsmErrorKind::BranchOutOfRange(_)));
+
+    let e = assembler
+        .assemble_str("bad.s", "one:\none:\n")
+        .err()
+        .unwrap();
+    assert_eq!(
+        e.kind,
+        AsmErrorKind::DuplicateSymbol(String::from("one"), String::from("bad.s:1"))
+    );
+}
+
+#[test]
+fn debugger_syntax_is_rejected() {
+    // `#` only starts an immediate operand, and `[addr]` needs memory
+    let mut assembler = Assembler::new(optable::optable(Variant::Nmos6502));
+    for text in [
+        "x = #3",
+        "  .byte #1",
+        "  .byte 1, ##2",
+        "  lda $10+#$20",
+        "  lda [$10]",
+    ] {
+        let e = assembler.assemble_str("bad.s", text).err().unwrap();
+        assert!(matches!(e.kind, AsmErrorKind::Syntax(_)), "{}", text);
+    }
+}
+
+#[test]
+fn assembled_code_runs() {
+    let output = assemble(
+        "        .org $0400
+        ; multiplies 7 by 6 by repeated addition
+multiply:
+        lda #0
+        ldx #6
+        clc
+@add:   adc #7
+        dex
+        bne @add
+        sta result
+done:   jmp done
+result: .byte 0",
+    );
+
+    let mut ram = Ram::new();
+    ram.load(output.origin, &output.bytes);
+    let mut cpu = Cpu::new(Box::new(ram));
+    cpu.regs.pc = output.origin;
+    for _ in 0..100 {
+        cpu.step();
+    }
+
+    assert_eq!(cpu.regs.pc, output.symbols["done"] as u16);
+    assert_eq!(cpu.bus.peek(output.symbols["result"] as u16), 42);
+}
+
+#[test]
+fn ca65_listing_reassembles_to_the_same_bytes() {
+    let output = assemble(
+        "        .org $8000
+reset:  sei
+        ldx #$FF
+        txs
+        jsr init
+@wait:  lda $2002
+        bpl @wait
+        lda a:$0010
+        jmp @wait
+data:   .byte $13, $37
+init:   lda ($20),y
+        ldx $10,y
+        rts
+        .org $FFFA
+        .word reset, reset, reset",
+    );
+
+    let optable = optable::optable(Variant::Nmos6502);
+    let disassembler = Disassembler::with_origin(output.bytes.clone(), optable, output.origin);
+    let map = CodeMap::trace(&disassembler, &[0x8000]);
+    let text = source::render(&map.listing(&disassembler), Dialect::Ca65);
+
+    let reassembled = assemble(&text);
+    assert_eq!(reassembled.origin, output.origin);
+    assert_eq!(reassembled.bytes, output.bytes, "\n{}", text);
+}
+
+#[test]
+fn wdc_bit_branches_take_two_operands() {
+    let output = assemble(
+        "        .setcpu \"65C02\"
+flags = $20
+        .org $0400
+loop:   smb3 $10
+        bbs3 $10,loop
+        bbr3 flags, done
+done:   wai
+        stp",
+    );
+    assert_eq!(
+        output.bytes,
+        [
+            0xB7, 0x10, // smb3 $10
+            0xBF, 0x10, 0xFB, // bbs3 $10,loop
+            0x3F, 0x20, 0x00, // bbr3 flags,done
+            0xCB, // wai
+            0xDB, // stp
+        ]
+    );
+
+    let optable = optable::optable(Variant::Wdc65C02);
+    let disassembler = Disassembler::with_origin(output.bytes.clone(), optable, output.origin);
+    let map = CodeMap::trace(&disassembler, &[0x0400]);
+    let text = source::render(&map.listing(&disassembler), Dialect::Ca65);
+    assert!(text.contains(".setcpu \"W65C02\""), "{}", text);
+    assert!(text.contains("BBS3 $10,sub_0400"), "{}", text);
+    assert_eq!(assemble(&text).bytes, output.bytes, "\n{}", text);
+
+    let mut assembler = Assembler::new(optable::optable(Variant::Wdc65C02));
+    let e = assembler
+        .assemble_str("bad.s", "  bbr0 $10\n")
+        .err()
+        .unwrap();
+    assert!(matches!(e.kind, AsmErrorKind::Syntax(_)));
+    let e = assembler
+        .assemble_str("bad.s", "  bbr0 $1234,*\n")
+        .err()
+        .unwrap();
+    assert_eq!(e.kind, AsmErrorKind::OutOfRange(0x1234, "the zero page"));
+}
+
+#[test]
+fn macros_take_arguments_and_keep_their_labels_apart() {
+    let output = assemble(
+        "        .macro inc16 addr
+        inc addr
+        bne @done
+        inc addr+1
+@done:
+        .endmacro
+
+ptr = $20
+        .org $0400
+start:  inc16 $10
+        inc16 ptr",
+    );
+
+    assert_eq!(
+        output.bytes,
+        [
+            0xE6, 0x10, // inc $10
+            0xD0, 0x02, // bne @done
+            0xE6, 0x11, // inc $11
+            0xE6, 0x20, // inc $20
+            0xD0, 0x02, // bne @done
+            0xE6, 0x21, // inc $21
+        ]
+    );
+    assert_eq!(output.symbols["ptr"], 0x20);
+    assert!(output.listing.contains("+ 0400"));
+}
+
+#[test]
+fn conditionals_pick_one_branch() {
+    let output = assemble(
+        "MODE = 2
+        .org $0400
+        .if MODE == 1
+        .byte 1
+        .elseif MODE == 2
+        .byte 2
+        .if 0
+        .byte $FF
+        .else
+        .byte 3
+        .endif
+        .else
+        .byte 4
+        .endif
+        .ifdef MODE
+        .byte 5
+        .endif
+        .ifndef missing
+        .byte 6
+        .endif
+        .ifdef missing
+        .byte missing
+        .endif",
+    );
+
+    assert_eq!(output.bytes, [2, 3, 5, 6]);
+}
+
+#[test]
+fn ifdef_only_sees_symbols_defined_above() {
+    // both passes skip it, though the second knows `later`
+    let output = assemble(
+        "        .org $8000
+        .ifdef later
+        lda $1234
+        .endif
+        nop
+later = 5",
+    );
+    assert_eq!(output.bytes, [0xEA]);
+
+    let output = assemble(
+        "        .org $8000
+        .ifndef flag
+flag = 1
+        lda #1
+        .endif
+        lda $10",
+    );
+    assert_eq!(output.bytes, [0xA9, 0x01, 0xA5, 0x10]);
+
+    let output = assemble(
+        "        .org $8000
+        .ifdef later
+        lda later
+        .endif
+later:  nop",
+    );
+    assert_eq!(output.bytes, [0xEA]);
+
+    // an assignment counts even before its value is known
+    let output = assemble(
+        "        .org $8000
+size = end - start
+        .ifdef size
+start:  .byte size
+        .endif
+end:",
+    );
+    assert_eq!(output.bytes, [0x01]);
+}
+
+#[test]
+fn repeats_count_up() {
+    let output = assemble(
+        "        .org $0400
+squares:
+        .repeat 4, i
+        .repeat 2
+        .byte i * i
+        .endrepeat
+        .endrepeat",
+    );
+
+    assert_eq!(output.bytes, [0, 0, 1, 1, 4, 4, 9, 9]);
+}
+
+#[test]
+fn symbol_file_lists_labels() {
+    let output = assemble(
+        "BIG = $12345
+COUNT = 3
+        .org $C000
+reset:  nop
+@loop:  jmp @loop",
+    );
+
+    assert_eq!(output.symbol_file(), "reset = $C000\nreset@loop = $C001\n");
+    assert!(output.constants.contains("COUNT"));
+    assert!(!output.constants.contains("reset"));
+}
+
+#[test]
+fn unclosed_blocks_point_at_their_start() {
+    let mut assembler = Assembler::new(optable::optable(Variant::Nmos6502));
+    let e = assembler
+        .assemble_str("bad.s", "  nop\n  .if 1\n  nop\n")
+        .err()
+        .unwrap();
+    assert_eq!((e.line, e.column), (2, 3));
+    assert_eq!(e.kind, AsmErrorKind::Unclosed(".if", ".endif"));
+
+    let e = assembler
+        .assemble_str("bad.s", "  .macro twice x\n  .byte x, x\n")
+        .err()
+        .unwrap();
+    assert_eq!(e.line, 1);
+    assert_eq!(e.kind, AsmErrorKind::Unclosed(".macro", ".endmacro"));
+
+    let e = assembler.assemble_str("bad.s", "  .endif\n").err().unwrap();
+    assert_eq!(e.kind, AsmErrorKind::Unmatched(String::from(".endif")));
+}
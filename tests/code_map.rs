@@ -0,0 +1,129 @@
+mod common;
+
+use common::disassembler;
+use vanilla::system::util::code_map::{self, CodeMap, Item, LabelKind};
+use vanilla::system::util::disassembler::Disassembler;
+use vanilla::system::util::symbols::SymbolTable;
+
+// the listing's items, with instructions as their text
+fn items(map: &CodeMap, disassembler: &Disassembler) -> Vec<String> {
+    map.listing(disassembler)
+        .items
+        .iter()
+        .map(|item| match item {
+            Item::Org(addr) => format!("org {:04X}", addr),
+            Item::Label(label) => format!("{}:", label),
+            Item::Instruction(line) => line.text.clone(),
+            Item::Bytes(addr, bytes) => format!("{:04X} {:02X?}", addr, bytes),
+        })
+        .collect()
+}
+
+#[test]
+fn follows_calls_jumps_and_branches() {
+    let disassembler = disassembler(
+        &[
+            0x20, 0x08, 0x80, // JSR $8008
+            0xD0, 0xFE, // BNE $8003
+            0x4C, 0x0D, 0x80, // JMP $800D
+            0x6C, 0x00, 0x90, // JMP ($9000)
+            0x01, 0x02, // never reached
+            0x60, // RTS
+        ],
+        0x8000,
+    );
+    let map = CodeMap::trace(&disassembler, &[0x8000]);
+
+    let code: Vec<u16> = map.code.keys().copied().collect();
+    assert_eq!(code, [0x8000, 0x8003, 0x8005, 0x8008, 0x800D]);
+    // the indirect jump is as far as it goes
+    assert!(!map.is_code(0x800B));
+    let labels: Vec<(u16, LabelKind)> = map.labels.iter().map(|(&a, &k)| (a, k)).collect();
+    assert_eq!(
+        labels,
+        [
+            (0x8000, LabelKind::Sub),
+            (0x8003, LabelKind::Local),
+            (0x8008, LabelKind::Sub),
+            (0x800D, LabelKind::Local),
+        ]
+    );
+
+    assert_eq!(
+        items(&map, &disassembler),
+        [
+            "org 8000",
+            "sub_8000:",
+            "JSR sub_8008",
+            "L_8003:",
+            "BNE L_8003",
+            "JMP L_800D",
+            "sub_8008:",
+            "JMP ($9000)",
+            "800B [01, 02]",
+            "L_800D:",
+            "RTS",
+        ]
+    );
+}
+
+#[test]
+fn data_runs_break_at_labels() {
+    let mut bytes = vec![0x60];
+    bytes.extend(1..=12);
+    let mut disassembler = disassembler(&bytes, 0x8000);
+    let mut symbols = SymbolTable::new();
+    symbols.insert("table", 0x800B, None);
+    disassembler.symbols = Some(&symbols);
+    let map = CodeMap::trace(&disassembler, &[0x8000]);
+
+    // at most 8 bytes a line, and a new one at `table`
+    assert_eq!(
+        items(&map, &disassembler),
+        [
+            "org 8000",
+            "sub_8000:",
+            "RTS",
+            "8001 [01, 02, 03, 04, 05, 06, 07, 08]",
+            "8009 [09, 0A]",
+            "table:",
+            "800B [0B, 0C]",
+        ]
+    );
+}
+
+#[test]
+fn labels_inside_instructions_are_left_out() {
+    let disassembler = disassembler(
+        &[
+            0xA9, 0xEA, // LDA #$EA
+            0xD0, 0xFD, // BNE $8001, into the operand
+            0x60, // RTS
+        ],
+        0x8000,
+    );
+    let map = CodeMap::trace(&disassembler, &[0x8000]);
+
+    assert!(!map.is_code(0x8001));
+    assert_eq!(map.labels.get(&0x8001), Some(&LabelKind::Local));
+    assert_eq!(map.label(0x8001), None);
+    assert_eq!(
+        items(&map, &disassembler),
+        ["org 8000", "sub_8000:", "LDA #$EA", "BNE $8001", "RTS"]
+    );
+    assert!(!map.listing(&disassembler).labels.contains_key(&0x8001));
+}
+
+#[test]
+fn starts_from_the_vectors() {
+    let mut bytes = vec![0xEA; 10];
+    bytes.extend([0x00, 0x80, 0x10, 0x80, 0x10, 0x80]);
+    assert_eq!(
+        code_map::vectors(&disassembler(&bytes, 0xFFF0)),
+        [0x8000, 0x8010]
+    );
+
+    // not when the image stops short of them
+    assert!(code_map::vectors(&disassembler(&bytes[..15], 0xFFF0)).is_empty());
+    assert!(code_map::vectors(&disassembler(&bytes, 0x8000)).is_empty());
+}
@@ -0,0 +1,44 @@
+// helpers the integration tests share
+#![allow(dead_code)]
+
+use vanilla::system::cpu::Variant;
+use vanilla::system::optable;
+use vanilla::system::util::assembler::{Assembler, Output};
+use vanilla::system::util::debugger::Debugger;
+use vanilla::system::util::disassembler::Disassembler;
+
+// adds 3 to A twice, then polls a register that never changes
+pub const PROGRAM: &str = "        .org $0400
+start:  lda #1
+        jsr add3
+        jsr add3
+        sta $10
+wait:   lda $2002
+        bpl wait
+done:   jmp done
+add3:   clc
+        adc #3
+        rts";
+
+pub fn assemble(program: &str) -> Output {
+    Assembler::new(optable::optable(Variant::Nmos6502))
+        .assemble_str("test.s", program)
+        .unwrap_or_else(|e| panic!("{}", e))
+}
+
+// NMOS code at `origin`
+pub fn disassembler(bytes: &[u8], origin: u16) -> Disassembler<'static> {
+    Disassembler::with_origin(bytes.to_vec(), optable::optable(Variant::Nmos6502), origin)
+}
+
+// `program` loaded and about to start, with its source lines
+// and symbols
+pub fn debugger(program: &str) -> Debugger {
+    let output = assemble(program);
+    let mut debugger = Debugger::with_image(Variant::Nmos6502, &output.bytes, output.origin, None);
+    debugger
+        .symbols
+        .parse("test.dbg", &output.debug_file())
+        .unwrap();
+    debugger
+}
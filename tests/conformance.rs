@@ -0,0 +1,26 @@
+use vanilla::system::cpu::Variant;
+use vanilla::system::util::conformance::Checker;
+
+fn run(variant: Variant) {
+    let mut checker = Checker::new(variant);
+    checker.iterations = 200;
+
+    let violations = checker.run();
+    let report: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+    assert!(violations.is_empty(), "\n{}", report.join("\n"));
+}
+
+#[test]
+fn nmos_flags_conform() {
+    run(Variant::Nmos6502);
+}
+
+#[test]
+fn ricoh_2a03_flags_conform() {
+    run(Variant::Ricoh2A03);
+}
+
+#[test]
+fn wdc_65c02_flags_conform() {
+    run(Variant::Wdc65C02);
+}
@@ -0,0 +1,486 @@
+use vanilla::bus::{Bus, Ram};
+use vanilla::system::cpu::{Cpu, Flag, Variant};
+
+const NMI_HANDLER: u16 = 0x0500;
+const IRQ_HANDLER: u16 = 0x0600;
+
+// `code` at $0400, with both handlers just an RTI
+fn cpu(variant: Variant, code: &[u8]) -> Cpu {
+    let mut ram = Ram::new();
+    ram.load(0x0400, code);
+    ram.load(NMI_HANDLER, &[0x40]);
+    ram.load(IRQ_HANDLER, &[0x40]);
+    ram.load(0xFFFA, &[0x00, 0x05, 0x00, 0x04, 0x00, 0x06]);
+    let mut cpu = Cpu::with_variant(variant, Box::new(ram));
+    cpu.regs.pc = 0x0400;
+    cpu.regs.s = 0xFD;
+    cpu
+}
+
+// the P and return address an interrupt left on the stack
+fn frame(cpu: &Cpu) -> (u8, u16) {
+    let s = cpu.regs.s as u16;
+    let byte = |offset: u16| cpu.bus.peek(0x0100 | ((s + offset) & 0xFF));
+    (byte(1), u16::from_le_bytes([byte(2), byte(3)]))
+}
+
+#[test]
+fn nmi_fires_once_per_edge() {
+    let mut cpu = cpu(Variant::Nmos6502, &[0xEA, 0xEA, 0xEA]);
+    cpu.set_flag(Flag::I, true);
+
+    cpu.assert_nmi();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, NMI_HANDLER);
+    assert_eq!(frame(&cpu), (0x24, 0x0400));
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0400);
+
+    // still held, so no second interrupt
+    cpu.assert_nmi();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0401);
+
+    cpu.release_nmi();
+    cpu.assert_nmi();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, NMI_HANDLER);
+}
+
+#[test]
+fn irq_is_masked_and_level_triggered() {
+    let mut cpu = cpu(Variant::Nmos6502, &[0xEA, 0x58, 0xEA]);
+    cpu.set_flag(Flag::I, true);
+
+    cpu.assert_irq();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0401);
+    // CLI lets it through
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, IRQ_HANDLER);
+    assert!(cpu.get_flag(Flag::I));
+    assert_eq!(frame(&cpu), (0x20, 0x0402));
+
+    // RTI clears I again, and the line is still low
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0402);
+    cpu.step();
+    assert_eq!(cpu.regs.pc, IRQ_HANDLER);
+
+    cpu.release_irq();
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0403);
+}
+
+#[test]
+fn brk_pushes_b_set_past_its_padding_byte() {
+    let mut cpu = cpu(Variant::Nmos6502, &[0x00, 0xFF, 0xEA]);
+    cpu.set_flag(Flag::C, true);
+
+    cpu.step();
+    assert_eq!(cpu.regs.pc, IRQ_HANDLER);
+    assert_eq!(frame(&cpu), (0x31, 0x0402));
+    assert!(cpu.get_flag(Flag::I));
+
+    // RTI drops B and the unused bit
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0402);
+    assert_eq!(cpu.regs.p, Flag::C.mask());
+}
+
+#[test]
+fn cmos_interrupts_clear_decimal_mode() {
+    let mut nmos = cpu(Variant::Nmos6502, &[0xEA]);
+    let mut cmos = cpu(Variant::Wdc65C02, &[0xEA]);
+    for cpu in [&mut nmos, &mut cmos] {
+        cpu.set_flag(Flag::D, true);
+        cpu.assert_nmi();
+        cpu.step();
+    }
+    assert!(nmos.get_flag(Flag::D));
+    assert!(!cmos.get_flag(Flag::D));
+    // the pushed copy still has D set
+    assert_eq!(frame(&cmos).0, 0x28);
+}
+
+#[test]
+fn rti_restores_pc_and_p() {
+    let mut cpu = cpu(Variant::Nmos6502, &[0x40]);
+    cpu.push_word(0x1234);
+    cpu.push(0xFF);
+
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x1234);
+    assert_eq!(cpu.regs.p, 0xCF);
+    assert_eq!(cpu.regs.s, 0xFD);
+}
+
+#[test]
+fn undocumented_opcodes_combine_two_instructions() {
+    let mut cpu = cpu(
+        Variant::Nmos6502,
+        &[
+            0xA7, 0x10, // LAX $10
+            0x87, 0x11, // SAX $11
+            0xC7, 0x12, // DCP $12
+            0xE7, 0x13, // ISC $13
+            0x0B, 0x80, // ANC #$80
+            0x6B, 0xFF, // ARR #$FF
+        ],
+    );
+    cpu.bus.write(0x10, 0x85);
+    cpu.bus.write(0x12, 0x05);
+    cpu.bus.write(0x13, 0x0F);
+
+    cpu.step();
+    assert_eq!((cpu.regs.a, cpu.regs.x), (0x85, 0x85));
+    assert!(cpu.get_flag(Flag::N));
+
+    cpu.regs.a = 0xF0;
+    cpu.regs.x = 0x3C;
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x11), 0x30);
+
+    // decrements to 4, then compares equal
+    cpu.regs.a = 0x04;
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x12), 0x04);
+    assert!(cpu.get_flag(Flag::Z) && cpu.get_flag(Flag::C));
+
+    // increments to $10, then subtracts it with a borrow out
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x13), 0x10);
+    assert_eq!(cpu.regs.a, 0xF4);
+    assert!(!cpu.get_flag(Flag::C) && cpu.get_flag(Flag::N));
+
+    // C copies N after the AND
+    cpu.regs.a = 0xFF;
+    cpu.step();
+    assert_eq!(cpu.regs.a, 0x80);
+    assert!(cpu.get_flag(Flag::C) && cpu.get_flag(Flag::N));
+
+    // AND then ROR, with C from bit 6 and V from bits 6 and 5
+    cpu.regs.a = 0xC0;
+    cpu.step();
+    assert_eq!(cpu.regs.a, 0xE0);
+    assert!(cpu.get_flag(Flag::C) && !cpu.get_flag(Flag::V));
+}
+
+#[test]
+fn undocumented_nops_skip_their_operands() {
+    let mut cpu = cpu(
+        Variant::Nmos6502,
+        &[
+            0x80, 0xFF, // NOP #$FF
+            0x14, 0x10, // NOP $10,X
+            0x0C, 0x00, 0x20, // NOP $2000
+            0x1A, // NOP
+        ],
+    );
+    let a = cpu.regs.a;
+    for pc in [0x0402, 0x0404, 0x0407, 0x0408] {
+        cpu.step();
+        assert_eq!(cpu.regs.pc, pc);
+    }
+    assert_eq!(cpu.regs.a, a);
+}
+
+#[test]
+fn jam_locks_up_the_cpu() {
+    let mut cpu = cpu(Variant::Nmos6502, &[0xEA, 0x02, 0xEA]);
+    cpu.step();
+    cpu.step();
+    assert!(cpu.jammed);
+    assert_eq!(cpu.regs.pc, 0x0401);
+
+    // not even an NMI gets through
+    let cycles = cpu.cycles;
+    cpu.assert_nmi();
+    cpu.step();
+    assert_eq!((cpu.regs.pc, cpu.cycles), (0x0401, cycles));
+
+    // a reset does
+    cpu.reset();
+    assert!(!cpu.jammed);
+    assert_eq!(cpu.regs.pc, 0x0400);
+}
+
+#[test]
+fn cmos_adds_bra_stack_and_stz() {
+    let mut cpu = cpu(
+        Variant::Wdc65C02,
+        &[
+            0x80, 0x02, // BRA +2
+            0xEA, 0xEA, // skipped
+            0xDA, // PHX
+            0x7A, // PLY
+            0x64, 0x10, // STZ $10
+            0x9C, 0x00, 0x20, // STZ $2000
+        ],
+    );
+    cpu.regs.x = 0x42;
+    cpu.bus.write(0x10, 0xFF);
+    cpu.bus.write(0x2000, 0xFF);
+
+    let cycles = cpu.cycles;
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0404);
+    assert_eq!(cpu.cycles - cycles, 3);
+
+    cpu.step();
+    cpu.step();
+    assert_eq!((cpu.regs.y, cpu.regs.s), (0x42, 0xFD));
+
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x10), 0x00);
+    assert_eq!(cpu.bus.peek(0x2000), 0x00);
+}
+
+#[test]
+fn trb_and_tsb_set_z_from_the_old_value() {
+    let mut cpu = cpu(
+        Variant::Wdc65C02,
+        &[
+            0x04, 0x10, // TSB $10
+            0x14, 0x10, // TRB $10
+        ],
+    );
+    cpu.regs.a = 0x0F;
+    cpu.bus.write(0x10, 0xF0);
+
+    // no bits in common
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x10), 0xFF);
+    assert!(cpu.get_flag(Flag::Z));
+
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x10), 0xF0);
+    assert!(!cpu.get_flag(Flag::Z));
+    assert_eq!(cpu.regs.a, 0x0F);
+}
+
+#[test]
+fn bit_immediate_only_changes_z() {
+    let mut cpu = cpu(
+        Variant::Wdc65C02,
+        &[
+            0x89, 0x80, // BIT #$80
+            0x24, 0x10, // BIT $10
+        ],
+    );
+    cpu.regs.a = 0x01;
+    cpu.set_flag(Flag::N, true);
+    cpu.set_flag(Flag::V, true);
+
+    cpu.step();
+    assert!(cpu.get_flag(Flag::Z));
+    assert!(cpu.get_flag(Flag::N) && cpu.get_flag(Flag::V));
+
+    // the zero page form copies bits 7 and 6
+    cpu.bus.write(0x10, 0x01);
+    cpu.step();
+    assert!(!cpu.get_flag(Flag::Z));
+    assert!(!cpu.get_flag(Flag::N) && !cpu.get_flag(Flag::V));
+}
+
+#[test]
+fn zero_page_indirect_goes_through_the_pointer() {
+    let mut cpu = cpu(
+        Variant::Wdc65C02,
+        &[
+            0xB2, 0x20, // LDA ($20)
+            0x92, 0x22, // STA ($22)
+        ],
+    );
+    cpu.bus.write(0x20, 0x00);
+    cpu.bus.write(0x21, 0x30);
+    cpu.bus.write(0x22, 0x01);
+    cpu.bus.write(0x23, 0x30);
+    cpu.bus.write(0x3000, 0x77);
+
+    cpu.step();
+    assert_eq!(cpu.regs.a, 0x77);
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x3001), 0x77);
+}
+
+#[test]
+fn cmos_fixes_the_indirect_jump_bug() {
+    // JMP ($10FF)
+    let code = [0x6C, 0xFF, 0x10];
+    let mut nmos = cpu(Variant::Nmos6502, &code);
+    let mut cmos = cpu(Variant::Wdc65C02, &code);
+    for cpu in [&mut nmos, &mut cmos] {
+        cpu.bus.write(0x10FF, 0x34);
+        cpu.bus.write(0x1100, 0x12);
+        cpu.bus.write(0x1000, 0x56);
+        cpu.step();
+    }
+
+    // NMOS fetches the high byte from the start of the page
+    assert_eq!(nmos.regs.pc, 0x5634);
+    assert_eq!(cmos.regs.pc, 0x1234);
+    assert_eq!((nmos.cycles, cmos.cycles), (5, 6));
+}
+
+#[test]
+fn cmos_cycle_counts_differ() {
+    // (code, X, D, NMOS cycles, CMOS cycles)
+    let cases: [(&[u8], u8, bool, u64, u64); 5] = [
+        // LDA $20F0,X crossing a page
+        (&[0xBD, 0xF0, 0x20], 0x20, false, 5, 5),
+        // ASL $2000,X only pays for a crossing on CMOS
+        (&[0x1E, 0x00, 0x20], 0x01, false, 7, 6),
+        (&[0x1E, 0xF0, 0x20], 0x20, false, 7, 7),
+        // ADC #$01 and SBC #$01 take one more in decimal mode
+        (&[0x69, 0x01], 0x00, true, 2, 3),
+        (&[0xE9, 0x01], 0x00, true, 2, 3),
+    ];
+
+    for (code, x, decimal, nmos_cycles, cmos_cycles) in cases {
+        for (variant, expected) in [
+            (Variant::Nmos6502, nmos_cycles),
+            (Variant::Wdc65C02, cmos_cycles),
+        ] {
+            let mut cpu = cpu(variant, code);
+            cpu.regs.x = x;
+            cpu.set_flag(Flag::D, decimal);
+            cpu.step();
+            assert_eq!(cpu.cycles, expected, "{:02X?} on {:?}", code, variant);
+        }
+    }
+}
+
+#[test]
+fn wdc_bit_instructions_test_zero_page_bits() {
+    let mut cpu = cpu(
+        Variant::Wdc65C02,
+        &[
+            0x07, 0x10, // RMB0 $10
+            0xF7, 0x11, // SMB7 $11
+            0x0F, 0x10, 0x02, // BBR0 $10,+2
+            0xEA, 0xEA, // skipped
+            0x8F, 0x10, 0x7F, // BBS0 $10,+127
+            0xFF, 0x11, 0xF1, // BBS7 $11,-15
+        ],
+    );
+    cpu.bus.write(0x10, 0xFF);
+
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.bus.peek(0x10), 0xFE);
+    assert_eq!(cpu.bus.peek(0x11), 0x80);
+    assert_eq!(cpu.regs.p, 0x00);
+
+    // taken, one cycle more
+    let cycles = cpu.cycles;
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0409);
+    assert_eq!(cpu.cycles - cycles, 6);
+
+    let cycles = cpu.cycles;
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x040C);
+    assert_eq!(cpu.cycles - cycles, 5);
+
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0400);
+}
+
+#[test]
+fn wai_sleeps_until_an_interrupt() {
+    let mut cpu = cpu(Variant::Wdc65C02, &[0xCB, 0xEA, 0xEA]);
+    cpu.set_flag(Flag::I, true);
+    cpu.step();
+    assert!(cpu.waiting);
+
+    let cycles = cpu.cycles;
+    cpu.step();
+    assert_eq!((cpu.regs.pc, cpu.cycles), (0x0401, cycles));
+
+    // a masked IRQ wakes it without a handler
+    cpu.assert_irq();
+    cpu.step();
+    assert!(!cpu.waiting);
+    assert_eq!(cpu.regs.pc, 0x0402);
+
+    let mut cpu = self::cpu(Variant::Wdc65C02, &[0xCB, 0xEA]);
+    cpu.step();
+    cpu.assert_nmi();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, NMI_HANDLER);
+    assert_eq!(frame(&cpu).1, 0x0401);
+}
+
+#[test]
+fn stp_stops_until_a_reset() {
+    let mut cpu = cpu(Variant::Wdc65C02, &[0xDB, 0xEA]);
+    cpu.step();
+    assert!(cpu.jammed);
+
+    cpu.assert_nmi();
+    cpu.step();
+    assert_eq!(cpu.regs.pc, 0x0401);
+
+    cpu.reset();
+    assert!(!cpu.jammed);
+    assert_eq!(cpu.regs.pc, 0x0400);
+}
+
+// runs `code` (ending in the ADC or SBC) and returns A and P
+fn decimal(variant: Variant, code: &[u8]) -> (u8, u8) {
+    let mut cpu = cpu(variant, code);
+    while cpu.regs.pc < 0x0400 + code.len() as u16 {
+        cpu.step();
+    }
+    (cpu.regs.a, cpu.regs.p & !Flag::D.mask())
+}
+
+const N: u8 = 0x80;
+const V: u8 = 0x40;
+const Z: u8 = 0x02;
+const C: u8 = 0x01;
+
+#[test]
+fn decimal_mode_matches_bruce_clarks_examples() {
+    // SED; CLC; LDA #$99; ADC #$01
+    let code = [0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01];
+    // NMOS takes Z from the binary sum and N before the fixup
+    assert_eq!(decimal(Variant::Nmos6502, &code), (0x00, N | C));
+    assert_eq!(decimal(Variant::Wdc65C02, &code), (0x00, Z | C));
+
+    // SED; SEC; LDA #$79; ADC #$00 overflows into bit 7
+    let code = [0xF8, 0x38, 0xA9, 0x79, 0x69, 0x00];
+    assert_eq!(decimal(Variant::Nmos6502, &code), (0x80, N | V));
+
+    // SED; SEC; LDA #$46; SBC #$12
+    let code = [0xF8, 0x38, 0xA9, 0x46, 0xE9, 0x12];
+    assert_eq!(decimal(Variant::Nmos6502, &code), (0x34, C));
+
+    // SED; SEC; LDA #$00; SBC #$01 borrows, flags from the binary $FF
+    let code = [0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01];
+    assert_eq!(decimal(Variant::Nmos6502, &code), (0x99, N));
+
+    // SED; SEC; LDA #$80; SBC #$01: NMOS keeps V and N from $7F
+    let code = [0xF8, 0x38, 0xA9, 0x80, 0xE9, 0x01];
+    assert_eq!(decimal(Variant::Nmos6502, &code), (0x79, V | C));
+}
+
+#[test]
+fn ricoh_2a03_ignores_decimal_mode() {
+    // the same ADC and SBC come out as plain binary
+    let code = [0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01];
+    assert_eq!(decimal(Variant::Ricoh2A03, &code), (0x9A, N));
+
+    let code = [0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01];
+    assert_eq!(decimal(Variant::Ricoh2A03, &code), (0xFF, N));
+
+    // and without the extra cycle
+    let mut cpu = cpu(Variant::Ricoh2A03, &[0x69, 0x01]);
+    cpu.set_flag(Flag::D, true);
+    cpu.step();
+    assert_eq!(cpu.cycles, 2);
+}
@@ -0,0 +1,447 @@
+mod common;
+
+use std::fs;
+use std::io::{self, BufReader, PipeReader, PipeWriter, Write};
+use std::thread::{self, JoinHandle};
+
+use common::PROGRAM;
+use vanilla::system::util::dap::{read_message, write_message, DapServer};
+use vanilla::system::util::debugger::Debugger;
+use vanilla::system::util::json::Json;
+
+struct Client {
+    input: PipeWriter,
+    output: BufReader<PipeReader>,
+    seq: i64,
+    // events read while waiting for a response
+    events: Vec<Json>,
+    server: JoinHandle<()>,
+}
+
+impl Client {
+    // a server on another thread
+    fn new(debugger: fn() -> Option<Debugger>) -> Client {
+        let (server_input, input) = io::pipe().unwrap();
+        let (output, mut server_output) = {
+            let (reader, writer) = io::pipe().unwrap();
+            (BufReader::new(reader), writer)
+        };
+        let server = thread::spawn(move || {
+            DapServer::new(debugger())
+                .serve(Box::new(BufReader::new(server_input)), &mut server_output)
+                .unwrap();
+        });
+        Client {
+            input,
+            output,
+            seq: 1,
+            events: Vec::new(),
+            server,
+        }
+    }
+
+    fn read(&mut self) -> Json {
+        read_message(&mut self.output).unwrap().unwrap()
+    }
+
+    // the whole response, failed or not
+    fn send(&mut self, command: &str, arguments: Json) -> Json {
+        let seq = self.seq;
+        self.seq += 1;
+        let request = Json::object([
+            ("seq", Json::from(seq)),
+            ("type", Json::from("request")),
+            ("command", Json::from(command)),
+            ("arguments", arguments),
+        ]);
+        write_message(&mut self.input, &request).unwrap();
+        loop {
+            let message = self.read();
+            match message.get("type").and_then(Json::as_str) {
+                Some("response") => {
+                    assert_eq!(message.get("request_seq"), Some(&Json::from(seq)));
+                    return message;
+                }
+                _ => self.events.push(message),
+            }
+        }
+    }
+
+    // the body of a response that has to succeed
+    fn request(&mut self, command: &str, arguments: Json) -> Json {
+        let response = self.send(command, arguments);
+        assert_eq!(
+            response.get("success"),
+            Some(&Json::from(true)),
+            "{}",
+            response
+        );
+        response.get("body").cloned().unwrap_or(Json::Null)
+    }
+
+    // the body of the next `name` event, skipping any others
+    fn event(&mut self, name: &str) -> Json {
+        loop {
+            let message = match self.events.is_empty() {
+                true => self.read(),
+                false => self.events.remove(0),
+            };
+            if message.get("event").and_then(Json::as_str) == Some(name) {
+                return message.get("body").cloned().unwrap_or(Json::Null);
+            }
+        }
+    }
+
+    fn stopped(&mut self) -> String {
+        let body = self.event("stopped");
+        String::from(body.get("reason").and_then(Json::as_str).unwrap())
+    }
+
+    // each frame's name, line and address
+    fn frames(&mut self) -> Vec<(String, i64, String)> {
+        let body = self.request("stackTrace", Json::object([("threadId", Json::from(1))]));
+        let frames = body.get("stackFrames").and_then(Json::as_array).unwrap();
+        frames
+            .iter()
+            .map(|frame| {
+                let text = |name| String::from(frame.get(name).and_then(Json::as_str).unwrap());
+                let line = frame.get("line").and_then(Json::as_i64).unwrap();
+                (text("name"), line, text("instructionPointerReference"))
+            })
+            .collect()
+    }
+
+    fn finish(mut self) {
+        self.request("disconnect", Json::object([]));
+        self.server.join().unwrap();
+    }
+}
+
+fn text(value: &Json, path: &[&str]) -> String {
+    let mut value = value;
+    for name in path {
+        value = value.get(name).unwrap();
+    }
+    String::from(value.as_str().unwrap())
+}
+
+fn attached() -> Option<Debugger> {
+    Some(common::debugger(PROGRAM))
+}
+
+#[test]
+fn debugs_an_attached_program_by_source_line() {
+    let mut client = Client::new(attached);
+
+    let capabilities = client.request("initialize", Json::object([]));
+    assert_eq!(
+        capabilities.get("supportsConfigurationDoneRequest"),
+        Some(&Json::from(true))
+    );
+    client.event("initialized");
+    client.request("attach", Json::object([]));
+
+    // the editor has its own idea of where the file is
+    let source = Json::object([("path", Json::from("/work/src/test.s"))]);
+    let line = |line: i32| Json::object([("line", Json::from(line))]);
+    let body = client.request(
+        "setBreakpoints",
+        Json::object([
+            ("source", source.clone()),
+            ("breakpoints", Json::from(vec![line(10), line(12)])),
+        ]),
+    );
+    let breakpoints = body.get("breakpoints").and_then(Json::as_array).unwrap();
+    assert_eq!(breakpoints[0].get("verified"), Some(&Json::from(true)));
+    assert_eq!(text(&breakpoints[0], &["instructionReference"]), "0x0413");
+    assert_eq!(breakpoints[1].get("verified"), Some(&Json::from(false)));
+
+    client.request("configurationDone", Json::Null);
+    assert_eq!(client.stopped(), "entry");
+
+    client.request("continue", Json::object([("threadId", Json::from(1))]));
+    assert_eq!(client.stopped(), "breakpoint");
+    assert_eq!(
+        client.frames(),
+        [
+            (String::from("add3"), 10, String::from("0x0413")),
+            (String::from("start+2"), 3, String::from("0x0402")),
+        ]
+    );
+
+    let body = client.request(
+        "variables",
+        Json::object([("variablesReference", Json::from(1))]),
+    );
+    let registers = body.get("variables").and_then(Json::as_array).unwrap();
+    assert_eq!(text(&registers[0], &["name"]), "A");
+    assert_eq!(text(&registers[0], &["value"]), "$01");
+    let body = client.request(
+        "setVariable",
+        Json::object([
+            ("variablesReference", Json::from(1)),
+            ("name", Json::from("A")),
+            ("value", Json::from("$10")),
+        ]),
+    );
+    assert_eq!(text(&body, &["value"]), "$10");
+
+    client.request("next", Json::object([("threadId", Json::from(1))]));
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(client.frames()[0].1, 11);
+    client.request("stepOut", Json::object([("threadId", Json::from(1))]));
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(
+        client.frames(),
+        [(String::from("start+5"), 4, String::from("0x0405"))]
+    );
+
+    let body = client.request(
+        "evaluate",
+        Json::object([
+            ("expression", Json::from("a + 1")),
+            ("context", Json::from("watch")),
+        ]),
+    );
+    assert_eq!(text(&body, &["result"]), "$14 (20)");
+    // the console takes debugger commands
+    let body = client.request(
+        "evaluate",
+        Json::object([
+            ("expression", Json::from("regs")),
+            ("context", Json::from("repl")),
+        ]),
+    );
+    assert!(text(&body, &["result"]).starts_with("PC=0405 A=13"));
+
+    // taking out the breakpoints, it's stuck polling until paused
+    client.request(
+        "setBreakpoints",
+        Json::object([("source", source), ("breakpoints", Json::from(vec![]))]),
+    );
+    client.request("continue", Json::object([("threadId", Json::from(1))]));
+    client.request("pause", Json::object([("threadId", Json::from(1))]));
+    assert_eq!(client.stopped(), "pause");
+    let frames = client.frames();
+    assert!(frames[0].1 == 6 || frames[0].1 == 7, "{:?}", frames);
+
+    client.finish();
+}
+
+#[test]
+fn reads_writes_and_disassembles_memory() {
+    let mut client = Client::new(attached);
+    client.request("initialize", Json::object([]));
+    client.request("attach", Json::object([]));
+
+    let body = client.request(
+        "readMemory",
+        Json::object([
+            ("memoryReference", Json::from("start")),
+            ("count", Json::from(2)),
+        ]),
+    );
+    assert_eq!(text(&body, &["address"]), "0x0400");
+    assert_eq!(text(&body, &["data"]), "qQE=");
+
+    let body = client.request(
+        "writeMemory",
+        Json::object([
+            ("memoryReference", Json::from("0x0200")),
+            ("data", Json::from("SGk=")),
+        ]),
+    );
+    assert_eq!(body.get("bytesWritten"), Some(&Json::from(2)));
+    let body = client.request(
+        "readMemory",
+        Json::object([
+            ("memoryReference", Json::from("$0200")),
+            ("count", Json::from(3)),
+        ]),
+    );
+    assert_eq!(text(&body, &["data"]), "SGkA");
+
+    let body = client.request(
+        "disassemble",
+        Json::object([
+            ("memoryReference", Json::from("0x0415")),
+            ("instructionOffset", Json::from(-2)),
+            ("instructionCount", Json::from(3)),
+        ]),
+    );
+    let instructions = body.get("instructions").and_then(Json::as_array).unwrap();
+    let lines: Vec<(String, String)> = instructions
+        .iter()
+        .map(|i| (text(i, &["address"]), text(i, &["instruction"])))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (String::from("0x0412"), String::from("CLC")),
+            (String::from("0x0413"), String::from("ADC #$03")),
+            (String::from("0x0415"), String::from("RTS")),
+        ]
+    );
+    assert_eq!(text(&instructions[0], &["symbol"]), "add3");
+    assert_eq!(instructions[1].get("line"), Some(&Json::from(10)));
+
+    client.finish();
+}
+
+#[test]
+fn addresses_out_of_range_fail_cleanly() {
+    let mut client = Client::new(attached);
+    client.request("initialize", Json::object([]));
+    client.request("attach", Json::object([]));
+
+    let past_the_end = || {
+        Json::object([
+            ("memoryReference", Json::from("$7FFFFFFFFFFFFFFF")),
+            ("offset", Json::from(1)),
+            ("count", Json::from(1)),
+        ])
+    };
+    for command in ["readMemory", "disassemble"] {
+        let response = client.send(command, past_the_end());
+        assert_eq!(
+            response.get("success"),
+            Some(&Json::from(false)),
+            "{}",
+            command
+        );
+    }
+
+    let body = client.request(
+        "setInstructionBreakpoints",
+        Json::object([(
+            "breakpoints",
+            Json::from(vec![Json::object([
+                ("instructionReference", Json::from("$7FFFFFFFFFFFFFFF")),
+                ("offset", Json::from(1)),
+            ])]),
+        )]),
+    );
+    let breakpoints = body.get("breakpoints").and_then(Json::as_array).unwrap();
+    assert_eq!(breakpoints[0].get("verified"), Some(&Json::from(false)));
+
+    // no more instructions than there are bytes
+    let body = client.request(
+        "disassemble",
+        Json::object([
+            ("memoryReference", Json::from("start")),
+            ("instructionOffset", Json::from(-1_000_000_000_000_000i64)),
+            ("instructionCount", Json::from(1_000_000_000_000_000i64)),
+        ]),
+    );
+    let instructions = body.get("instructions").and_then(Json::as_array).unwrap();
+    assert_eq!(instructions.len(), 0x10000);
+
+    client.finish();
+}
+
+#[test]
+fn skips_messages_that_arent_json() {
+    let mut client = Client::new(attached);
+    let body = r#"{"seq": 1, "type": "request", "command": "threads",}"#;
+    write!(
+        client.input,
+        "Content-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    client.seq = 2;
+
+    // the next request still gets its answer
+    let body = client.request("threads", Json::object([]));
+    assert!(body.get("threads").is_some());
+    let output = client.event("output");
+    assert_eq!(text(&output, &["category"]), "stderr");
+    assert!(text(&output, &["output"]).contains("expected a field name"));
+
+    client.finish();
+}
+
+#[test]
+fn launches_a_program_from_files() {
+    let output = common::assemble(PROGRAM);
+    let dir = std::env::temp_dir().join(format!("vanilla-dap-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("test.bin");
+    let labels = dir.join("test.dbg");
+    fs::write(&program, &output.bytes).unwrap();
+    fs::write(&labels, output.debug_file()).unwrap();
+
+    let mut client = Client::new(|| None);
+    client.request("initialize", Json::object([]));
+    assert!(client
+        .send("attach", Json::object([]))
+        .get("message")
+        .is_some());
+    let response = client.send(
+        "launch",
+        Json::object([("program", Json::from("/no/such/file.bin"))]),
+    );
+    assert!(text(&response, &["message"]).starts_with("error reading"));
+
+    client.request(
+        "launch",
+        Json::object([
+            ("program", Json::from(program.to_str().unwrap())),
+            ("origin", Json::from("$0400")),
+            (
+                "labels",
+                Json::from(vec![Json::from(labels.to_str().unwrap())]),
+            ),
+            ("stopOnEntry", Json::from(true)),
+        ]),
+    );
+    // only stopping on the second call
+    let body = client.request(
+        "setFunctionBreakpoints",
+        Json::object([(
+            "breakpoints",
+            Json::from(vec![Json::object([
+                ("name", Json::from("add3")),
+                ("hitCondition", Json::from("2")),
+            ])]),
+        )]),
+    );
+    let breakpoints = body.get("breakpoints").and_then(Json::as_array).unwrap();
+    assert_eq!(text(&breakpoints[0], &["instructionReference"]), "0x0412");
+    client.request("configurationDone", Json::Null);
+    assert_eq!(client.stopped(), "entry");
+    assert_eq!(
+        client.frames(),
+        [(String::from("start"), 2, String::from("0x0400"))]
+    );
+
+    client.request("continue", Json::object([("threadId", Json::from(1))]));
+    assert_eq!(client.stopped(), "function breakpoint");
+    let body = client.request("evaluate", Json::object([("expression", Json::from("a"))]));
+    assert_eq!(text(&body, &["result"]), "$04 (4)");
+
+    client.request(
+        "setInstructionBreakpoints",
+        Json::object([(
+            "breakpoints",
+            Json::from(vec![Json::object([(
+                "instructionReference",
+                Json::from("wait"),
+            )])]),
+        )]),
+    );
+    client.request("continue", Json::object([("threadId", Json::from(1))]));
+    assert_eq!(client.stopped(), "instruction breakpoint");
+    let body = client.request(
+        "evaluate",
+        Json::object([
+            ("expression", Json::from("bt")),
+            ("context", Json::from("repl")),
+        ]),
+    );
+    assert_eq!(text(&body, &["result"]), "#0  $040A (wait)");
+
+    client.finish();
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,296 @@
+mod common;
+
+use std::io::Cursor;
+
+use vanilla::bus::{Access, Bus, Ram, WatchKind, Watched};
+use vanilla::system::cpu::{Flag, Variant};
+use vanilla::system::util::debugger::{CommandError, Debugger, Stop};
+use vanilla::system::util::expression::EvalError;
+
+// adds 3 to A twice through a subroutine, then traps
+const PROGRAM: &str = "        .org $0400
+start:  lda #1
+        jsr add3
+        jsr add3
+        sta $10
+done:   jmp done
+add3:   clc
+        adc #3
+        rts";
+
+fn debugger() -> Debugger {
+    common::debugger(PROGRAM)
+}
+
+#[test]
+fn steps_into_and_over_subroutines() {
+    let mut debugger = debugger();
+
+    debugger.command("step 2").unwrap();
+    assert_eq!(debugger.cpu.regs.pc, debugger.symbols.addr("add3").unwrap());
+    debugger.command("step 3").unwrap();
+    assert_eq!(debugger.cpu.regs.pc, 0x0405);
+
+    // back after the first JSR, now step over the second one
+    let text = debugger.command("next").unwrap();
+    assert_eq!(debugger.cpu.regs.pc, 0x0408);
+    assert_eq!(debugger.cpu.regs.a, 7);
+    assert!(text.contains("STA $10"), "{}", text);
+
+    assert_eq!(debugger.step_over(), Stop::Done);
+    assert_eq!(debugger.run(100, &|_| false), Stop::Trap);
+    assert_eq!(debugger.cpu.bus.peek(0x10), 7);
+}
+
+#[test]
+fn backtraces_show_each_call() {
+    let mut debugger = debugger();
+
+    debugger.command("step 3").unwrap();
+    assert_eq!(debugger.calls.len(), 1);
+    assert_eq!(
+        debugger.command("bt").unwrap(),
+        "#0  $040E (add3+1)\n#1  $0402 (start+2)"
+    );
+    // returning drops the call
+    debugger.command("step 2").unwrap();
+    assert!(debugger.calls.is_empty());
+    assert_eq!(debugger.command("bt").unwrap(), "#0  $0405 (start+5)");
+
+    // an IRQ taken just before a JSR isn't a call
+    let mut debugger = self::debugger();
+    debugger.command("step").unwrap();
+    debugger.cpu.assert_irq();
+    debugger.command("step").unwrap();
+    assert_eq!(debugger.cpu.regs.pc, 0x0000);
+    assert!(debugger.calls.is_empty());
+}
+
+#[test]
+fn images_reset_only_when_they_hold_the_vector() {
+    // ends at $FFFF, but starts past $FFFC
+    let debugger = Debugger::with_image(Variant::Nmos6502, &[0xEA, 0xEA], 0xFFFE, None);
+    assert_eq!(debugger.cpu.regs.pc, 0xFFFE);
+
+    let mut image = [0xEA; 0x10];
+    image[0x0C..0x0E].copy_from_slice(&[0x34, 0x12]);
+    let debugger = Debugger::with_image(Variant::Nmos6502, &image, 0xFFF0, None);
+    assert_eq!(debugger.cpu.regs.pc, 0x1234);
+}
+
+#[test]
+fn stops_at_breakpoints() {
+    let mut debugger = debugger();
+
+    let text = debugger.command("break add3+1").unwrap();
+    assert_eq!(text, "breakpoint 1 at $040E (add3+1)");
+    debugger.command("b done").unwrap();
+
+    let text = debugger.command("c").unwrap();
+    assert!(text.starts_with("breakpoint 1 at $040E"), "{}", text);
+    assert_eq!(debugger.cpu.regs.a, 1);
+    // an empty line repeats `c`, carrying on past the breakpoint
+    debugger.command("").unwrap();
+    assert_eq!(debugger.cpu.regs.pc, 0x040E);
+    assert_eq!(debugger.cpu.regs.a, 4);
+
+    debugger.command("delete 1").unwrap();
+    let text = debugger.command("c").unwrap();
+    assert!(text.starts_with("breakpoint 2 at $040A (done)"), "{}", text);
+    assert_eq!(
+        debugger.command("delete 1"),
+        Err(CommandError::NoBreakpoint(1))
+    );
+}
+
+#[test]
+fn edits_registers_flags_and_memory() {
+    let mut debugger = debugger();
+
+    debugger.command("set a $80").unwrap();
+    debugger.command("set c 1").unwrap();
+    debugger.command("set pc add3").unwrap();
+    let text = debugger.command("regs").unwrap();
+    assert_eq!(text, "PC=040D A=80 X=00 Y=00 S=FD P=01 [nv-bdizC] cycles=0");
+    assert!(debugger.cpu.get_flag(Flag::C));
+
+    let text = debugger.command("deposit $0200 'H' 'i' 0").unwrap();
+    assert_eq!(text, format!("0200  48 69 00{}  Hi.", " ".repeat(39)));
+    assert_eq!(debugger.cpu.bus.peek(0x0201), b'i');
+
+    assert_eq!(
+        debugger.command("set a $100"),
+        Err(CommandError::OutOfRange(0x100, "a register"))
+    );
+    assert!(matches!(
+        debugger.command("set q 1"),
+        Err(CommandError::UnknownRegister(_))
+    ));
+}
+
+#[test]
+fn lists_code_around_pc() {
+    let mut debugger = debugger();
+    debugger.command("step").unwrap();
+
+    let text = debugger.command("list").unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines.iter().position(|&line| line == "start:").unwrap();
+    assert_eq!(lines[start + 1], "  0400  A9 01     LDA #$01");
+    assert_eq!(lines[start + 2], "> 0402  20 0D 04  JSR add3");
+    assert!(lines.contains(&"done:"));
+}
+
+#[test]
+fn repl_runs_until_quit() {
+    let mut debugger = debugger();
+    let mut input = Cursor::new("bogus\ns\nq\nregs\n");
+    let mut output = Vec::new();
+    debugger.repl(&mut input, &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.contains("error: unknown command `bogus`"),
+        "{}",
+        output
+    );
+    assert!(output.contains("> 0402  20 0D 04  JSR add3"), "{}", output);
+    assert!(!output.contains("PC="), "{}", output);
+    assert!(debugger.is_done());
+}
+
+#[test]
+fn watched_bus_notes_matching_accesses() {
+    let mut bus = Watched::new(Ram::new());
+    let change = bus.watches.borrow_mut().add(0x10..=0x1F, WatchKind::Change);
+    let read = bus.watches.borrow_mut().add(0x20..=0x20, WatchKind::Read);
+
+    bus.write(0x10, 0);
+    bus.write(0x1F, 5);
+    bus.write(0x30, 5);
+    bus.read(0x20);
+    bus.peek(0x20);
+
+    let access = |id, kind, addr, old, new| Access {
+        id,
+        kind,
+        addr,
+        old,
+        new,
+    };
+    assert_eq!(
+        bus.watches.borrow().hits,
+        [
+            access(change, WatchKind::Change, 0x1F, 0, 5),
+            access(read, WatchKind::Read, 0x20, 0, 0),
+        ]
+    );
+}
+
+#[test]
+fn stops_after_a_watched_access() {
+    let mut debugger = debugger();
+
+    let text = debugger.command("watch change $10").unwrap();
+    assert_eq!(text, "watchpoint 1 on $0010");
+    // writing from the debugger doesn't count
+    debugger.command("deposit $10 1").unwrap();
+
+    let text = debugger.command("c").unwrap();
+    assert!(
+        text.starts_with("watchpoint 1 at $0408 (start+8): $0010 changed from $01 to $07\n"),
+        "{}",
+        text
+    );
+    assert_eq!(debugger.cpu.regs.pc, 0x040A);
+
+    debugger.command("unwatch 1").unwrap();
+    assert!(matches!(
+        debugger.command("watch write $20 $10"),
+        Err(CommandError::Usage(_))
+    ));
+    assert_eq!(debugger.command("watch").unwrap(), "no watchpoints");
+    debugger.command("set pc start").unwrap();
+    debugger.command("watch write $10 $1F").unwrap();
+    debugger.command("watch read add3 add3+4").unwrap();
+
+    // the opcode fetch of `clc` is a read too
+    let text = debugger.command("c").unwrap();
+    assert!(
+        text.starts_with("watchpoint 3 at $040D (add3): read $040D (add3) = $18"),
+        "{}",
+        text
+    );
+    debugger.command("unwatch 3").unwrap();
+    let text = debugger.command("c").unwrap();
+    assert!(
+        text.starts_with("watchpoint 2 at $0408 (start+8): wrote $07 to $0010, was $07"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn conditions_pick_which_hits_stop() {
+    let mut debugger = debugger();
+
+    let text = debugger.command("break add3 if a == #4").unwrap();
+    assert_eq!(text, "breakpoint 1 at $040D (add3) if a == #4");
+    let text = debugger.command("c").unwrap();
+    assert!(
+        text.starts_with("breakpoint 1 at $040D (add3)\n"),
+        "{}",
+        text
+    );
+    assert_eq!(debugger.cpu.regs.a, 4);
+    debugger.command("delete all").unwrap();
+
+    // no address: checked after every instruction, memory included
+    debugger.command("set pc start").unwrap();
+    debugger.command("deposit $10 0").unwrap();
+    debugger
+        .command("break if [$10] != 0 && cycles > 0 && !c")
+        .unwrap();
+    let text = debugger.command("c").unwrap();
+    assert!(text.starts_with("breakpoint 2 at $040A (done)"), "{}", text);
+
+    assert_eq!(
+        debugger.command("break if missing == 1"),
+        Err(CommandError::Eval(EvalError::Undefined(String::from(
+            "missing"
+        ))))
+    );
+    assert!(matches!(
+        debugger.command("break add3 if (a"),
+        Err(CommandError::Syntax(_))
+    ));
+}
+
+#[test]
+fn counts_ignores_and_logs_hits() {
+    let mut debugger = debugger();
+
+    debugger.command("break add3").unwrap();
+    let text = debugger.command("ignore 1 1").unwrap();
+    assert_eq!(
+        text,
+        "breakpoint 1 at $040D (add3), ignoring the next 1 hits"
+    );
+    debugger.command("log add3+3 if a > 1").unwrap();
+
+    let text = debugger.command("c").unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines[0],
+        "log 2 at $0410 (add3+3): PC=0410 A=04 X=00 Y=00 S=FB P=00 [nv-bdizc] cycles=12"
+    );
+    assert_eq!(lines[1], "breakpoint 1 at $040D (add3)");
+    assert_eq!(debugger.cpu.regs.a, 4);
+
+    let text = debugger.command("break").unwrap();
+    assert_eq!(
+        text,
+        "  1  breakpoint 1 at $040D (add3), hit 2 times\n  \
+         2  log 2 at $0410 (add3+3) if a > 1, hit 1 times"
+    );
+}
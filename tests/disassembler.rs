@@ -0,0 +1,182 @@
+mod common;
+
+use common::disassembler;
+use vanilla::bus::{Bus, Ram};
+use vanilla::system::cpu::{AddrMode, Cpu, Instruction, Variant};
+use vanilla::system::optable;
+use vanilla::system::util::disassembler::{Disassembler, Operand};
+
+#[test]
+fn decodes_structured_lines() {
+    let disassembler = disassembler(
+        &[0xA9, 0x01, 0x8D, 0x00, 0x20, 0xA7, 0x10, 0x4C, 0x00],
+        0x8000,
+    );
+    let lines: Vec<_> = disassembler.lines().collect();
+    assert_eq!(lines.len(), 4);
+
+    assert_eq!(lines[0].addr, 0x8000);
+    assert_eq!(lines[0].bytes, [0xA9, 0x01]);
+    assert_eq!(lines[0].op.instruction, Instruction::Lda);
+    assert_eq!(lines[0].op.address_mode, AddrMode::Imm);
+    assert_eq!(lines[0].operand, Operand::Byte(0x01));
+    assert_eq!(lines[0].next_addr(), 0x8002);
+    assert_eq!(lines[0].to_string(), "8000  A9 01     LDA #$01");
+
+    assert_eq!(lines[1].operand, Operand::Word(0x2000));
+    assert_eq!(lines[1].text, "STA $2000");
+    assert_eq!(lines[2].text, "*LAX $10");
+
+    // the data runs out in the middle of the last one
+    assert!(lines[3].is_truncated());
+    assert_eq!(lines[3].bytes, [0x4C, 0x00]);
+    assert_eq!(lines[3].text, "JMP <truncated: 2 of 3 bytes>");
+    assert_eq!(lines[3].target(), None);
+}
+
+#[test]
+fn ranges_cover_instructions_starting_inside_them() {
+    let disassembler = disassembler(&[0xEA, 0x8D, 0x00, 0x20, 0xEA, 0xEA], 0x8000);
+    let lines: Vec<u16> = disassembler.range(0x8001..0x8002).map(|l| l.addr).collect();
+    assert_eq!(lines, [0x8001]);
+    let lines: Vec<u16> = disassembler.range(..=0x8004).map(|l| l.addr).collect();
+    assert_eq!(lines, [0x8000, 0x8001, 0x8004]);
+    // clamped to the data
+    let lines: Vec<u16> = disassembler.range(0x7000..).map(|l| l.addr).collect();
+    assert_eq!(lines, [0x8000, 0x8001, 0x8004, 0x8005]);
+    assert_eq!(disassembler.range(0x9000..).count(), 0);
+    assert!(disassembler.decode(0x7FFF).is_none());
+
+    // decoding in the middle of an instruction just carries on
+    let line = disassembler.decode(0x8002).unwrap();
+    assert_eq!(line.op.instruction, Instruction::Brk);
+}
+
+#[test]
+fn data_ending_at_ffff_stops_cleanly() {
+    let disassembler = disassembler(&[0xEA, 0xEA, 0x20, 0x00], 0xFFFC);
+    let lines: Vec<_> = disassembler.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[2].addr, 0xFFFE);
+    assert!(lines[2].is_truncated());
+}
+
+#[test]
+fn around_syncs_up_before_the_address() {
+    // a lone step back from $8002 would land on the LDA's operand
+    let short = disassembler(&[0xA9, 0xEA, 0xEA, 0xEA], 0x8000);
+    let lines: Vec<String> = short
+        .around(0x8002, 1, 1)
+        .iter()
+        .map(|line| line.text.clone())
+        .collect();
+    assert_eq!(lines, ["LDA #$EA", "NOP", "NOP"]);
+
+    let longer = disassembler(&[0xA9, 0x01, 0x8D, 0x00, 0x20, 0xEA, 0xEA], 0x8000);
+    let addrs: Vec<u16> = longer
+        .around(0x8005, 5, 0)
+        .iter()
+        .map(|line| line.addr)
+        .collect();
+    assert_eq!(addrs, [0x8000, 0x8002, 0x8005]);
+    // nothing before the start of the data
+    assert_eq!(longer.around(0x8000, 3, 0).len(), 1);
+}
+
+#[test]
+fn disassembles_live_memory() {
+    let mut ram = Ram::new();
+    ram.load(0x0400, &[0xA9, 0x01, 0xEA]);
+    let mut cpu = Cpu::new(Box::new(ram));
+
+    let optable = optable::optable(Variant::Nmos6502);
+    let text = |cpu: &Cpu| {
+        let disassembler = Disassembler::with_memory(&*cpu.bus, optable);
+        disassembler.decode(0x0400).unwrap().text
+    };
+    assert_eq!(text(&cpu), "LDA #$01");
+    cpu.bus.write(0x0401, 0x02);
+    assert_eq!(text(&cpu), "LDA #$02");
+}
+
+#[test]
+fn branch_targets_wrap_around_memory() {
+    let forward = disassembler(&[0xD0, 0x10], 0xFFFE);
+    let line = forward.decode(0xFFFE).unwrap();
+    assert_eq!(line.target(), Some(0x0010));
+    assert_eq!(line.text, "BNE $0010");
+
+    let backward = disassembler(&[0xF0, 0xFC], 0x0000);
+    assert_eq!(backward.decode(0x0000).unwrap().target(), Some(0xFFFE));
+
+    let jump = disassembler(&[0x4C, 0x34, 0x12], 0x0000);
+    assert_eq!(jump.decode(0x0000).unwrap().target(), Some(0x1234));
+}
+
+#[test]
+fn resolves_effective_addresses() {
+    let mut ram = Ram::new();
+    // ($FF) takes its high byte from $00, not $0100
+    ram.load(0x0000, &[0x12, 0x80]);
+    ram.load(0x00FF, &[0x34, 0x99]);
+    ram.load(0x1236, &[0x55]);
+    ram.load(0x1234, &[0x66]);
+    ram.load(0x8012, &[0x77]);
+    ram.load(0x10FF, &[0x00]);
+    ram.load(0x1000, &[0x20]);
+    let mut cpu = Cpu::new(Box::new(ram));
+    cpu.regs.x = 0x10;
+    cpu.regs.y = 0x02;
+
+    let cases: [(&[u8], &str, u16, Option<u8>); 5] = [
+        (
+            &[0xB1, 0xFF],
+            "LDA ($FF),Y @ $1236 = #$55",
+            0x1236,
+            Some(0x55),
+        ),
+        (
+            &[0xA1, 0xEF],
+            "LDA ($EF,X) @ $1234 = #$66",
+            0x1234,
+            Some(0x66),
+        ),
+        // $F0 + X wraps to the pointer at $00
+        (
+            &[0xA1, 0xF0],
+            "LDA ($F0,X) @ $8012 = #$77",
+            0x8012,
+            Some(0x77),
+        ),
+        (
+            &[0xB5, 0xF6],
+            "LDA $F6,X @ $0006 = #$00",
+            0x0006,
+            Some(0x00),
+        ),
+        // the NMOS JMP ($xxFF) bug, and no value for a jump
+        (&[0x6C, 0xFF, 0x10], "JMP ($10FF) @ $2000", 0x2000, None),
+    ];
+    for (bytes, text, addr, value) in cases {
+        let line = disassembler(bytes, 0x0400).decode(0x0400).unwrap();
+        let effective = line.effective(&cpu).unwrap();
+        assert_eq!((effective.addr, effective.value), (addr, value), "{}", text);
+        assert_eq!(line.annotated(&cpu), text);
+    }
+
+    // nothing to resolve
+    for bytes in [
+        &[0xA9, 0x01][..],
+        &[0x4C, 0x00, 0x20],
+        &[0xD0, 0x00],
+        &[0xEA],
+    ] {
+        let line = disassembler(bytes, 0x0400).decode(0x0400).unwrap();
+        assert_eq!(line.effective(&cpu), None, "{}", line.text);
+        assert_eq!(line.annotated(&cpu), line.text);
+    }
+
+    // plain addresses only get the value
+    let line = disassembler(&[0xA5, 0x01], 0x0400).decode(0x0400).unwrap();
+    assert_eq!(line.annotated(&cpu), "LDA $01 = #$80");
+}
@@ -0,0 +1,23 @@
+use std::fs;
+
+use vanilla::system::cpu::Variant;
+use vanilla::system::util::functional_test::{FunctionalTest, Outcome};
+
+fn run(variant: Variant) {
+    let image = fs::read("resources/6502_functional_test.bin").unwrap();
+
+    let mut test = FunctionalTest::new(image);
+    test.variant = variant;
+    let (report, _) = test.run();
+    assert_eq!(report.outcome, Outcome::Passed, "{:?}", report);
+}
+
+#[test]
+fn dormann_functional_test() {
+    run(Variant::Nmos6502);
+}
+
+#[test]
+fn dormann_functional_test_65c02() {
+    run(Variant::Wdc65C02);
+}
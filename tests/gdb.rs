@@ -0,0 +1,185 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use common::PROGRAM;
+use vanilla::system::util::gdb::{Connection, GdbServer};
+
+fn server() -> GdbServer {
+    GdbServer::new(common::debugger(PROGRAM))
+}
+
+// serves one client on another thread, as the debugger isn't Send
+fn spawn<S: Connection + Send + 'static>(
+    accept: impl FnOnce() -> S + Send + 'static,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut stream = accept();
+        server().serve(&mut stream).unwrap();
+    })
+}
+
+struct Client<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> Client<S> {
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send_raw(&mut self, packet: &[u8]) -> u8 {
+        self.stream.write_all(packet).unwrap();
+        self.byte()
+    }
+
+    // sends a packet and returns the reply, acking both ways
+    fn send(&mut self, data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let ack = self.send_raw(format!("${}#{:02x}", data, sum).as_bytes());
+        assert_eq!(ack, b'+', "{}", data);
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.byte(), self.byte()];
+        let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(
+            checksum,
+            data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+}
+
+fn connect() -> (Client<TcpStream>, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream
+    });
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    (Client { stream }, handle)
+}
+
+#[test]
+fn reads_and_writes_registers_and_memory() {
+    let (mut client, handle) = connect();
+
+    let supported = client.send("qSupported:multiprocess+;xmlRegisters=i386");
+    assert!(supported.contains("qXfer:features:read+"), "{}", supported);
+    let xml = client.send("qXfer:features:read:target.xml:0,1000");
+    assert!(xml.starts_with("l<?xml"), "{}", xml);
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16""#), "{}", xml);
+    // a short read says there's more
+    assert_eq!(client.send("qXfer:features:read:target.xml:0,5"), "m<?xml");
+
+    assert_eq!(client.send("?"), "S05");
+    assert_eq!(client.send("G01020304051204"), "OK");
+    assert_eq!(client.send("p5"), "1204");
+    assert_eq!(client.send("P0=ff"), "OK");
+    assert_eq!(client.send("g"), "ff020304051204");
+    assert_eq!(client.send("p9"), "E01");
+
+    assert_eq!(client.send("m400,2"), "a901");
+    assert_eq!(client.send("M200,2:4869"), "OK");
+    assert_eq!(client.send("m200,3"), "486900");
+    assert_eq!(client.send("M200,2:48"), "E01");
+    assert_eq!(client.send("vMustReplyEmpty"), "");
+
+    // a bad checksum gets a `-` and no reply
+    assert_eq!(client.send_raw(b"$g#00"), b'-');
+    assert_eq!(client.send("D"), "OK");
+    handle.join().unwrap();
+}
+
+#[test]
+fn stops_at_breakpoints_watchpoints_and_interrupts() {
+    let (mut client, handle) = connect();
+
+    assert_eq!(client.send("Z0,412,1"), "OK");
+    assert_eq!(client.send("c"), "S05");
+    assert_eq!(client.send("p5"), "1204");
+    assert_eq!(client.send("z0,412,1"), "OK");
+    assert_eq!(client.send("s"), "S05");
+    assert_eq!(client.send("p5"), "1304");
+
+    // inserted twice, removed once, so the second JSR
+    // doesn't stop on the return address it pushes
+    assert_eq!(client.send("Z2,1fc,2"), "OK");
+    assert_eq!(client.send("Z2,1fc,2"), "OK");
+    assert_eq!(client.send("z2,1fc,2"), "OK");
+
+    assert_eq!(client.send("Z2,10,1"), "OK");
+    assert_eq!(client.send("vCont;c"), "T05watch:0010;");
+    assert_eq!(client.send("g"), "070000fd000a04");
+    assert_eq!(client.send("?"), "T05watch:0010;");
+    assert_eq!(client.send("z2,10,1"), "OK");
+
+    // now stuck polling $2002, until interrupted
+    let sum = b'c';
+    client
+        .stream
+        .write_all(format!("$c#{:02x}", sum).as_bytes())
+        .unwrap();
+    assert_eq!(client.byte(), b'+');
+    thread::sleep(Duration::from_millis(50));
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+
+    client.stream.write_all(b"$k#6b").unwrap();
+    assert_eq!(client.byte(), b'+');
+    handle.join().unwrap();
+}
+
+#[test]
+fn stops_acking_when_asked() {
+    let (mut client, handle) = connect();
+
+    assert_eq!(client.send("QStartNoAckMode"), "OK");
+    client.stream.write_all(b"$p5#a5").unwrap();
+    let mut reply = [0; 8];
+    client.stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"$0004#c4");
+
+    client.stream.write_all(b"$D#44").unwrap();
+    client.stream.read_exact(&mut reply[..6]).unwrap();
+    assert_eq!(&reply[..6], b"$OK#9a");
+    handle.join().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn serves_a_unix_socket() {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = std::env::temp_dir().join(format!("vanilla-gdb-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let handle = spawn(move || listener.accept().unwrap().0);
+
+    let mut client = Client {
+        stream: UnixStream::connect(&path).unwrap(),
+    };
+    assert_eq!(client.send("m400,1"), "a9");
+    assert_eq!(client.send("D"), "OK");
+    handle.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,162 @@
+use std::fs;
+use std::path::PathBuf;
+
+use vanilla::system::cpu::{AddrMode, Instruction};
+use vanilla::system::util::instr_set_parser::{InstrSetParser, ParseErrorKind};
+
+const HEADER: &str = "opcode,mnemonic,addressing mode,bytes,cycles,flags\n";
+
+// writes `rows` to a file of its own under the temp dir
+fn file(name: &str, rows: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vanilla-isp-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, format!("{}{}", HEADER, rows)).unwrap();
+    path
+}
+
+fn parser(name: &str, rows: &str) -> InstrSetParser {
+    InstrSetParser::new(file(name, rows).to_str().unwrap())
+}
+
+#[test]
+fn errors_point_at_the_field() {
+    let e = parser(
+        "mnemonic.csv",
+        "0xA9,LDA,IMM,2,2,cZidbvN\n0xAD,LDQ,ABS,3,4,cZidbvN\n",
+    )
+    .parse()
+    .err()
+    .unwrap();
+    assert_eq!(
+        e.kind,
+        ParseErrorKind::UnknownInstruction(String::from("LDQ"))
+    );
+    assert_eq!((e.line, e.column), (3, 6));
+    assert!(
+        e.to_string()
+            .ends_with("\n    0xAD,LDQ,ABS,3,4,cZidbvN\n         ^"),
+        "{}",
+        e
+    );
+
+    let e = parser("mode.csv", "0xAD,LDA,ABSZ,3,4,cZidbvN\n")
+        .parse()
+        .err()
+        .unwrap();
+    assert_eq!(
+        e.kind,
+        ParseErrorKind::UnknownAddrMode(String::from("ABSZ"))
+    );
+    assert_eq!((e.line, e.column), (2, 10));
+
+    let e = parser("cycles.csv", "0xAD,LDA,ABS,3,four,cZidbvN\n")
+        .parse()
+        .err()
+        .unwrap();
+    assert_eq!(e.kind, ParseErrorKind::InvalidCycles(String::from("four")));
+    assert_eq!((e.line, e.column), (2, 16));
+
+    let e = parser("fields.csv", "0xAD,LDA,ABS,3,4\n")
+        .parse()
+        .err()
+        .unwrap();
+    assert_eq!(e.kind, ParseErrorKind::FieldCount(5));
+    assert_eq!((e.line, e.column), (2, 0));
+}
+
+#[test]
+fn lengths_must_match_the_mode() {
+    let e = parser("bytes.csv", "0xAD,LDA,ABS,2,4,cZidbvN\n")
+        .parse()
+        .err()
+        .unwrap();
+    assert_eq!(e.kind, ParseErrorKind::BytesMismatch(AddrMode::Abs, 2));
+    assert_eq!((e.line, e.column), (2, 14));
+    assert!(e
+        .to_string()
+        .contains("ABS instructions are 3 bytes long, not 2"));
+
+    let e = parser("count.csv", "0xAD,LDA,ABS,three,4,cZidbvN\n")
+        .parse()
+        .err()
+        .unwrap();
+    assert_eq!(e.kind, ParseErrorKind::InvalidBytes(String::from("three")));
+
+    let optable = parser("flags.csv", "0x69,ADC,IMM,2,2,CZidbVN\n")
+        .parse()
+        .unwrap();
+    assert_eq!(optable[0x69].info.bytes, 2);
+    assert_eq!(optable[0x69].info.flags, 0xC3);
+}
+
+#[test]
+fn branch_cycles_drop_the_taken_count() {
+    let optable = parser("branch.csv", "0xD0,BNE,REL,2,2/3,czidbvn\n")
+        .parse()
+        .unwrap();
+    assert_eq!(optable[0xD0].cycles, 2);
+    assert_eq!(optable[0xD0].info.instruction, Instruction::Bne);
+}
+
+#[test]
+fn duplicates_fail_strict_parses() {
+    let rows = "0xEA,NOP,IMP,1,2,czidbvn\n0xEA,INX,IMP,1,2,cZidbvN\n";
+    let e = parser("duplicate.csv", rows).parse().err().unwrap();
+    assert_eq!(e.kind, ParseErrorKind::DuplicateOpcode(0xEA, 2));
+    assert_eq!((e.line, e.column), (3, 1));
+
+    // a later file may override an earlier one
+    let mut optable = parser("base.csv", "0xEA,NOP,IMP,1,2,czidbvn\n")
+        .parse()
+        .unwrap();
+    parser("overlay.csv", "0xEA,INX,IMP,1,2,cZidbvN\n")
+        .parse_into(&mut optable)
+        .unwrap();
+    assert_eq!(optable[0xEA].info.instruction, Instruction::Inx);
+}
+
+#[test]
+fn lenient_parses_collect_warnings() {
+    let path = file(
+        "lenient.csv",
+        "0xA9,LDA,IMM,2,2,cZidbvN\n\
+         0xAD,LDQ,ABS,3,4,cZidbvN\n\
+         0xEA,NOP,IMP,1,2,czidbvn\n\
+         0xEA,INX,IMP,1,2,cZidbvN\n\
+         0xE8,INX,IMP,1,2,cZidbvX\n",
+    );
+    let mut parser = InstrSetParser::lenient(path.to_str().unwrap());
+    let optable = parser.parse().unwrap();
+
+    let warnings: Vec<(usize, ParseErrorKind)> = parser
+        .warnings
+        .iter()
+        .map(|w| (w.line, w.kind.clone()))
+        .collect();
+    assert_eq!(
+        warnings,
+        [
+            (3, ParseErrorKind::UnknownInstruction(String::from("LDQ"))),
+            (5, ParseErrorKind::DuplicateOpcode(0xEA, 4)),
+            (6, ParseErrorKind::InvalidFlags(String::from("cZidbvX"))),
+        ]
+    );
+    // good lines still count, and the duplicate wins
+    assert_eq!(optable[0xA9].info.instruction, Instruction::Lda);
+    assert_eq!(optable[0xA9].info.address_mode, AddrMode::Imm);
+    assert_eq!(optable[0xEA].info.instruction, Instruction::Inx);
+    // skipped ones are left as the default NOP
+    assert_eq!(optable[0xAD].info.instruction, Instruction::Nop);
+    assert!(optable[0xAD].info.undocumented);
+}
+
+#[test]
+fn missing_files_are_reported() {
+    let e = InstrSetParser::new("/no/such/ops.csv")
+        .parse()
+        .err()
+        .unwrap();
+    assert!(matches!(e.kind, ParseErrorKind::Io(_)));
+    assert_eq!(e.line, 0);
+}
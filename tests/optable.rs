@@ -0,0 +1,98 @@
+use vanilla::system::cpu::{AddrMode, Flag, Instruction, Variant};
+use vanilla::system::optable;
+use vanilla::system::util::instr_set_parser::InstrSetParser;
+
+// the tables generated by build.rs must decode exactly
+// like the ones parsed from the same files at runtime
+fn check(variant: Variant, files: &[&str]) {
+    let (base, overlays) = files.split_first().unwrap();
+    let mut parsed = InstrSetParser::new(base).parse().unwrap();
+    for overlay in overlays {
+        InstrSetParser::new(overlay)
+            .parse_into(&mut parsed)
+            .unwrap();
+    }
+
+    for (opcode, (built, parsed)) in optable::optable(variant).iter().zip(&parsed).enumerate() {
+        assert_eq!(built.info, parsed.info, "opcode {:#04x}", opcode);
+        assert_eq!(built.cycles, parsed.cycles, "opcode {:#04x}", opcode);
+    }
+}
+
+#[test]
+fn nmos_table_matches_csv() {
+    check(
+        Variant::Nmos6502,
+        &["resources/6502ops.csv", "resources/6502undoc.csv"],
+    );
+}
+
+#[test]
+fn cmos_table_matches_csv() {
+    check(
+        Variant::Wdc65C02,
+        &["resources/6502ops.csv", "resources/65c02ops.csv"],
+    );
+}
+
+#[test]
+fn looks_up_opcodes_both_ways() {
+    let info = optable::info(Variant::Nmos6502, 0xAD);
+    assert_eq!(info.instruction, Instruction::Lda);
+    assert_eq!(info.address_mode, AddrMode::Abs);
+    assert_eq!(info.bytes, 3);
+    assert!(info.affects(Flag::Z) && info.affects(Flag::N) && !info.affects(Flag::C));
+    assert!(!info.undocumented);
+    assert!(optable::info(Variant::Nmos6502, 0xA7).undocumented);
+
+    let nmos = optable::optable(Variant::Nmos6502);
+    assert_eq!(
+        optable::opcode_for(nmos, Instruction::Lda, AddrMode::Abs),
+        Some(0xAD)
+    );
+    assert_eq!(
+        optable::opcode_for_mnemonic(nmos, "LDA", AddrMode::Abs),
+        Some(0xAD)
+    );
+    assert_eq!(
+        optable::opcode_for_mnemonic(nmos, "LDQ", AddrMode::Abs),
+        None
+    );
+    // the documented NOP over the undocumented copies
+    assert_eq!(
+        optable::opcode_for(nmos, Instruction::Nop, AddrMode::Imp),
+        Some(0xEA)
+    );
+    assert_eq!(
+        optable::opcode_for(nmos, Instruction::Lda, AddrMode::Zpi),
+        None
+    );
+
+    let cmos = optable::optable(Variant::Wdc65C02);
+    assert_eq!(
+        optable::opcode_for(cmos, Instruction::Lda, AddrMode::Zpi),
+        Some(0xB2)
+    );
+}
+
+#[test]
+fn lists_the_modes_of_an_instruction() {
+    let nmos = optable::optable(Variant::Nmos6502);
+    let mut modes = optable::modes_for(nmos, Instruction::Ldx);
+    modes.sort_by_key(|mode| mode.name());
+    assert_eq!(
+        modes,
+        [
+            AddrMode::Abs,
+            AddrMode::Absy,
+            AddrMode::Imm,
+            AddrMode::Zp,
+            AddrMode::Zpy
+        ]
+    );
+    assert_eq!(
+        optable::modes_for(nmos, Instruction::Jmp),
+        [AddrMode::Abs, AddrMode::Ind]
+    );
+    assert!(optable::modes_for(nmos, Instruction::Stz).is_empty());
+}
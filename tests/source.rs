@@ -0,0 +1,117 @@
+mod common;
+
+use common::assemble;
+use vanilla::system::cpu::Variant;
+use vanilla::system::optable;
+use vanilla::system::util::code_map::CodeMap;
+use vanilla::system::util::disassembler::Disassembler;
+use vanilla::system::util::source::{self, Dialect};
+
+// code each dialect needs to write its own way: an absolute operand
+// below $100, zero page, pointers, and an undocumented opcode
+const IMAGE: &[u8] = &[
+    0xAD, 0x10, 0x00, // LDA $0010
+    0xA5, 0x10, // LDA $10
+    0xB1, 0x20, // LDA ($20),Y
+    0x20, 0x0E, 0x80, // JSR $800E
+    0x6C, 0x34, 0x12, // JMP ($1234)
+    0xFF, // data
+    0xA7, 0x10, // LAX $10
+    0x9D, 0x05, 0x00, // STA $0005,X
+    0x60, // RTS
+];
+
+fn render(dialect: Dialect) -> String {
+    let optable = optable::optable(Variant::Nmos6502);
+    let disassembler = Disassembler::with_origin(IMAGE.to_vec(), optable, 0x8000);
+    let map = CodeMap::trace(&disassembler, &[0x8000]);
+    source::render(&map.listing(&disassembler), dialect)
+}
+
+#[test]
+fn asm6_writes_forced_absolute_operands_as_bytes() {
+    let text = render(Dialect::Asm6);
+    assert_eq!(
+        text,
+        "        .org $8000
+
+sub_8000:
+        .db $AD, $10, $00 ; LDA $0010
+        LDA $10
+        LDA ($20),Y
+        JSR sub_800E
+        JMP ($1234)
+        .db $FF
+
+sub_800E:
+        .db $A7, $10 ; *LAX $10
+        .db $9D, $05, $00 ; STA $0005,X
+        RTS
+"
+    );
+
+    // the built-in assembler takes the same subset
+    let output = assemble(&text);
+    assert_eq!(output.origin, 0x8000);
+    assert_eq!(output.bytes, IMAGE);
+}
+
+#[test]
+fn ca65_forces_sizes_and_names_the_segment() {
+    let text = render(Dialect::Ca65);
+    assert_eq!(
+        text,
+        "        .setcpu \"6502\"
+        .segment \"CODE\"
+        .org $8000
+
+sub_8000:
+        LDA a:$0010
+        LDA $10
+        LDA ($20),Y
+        JSR sub_800E
+        JMP ($1234)
+        .byte $FF
+
+sub_800E:
+        .byte $A7, $10 ; *LAX $10
+        STA a:$0005,X
+        RTS
+"
+    );
+
+    let output = assemble(&text);
+    assert_eq!(output.bytes, IMAGE);
+}
+
+#[test]
+fn nesasm_marks_zero_page_and_uses_brackets() {
+    assert_eq!(
+        render(Dialect::Nesasm),
+        "        .code
+        .bank 0
+        .org $8000
+
+sub_8000:
+        LDA $0010
+        LDA <$10
+        LDA [$20],Y
+        JSR sub_800E
+        JMP [$1234]
+        .db $FF
+
+sub_800E:
+        .db $A7, $10 ; *LAX $10
+        STA $0005,X
+        RTS
+"
+    );
+}
+
+#[test]
+fn dialects_parse_by_name() {
+    assert_eq!("CA65".parse(), Ok(Dialect::Ca65));
+    assert_eq!("asm6".parse(), Ok(Dialect::Asm6));
+    assert_eq!("NESasm".parse(), Ok(Dialect::Nesasm));
+    assert!("tass".parse::<Dialect>().is_err());
+}
@@ -0,0 +1,162 @@
+use vanilla::system::cpu::Variant;
+use vanilla::system::optable;
+use vanilla::system::util::assembler::Assembler;
+use vanilla::system::util::disassembler::Disassembler;
+use vanilla::system::util::symbols::{LabelErrorKind, SymbolTable};
+
+fn parse(text: &str) -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    match symbols.parse("labels", text) {
+        Ok(()) => symbols,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn reads_every_format() {
+    let plain = parse("; from the assembler\nreset = $C000\nvblank = 0xC010\n");
+    assert_eq!(plain.addr("reset"), Some(0xC000));
+    assert_eq!(plain.name(0xC010), Some("vblank"));
+
+    let vice = parse("al 00C000 .reset\nal C:c010 .vblank\nbreak c000\n");
+    assert_eq!(vice.addr("reset"), Some(0xC000));
+    assert_eq!(vice.name(0xC010), Some("vblank"));
+
+    let fceux = parse("$C000#reset#entry point\n$0200/10#buffer#\n");
+    assert_eq!(fceux.name(0xC000), Some("reset"));
+    assert_eq!(fceux.describe(0x020F).as_deref(), Some("buffer+15"));
+    assert_eq!(fceux.describe(0x0210), None);
+
+    let dbg = parse(
+        "version\tmajor=2,minor=0\n\
+         sym\tid=0,name=\"COUNT\",addrsize=zeropage,scope=0,def=1,val=0xC000,type=equ\n\
+         sym\tid=1,name=\"reset\",addrsize=absolute,scope=0,def=2,val=0xC000,seg=0,type=lab\n\
+         sym\tid=2,name=\"@loop\",addrsize=absolute,parent=1,def=3,val=0xC003,seg=0,type=lab\n\
+         sym\tid=3,name=\"table\",addrsize=absolute,size=4,scope=0,def=4,val=0xC010,type=lab\n\
+         sym\tid=4,name=\"external\",addrsize=absolute,scope=0,def=5,type=imp\n",
+    );
+    // the label wins over the equate with the same value
+    assert_eq!(dbg.name(0xC000), Some("reset"));
+    assert_eq!(dbg.addr("COUNT"), Some(0xC000));
+    assert_eq!(dbg.addr("reset@loop"), Some(0xC003));
+    assert_eq!(dbg.describe(0xC013).as_deref(), Some("table+3"));
+    assert_eq!(dbg.describe(0xC014), None);
+    assert_eq!(dbg.addr("external"), None);
+}
+
+#[test]
+fn describes_addresses_near_a_symbol() {
+    let symbols = parse("zp = $00\nstack = $0100\n");
+    assert_eq!(symbols.describe(0x0000).as_deref(), Some("zp"));
+    assert_eq!(symbols.describe(0x0010).as_deref(), Some("zp+16"));
+    assert_eq!(symbols.describe(0x0011), None);
+    assert_eq!(symbols.describe(0x0105).as_deref(), Some("stack+5"));
+}
+
+#[test]
+fn errors_name_the_line() {
+    let mut symbols = SymbolTable::new();
+    let e = symbols
+        .parse("bad.sym", "reset = $C000\nnmi $C010\n")
+        .err()
+        .unwrap();
+    assert_eq!(e.line, 2);
+    assert!(matches!(e.kind, LabelErrorKind::InvalidLine(_)));
+
+    let e = symbols.parse("bad.sym", "reset = $10000\n").err().unwrap();
+    assert_eq!(
+        e.kind,
+        LabelErrorKind::InvalidAddress(String::from("$10000"))
+    );
+}
+
+#[test]
+fn disassembly_uses_assembler_symbols() {
+    let optable = optable::optable(Variant::Nmos6502);
+    let output = Assembler::new(optable)
+        .assemble_str(
+            "test.s",
+            "PPUSTATUS = $2002
+        .org $8000
+reset:  lda PPUSTATUS
+        bpl reset
+        lda table+2
+        jsr $9000
+table:  .byte 1, 2, 3",
+        )
+        .unwrap();
+    // only the debug file has the constants
+    assert!(!output.symbol_file().contains("PPUSTATUS"));
+    let symbols = parse(&output.debug_file());
+
+    let mut disassembler = Disassembler::with_origin(output.bytes, optable, output.origin);
+    disassembler.symbols = Some(&symbols);
+    let text: Vec<String> = disassembler.range(..0x800B).map(|line| line.text).collect();
+    assert_eq!(
+        text,
+        ["LDA PPUSTATUS", "BPL reset", "LDA table+2", "JSR $9000"]
+    );
+}
+
+#[test]
+fn debug_files_map_addresses_to_source_lines() {
+    let optable = optable::optable(Variant::Nmos6502);
+    let output = Assembler::new(optable)
+        .assemble_str(
+            "src/test.s",
+            "        .macro twice
+        asl
+        asl
+        .endmacro
+
+        .org $0400
+start:  lda #1
+
+        twice
+        rts
+ENTRY = start",
+        )
+        .unwrap();
+    let mut symbols = SymbolTable::new();
+    symbols.parse("test.dbg", &output.debug_file()).unwrap();
+
+    assert_eq!(symbols.addr("start"), Some(0x0400));
+    // the label names the address, not the constant
+    assert_eq!(symbols.addr("ENTRY"), Some(0x0400));
+    assert_eq!(symbols.name(0x0400), Some("start"));
+    let line = symbols.line(0x0401).unwrap();
+    assert_eq!(
+        (line.file.as_str(), line.line, line.addr),
+        ("src/test.s", 7, 0x0400)
+    );
+    // the macro's bytes belong to the line that invoked it
+    let line = symbols.line(0x0403).unwrap();
+    assert_eq!((line.line, line.addr, line.size), (9, 0x0402, 2));
+    assert_eq!(symbols.line(0x0405), None);
+
+    // blank lines move on to the next one with code
+    assert_eq!(
+        symbols.find_line("/home/me/src/test.s", 8),
+        Some((9, vec![0x0402]))
+    );
+    assert_eq!(symbols.find_line("test.s", 10), Some((10, vec![0x0404])));
+    assert_eq!(symbols.find_line("test.s", 11), None);
+    assert_eq!(symbols.find_line("other.s", 7), None);
+
+    // ca65 splits lines into spans and marks the ones inside macros
+    let dbg = parse(
+        "version\tmajor=2,minor=0\n\
+         file\tid=0,name=\"main.s\",size=100,mtime=0x00000000,mod=0\n\
+         seg\tid=0,name=\"CODE\",start=0x008000,size=0x0010,addrsize=absolute,type=ro\n\
+         span\tid=0,seg=0,start=0,size=2\n\
+         span\tid=1,seg=0,start=2,size=3\n\
+         span\tid=2,seg=0,start=5,size=1\n\
+         line\tid=0,file=0,line=4,span=0+1\n\
+         line\tid=1,file=0,line=9,type=2,span=2\n\
+         line\tid=2,file=0,line=12,span=2\n",
+    );
+    assert_eq!(dbg.line(0x8000).unwrap().line, 4);
+    let line = dbg.line(0x8004).unwrap();
+    assert_eq!((line.line, line.addr, line.size), (4, 0x8002, 3));
+    assert_eq!(dbg.line(0x8005).unwrap().line, 12);
+}